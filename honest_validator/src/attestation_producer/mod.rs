@@ -1,39 +1,44 @@
-use crate::beacon_node::{BasicBeaconNode, BeaconNode};
+use crate::beacon_node::BeaconNode;
+use crate::slashing_protection::SlashingProtection;
 use bls::{SecretKey, Signature};
+use log::{error, info};
 use ssz_types::BitList;
 use std::convert::TryInto;
+use std::sync::Arc;
 use tree_hash::TreeHash;
 use types::beacon_state::BeaconState;
+use types::chain_spec::ChainSpec;
 use types::config::*;
-use types::primitives::{CommitteeIndex, Slot, ValidatorIndex};
+use types::helper_functions::compute_start_slot_at_epoch;
+use types::primitives::{CommitteeIndex, Slot, ValidatorIndex, H256};
 use types::types::{Attestation, AttestationData, Checkpoint};
 
 const MAX_VALIDATORS_PER_COMMITTEE: usize = 4;
 
-pub struct AttestationProducer<C: Config> {
+pub struct AttestationProducer<C: Config, B: BeaconNode<C>> {
     pub config: C,
-    pub beacon_node: BasicBeaconNode,
+    pub spec: ChainSpec,
+    pub beacon_node: Arc<B>,
+    pub slashing_protection: Arc<SlashingProtection>,
 }
 
-impl<C: Config> AttestationProducer<C> {
+impl<C: Config, B: BeaconNode<C>> AttestationProducer<C, B> {
     fn construct_attestation_data(
         &self,
-        head_state: &BeaconState<MinimalConfig>,
+        head_state: &BeaconState<C>,
         assigned_slot: Slot,
         committee_index: CommitteeIndex,
     ) -> AttestationData {
-        let epoch = self.beacon_node.get_current_epoch(head_state);
+        let epoch = head_state.get_current_epoch();
 
-        let start_slot = self.beacon_node.compute_start_slot_at_epoch(epoch);
+        let start_slot = compute_start_slot_at_epoch::<C>(epoch);
 
         let head_block_root = head_state.latest_block_header.state_root;
 
         let epoch_boundary_block_root = if start_slot == head_state.slot {
             head_block_root
         } else {
-            self.beacon_node
-                .get_block_root_at_slot(head_state, start_slot)
-                .unwrap()
+            head_state.get_block_root_at_slot(start_slot).unwrap()
         };
 
         let target = Checkpoint {
@@ -54,13 +59,12 @@ impl<C: Config> AttestationProducer<C> {
 
     fn get_signed_attestation_data(
         &self,
-        state: &BeaconState<MinimalConfig>,
+        state: &BeaconState<C>,
         attestation_data: &AttestationData,
         privkey: SecretKey,
     ) -> Signature {
-        let domain = self.beacon_node.get_domain(
-            state,
-            MinimalConfig::domain_attestation(),
+        let domain = state.get_domain(
+            self.spec.domain_beacon_attester,
             Some(attestation_data.target.epoch),
         );
         Signature::new(&attestation_data.tree_hash_root()[..], domain, &privkey)
@@ -68,11 +72,23 @@ impl<C: Config> AttestationProducer<C> {
 
     fn construct_attestation(
         &self,
-        head_state: &BeaconState<MinimalConfig>,
+        head_state: &BeaconState<C>,
         attestation_data: AttestationData,
         validator_committee_index: ValidatorIndex,
+        pubkey: &str,
         privkey: SecretKey,
-    ) -> Option<Attestation<MinimalConfig>> {
+    ) -> Option<Attestation<C>> {
+        let signing_root = H256::from_slice(&attestation_data.tree_hash_root()[..]);
+        if let Err(e) = self.slashing_protection.check_and_insert_attestation(
+            pubkey,
+            attestation_data.source.epoch,
+            attestation_data.target.epoch,
+            signing_root,
+        ) {
+            error!("Refusing to sign slashable attestation: {:?}", e);
+            return None;
+        }
+
         let mut aggregation_bits = BitList::with_capacity(MAX_VALIDATORS_PER_COMMITTEE)
             .ok()
             .unwrap();
@@ -92,13 +108,14 @@ impl<C: Config> AttestationProducer<C> {
 
     pub fn get_attestation(
         &self,
-        beacon_state: &BeaconState<MinimalConfig>,
+        beacon_state: &BeaconState<C>,
         commitee_index: CommitteeIndex,
         validator_commitee_index: ValidatorIndex,
+        pubkey: &str,
         privkey: SecretKey,
-    ) -> Option<Attestation<MinimalConfig>> {
-        println!(
-            "\tValidator at committe {} (position {}) starts attestation",
+    ) -> Option<Attestation<C>> {
+        info!(
+            "Validator at committe {} (position {}) starts attestation",
             commitee_index, validator_commitee_index
         );
 
@@ -109,6 +126,7 @@ impl<C: Config> AttestationProducer<C> {
             &beacon_state,
             attestation_data,
             validator_commitee_index,
+            pubkey,
             privkey,
         )
     }
@@ -117,13 +135,16 @@ impl<C: Config> AttestationProducer<C> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_beacon_node::MockBeaconNode;
     use types::config::MinimalConfig;
 
     #[test]
     fn construct_attestation_data() {
         let attestation_producer = AttestationProducer {
             config: MinimalConfig::default(),
-            beacon_node: BasicBeaconNode::new(),
+            spec: ChainSpec::from_config::<MinimalConfig>(),
+            beacon_node: Arc::new(MockBeaconNode::default()),
+            slashing_protection: Arc::new(SlashingProtection::in_memory()),
         };
 
         let beacon_state: BeaconState<MinimalConfig> = BeaconState {
@@ -148,11 +169,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn should_target_epoch_boundary_block_root() {
+        let attestation_producer = AttestationProducer {
+            config: MinimalConfig::default(),
+            spec: ChainSpec::from_config::<MinimalConfig>(),
+            beacon_node: Arc::new(MockBeaconNode::default()),
+            slashing_protection: Arc::new(SlashingProtection::in_memory()),
+        };
+
+        let mut block_roots = vec![H256::zero(); 64];
+        block_roots[16] = H256::from_low_u64_le(16);
+        let beacon_state: BeaconState<MinimalConfig> = BeaconState {
+            slot: 64 + 19,
+            block_roots: block_roots.into(),
+            ..BeaconState::default()
+        };
+
+        let attestation_data =
+            attestation_producer.construct_attestation_data(&beacon_state, 64 + 18, 0);
+        assert_eq!(attestation_data.target.epoch, 10);
+        assert_eq!(attestation_data.target.root, H256::from_low_u64_le(16));
+    }
+
     #[test]
     fn get_signed_attestation_data() {
         let attestation_producer = AttestationProducer {
             config: MinimalConfig::default(),
-            beacon_node: BasicBeaconNode::new(),
+            spec: ChainSpec::from_config::<MinimalConfig>(),
+            beacon_node: Arc::new(MockBeaconNode::default()),
+            slashing_protection: Arc::new(SlashingProtection::in_memory()),
         };
 
         let beacon_state: BeaconState<MinimalConfig> = BeaconState {
@@ -173,4 +219,27 @@ mod tests {
         );
         assert_eq!(signature.is_empty(), false);
     }
+
+    #[test]
+    fn should_not_sign_double_vote() {
+        let attestation_producer = AttestationProducer {
+            config: MinimalConfig::default(),
+            spec: ChainSpec::from_config::<MinimalConfig>(),
+            beacon_node: Arc::new(MockBeaconNode::default()),
+            slashing_protection: Arc::new(SlashingProtection::in_memory()),
+        };
+        let pubkey = "0xa99a76ed7796f7be22d5b7e85deeb7c5677e88e511e0b337618f8c4eb61349b4bf2d153f649f7b53359fe8b94a38e44c";
+        let privkey = SecretKey::from_bytes(&vec![0u8; 48]).unwrap();
+
+        let beacon_state: BeaconState<MinimalConfig> = BeaconState::default();
+        let attestation =
+            attestation_producer.get_attestation(&beacon_state, 0, 0, pubkey, privkey.clone());
+        assert!(attestation.is_some());
+
+        let mut conflicting_state = beacon_state.clone();
+        conflicting_state.latest_block_header.state_root = H256::from_slice(&[1; 32]);
+        let attestation =
+            attestation_producer.get_attestation(&conflicting_state, 0, 0, pubkey, privkey);
+        assert!(attestation.is_none());
+    }
 }
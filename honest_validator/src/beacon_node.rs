@@ -1,28 +1,53 @@
-use crate::rest_client::RestClient;
-use bls::PublicKeyBytes;
-use bytes::{BufMut, BytesMut};
+use crate::rest_client::{self, CircuitState, RestClient};
+use async_trait::async_trait;
+use bls::{PublicKeyBytes, Signature};
+use serde::de::{DeserializeOwned, IgnoredAny};
 use serde::{Deserialize, Serialize};
-use std::rc::Rc;
+use std::marker::PhantomData;
 use types::beacon_state::BeaconState;
-use types::config::MinimalConfig;
-use types::primitives::{CommitteeIndex, Domain, DomainType, Epoch, Slot, ValidatorIndex, H256};
-use types::types::{Attestation, BeaconBlock};
-
-const SLOTS_PER_HISTORICAL_ROOT: Slot = 8192;
-const SLOTS_PER_EPOCH: u64 = 8;
+use types::config::Config as EthConfig;
+use types::primitives::{CommitteeIndex, Epoch, Slot, ValidatorIndex};
+use types::types::{Attestation, BeaconBlock, SignedBeaconBlock, VoluntaryExit};
 
 #[derive(PartialEq, Debug)]
 pub enum Error {
-    SlotOutOfRange,
-    IndexOutOfRange,
-    ApiError,
-    AttestionPublishingError,
+    /// The request to the beacon node failed.
+    Request(rest_client::Error),
+    /// The beacon node answered without a beacon state, e.g. while it is syncing.
+    StateUnavailable,
+    /// The beacon node has no block to propose at the requested slot.
+    BlockUnavailable,
+    /// No beacon node is configured.
+    NoBeaconNodes,
+}
+
+impl Error {
+    /// Whether the same call may succeed if made again later. Other errors need the
+    /// configuration or the request to be fixed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Request(e) => e.is_retryable(),
+            Error::StateUnavailable => true,
+            Error::BlockUnavailable | Error::NoBeaconNodes => false,
+        }
+    }
+}
+
+impl From<rest_client::Error> for Error {
+    fn from(error: rest_client::Error) -> Error {
+        Error::Request(error)
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-pub struct BeaconStateResponse {
+pub struct BeaconStateResponse<C: EthConfig> {
     pub root: String,
-    pub beacon_state: Option<BeaconState<MinimalConfig>>,
+    pub beacon_state: Option<BeaconState<C>>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SyncingResponse {
+    pub is_syncing: bool,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -40,154 +65,125 @@ pub struct DutiesRequest {
     pub epoch: Epoch,
 }
 
-pub trait BeaconNode {
-    fn get_state(&self) -> &Option<BeaconState<MinimalConfig>>;
+/// Beacon node API used by the validator client, for a chain running the `C` preset.
+/// Implementations are shared between the tasks performing duties concurrently.
+#[async_trait]
+pub trait BeaconNode<C: EthConfig>: Send + Sync {
+    async fn get_state(&self) -> Result<BeaconState<C>, Error>;
 
-    fn get_current_epoch(&self, state: &BeaconState<MinimalConfig>) -> Epoch;
+    /// Whether the beacon node is still catching up with the chain, in which case its head is
+    /// not to be relied on.
+    async fn is_syncing(&self) -> Result<bool, Error> {
+        Ok(false)
+    }
 
-    fn compute_start_slot_at_epoch(&self, epoch: Epoch) -> Slot;
-    fn get_block_root(
+    async fn get_duties(
         &self,
-        state: &BeaconState<MinimalConfig>,
+        validators: Vec<PublicKeyBytes>,
         epoch: Epoch,
-    ) -> Result<H256, Error>;
-
-    fn get_duties(&self, validators: Vec<PublicKeyBytes>, epoch: Epoch) -> Vec<DutyInfo>;
+    ) -> Result<Vec<DutyInfo>, Error>;
 
-    fn publish_attestation(&self, attestation: Attestation<MinimalConfig>) -> Result<(), Error>;
+    async fn publish_attestation(&self, attestation: Attestation<C>) -> Result<(), Error>;
 
-    fn get_block_root_at_slot(
+    /// Unsigned block for `slot`, built by the beacon node around the given RANDAO reveal.
+    async fn get_block(
         &self,
-        state: &BeaconState<MinimalConfig>,
         slot: Slot,
-    ) -> Result<H256, Error>;
+        randao_reveal: &Signature,
+    ) -> Result<BeaconBlock<C>, Error>;
 
-    fn get_domain(
-        &self,
-        state: &BeaconState<MinimalConfig>,
-        domain_type: DomainType,
-        message_epoch: Option<Epoch>,
-    ) -> Domain;
+    async fn publish_block(&self, block: SignedBeaconBlock<C>) -> Result<(), Error>;
 
-    fn get_block(&self, slot: Slot, root: String) -> Option<BeaconBlock<MinimalConfig>>;
+    async fn publish_voluntary_exit(&self, exit: VoluntaryExit) -> Result<(), Error>;
+
+    /// Whether requests are currently sent to the beacon node, see `rest_client::CircuitBreaker`.
+    fn circuit_state(&self) -> CircuitState {
+        CircuitState::Closed
+    }
 }
 
 #[derive(Clone)]
-pub struct BasicBeaconNode {
-    pub beacon_node_rest_client: Rc<RestClient>,
-    last_known_state: Option<BeaconState<MinimalConfig>>,
+pub struct BasicBeaconNode<C: EthConfig> {
+    pub beacon_node_rest_client: RestClient,
+    config: PhantomData<C>,
 }
 
-impl BasicBeaconNode {
-    pub fn new() -> BasicBeaconNode {
-        let beacon_node_rest_client =
-            Rc::new(RestClient::new(String::from("http://localhost:5052")).unwrap());
-        let state: Option<BeaconStateResponse> = beacon_node_rest_client.get(&"/beacon/state");
-        let last_known_state = match state {
-            Some(state_response) => state_response.beacon_state,
-            None => None,
-        };
+impl<C: EthConfig> BasicBeaconNode<C> {
+    pub fn new(url: String) -> BasicBeaconNode<C> {
+        BasicBeaconNode::from_rest_client(RestClient::new(url).unwrap())
+    }
+
+    pub fn from_rest_client(beacon_node_rest_client: RestClient) -> BasicBeaconNode<C> {
         BasicBeaconNode {
             beacon_node_rest_client,
-            last_known_state,
+            config: PhantomData,
         }
     }
-
-    pub fn update_state(&mut self) -> () {
-        let client = &self.beacon_node_rest_client;
-        match client.get(&"/beacon/state") {
-            Some(state) => self.last_known_state = state,
-            None => println!("failed update state"),
-        };
-    }
 }
 
-impl BeaconNode for BasicBeaconNode {
-    fn get_state(&self) -> &Option<BeaconState<MinimalConfig>> {
-        &self.last_known_state
+#[async_trait]
+impl<C: EthConfig + Serialize + DeserializeOwned> BeaconNode<C> for BasicBeaconNode<C> {
+    async fn get_state(&self) -> Result<BeaconState<C>, Error> {
+        let response: BeaconStateResponse<C> =
+            self.beacon_node_rest_client.get("/beacon/state").await?;
+        response.beacon_state.ok_or(Error::StateUnavailable)
     }
 
-    fn get_block(&self, slot: Slot, root: String) -> Option<BeaconBlock<MinimalConfig>> {
-        let url = format!("/validator/block?slot={}&root={}", slot, root);
-        (&self).beacon_node_rest_client.get(&url[..])
+    async fn is_syncing(&self) -> Result<bool, Error> {
+        let response: SyncingResponse = self.beacon_node_rest_client.get("/node/syncing").await?;
+        Ok(response.is_syncing)
     }
 
-    fn get_current_epoch(&self, state: &BeaconState<MinimalConfig>) -> Epoch {
-        state.slot / 8
+    async fn get_block(
+        &self,
+        slot: Slot,
+        randao_reveal: &Signature,
+    ) -> Result<BeaconBlock<C>, Error> {
+        let url = format!(
+            "/validator/block?slot={}&randao_reveal=0x{}",
+            slot,
+            hex::encode(randao_reveal.as_bytes())
+        );
+        Ok(self.beacon_node_rest_client.get(&url[..]).await?)
     }
 
-    fn compute_start_slot_at_epoch(&self, epoch: Epoch) -> Slot {
-        epoch * 8
+    async fn publish_block(&self, block: SignedBeaconBlock<C>) -> Result<(), Error> {
+        self.beacon_node_rest_client
+            .post::<IgnoredAny, _>("/validator/block", &block)
+            .await?;
+        Ok(())
     }
 
-    fn get_duties(&self, validators: Vec<PublicKeyBytes>, epoch: Epoch) -> Vec<DutyInfo> {
-        let request_body = Option::Some(DutiesRequest {
-            pubkeys: validators,
-            epoch,
-        });
-        (&self)
-            .beacon_node_rest_client
-            .post("/validator/duties", request_body)
-            .unwrap()
+    async fn publish_voluntary_exit(&self, exit: VoluntaryExit) -> Result<(), Error> {
+        self.beacon_node_rest_client
+            .post::<IgnoredAny, _>("/beacon/pool/voluntary_exits", &exit)
+            .await?;
+        Ok(())
     }
 
-    fn publish_attestation(&self, request_body: Attestation<MinimalConfig>) -> Result<(), Error> {
-        match (&self)
-            .beacon_node_rest_client
-            .post("/validator/attestation", Option::Some(request_body))
-        {
-            Some(()) => Ok(()),
-            _ => Err(Error::AttestionPublishingError),
-        }
-    }
-
-    fn get_block_root(
+    async fn get_duties(
         &self,
-        state: &BeaconState<MinimalConfig>,
+        validators: Vec<PublicKeyBytes>,
         epoch: Epoch,
-    ) -> Result<H256, Error> {
-        let slot: Slot = epoch * SLOTS_PER_EPOCH;
-        self.get_block_root_at_slot(state, slot)
+    ) -> Result<Vec<DutyInfo>, Error> {
+        let request_body = DutiesRequest {
+            pubkeys: validators,
+            epoch,
+        };
+        Ok(self
+            .beacon_node_rest_client
+            .post("/validator/duties", &request_body)
+            .await?)
     }
 
-    fn get_block_root_at_slot(
-        &self,
-        state: &BeaconState<MinimalConfig>,
-        slot: Slot,
-    ) -> Result<H256, Error> {
-        if slot < state.slot && state.slot <= slot + SLOTS_PER_HISTORICAL_ROOT {
-            let root_idx = (slot % SLOTS_PER_HISTORICAL_ROOT) as usize;
-            return Ok(state.historical_roots[root_idx]);
-        }
-        Err(Error::SlotOutOfRange)
+    async fn publish_attestation(&self, request_body: Attestation<C>) -> Result<(), Error> {
+        self.beacon_node_rest_client
+            .post::<IgnoredAny, _>("/validator/attestation", &request_body)
+            .await?;
+        Ok(())
     }
 
-    fn get_domain(
-        &self,
-        state: &BeaconState<MinimalConfig>,
-        domain_type: DomainType,
-        message_epoch: Option<Epoch>,
-    ) -> Domain {
-        let mut bytes: Vec<u8> = int_to_bytes4(domain_type);
-        let epoch = match message_epoch {
-            Some(epoch) => epoch,
-            None => state.fork.epoch,
-        };
-        let mut version = if epoch < state.fork.epoch {
-            state.fork.previous_version.clone().to_vec()
-        } else {
-            state.fork.current_version.clone().to_vec()
-        };
-        bytes.append(&mut version);
-        let mut fork_and_domain = [0; 8];
-        fork_and_domain.copy_from_slice(&bytes);
-
-        u64::from_le_bytes(fork_and_domain)
+    fn circuit_state(&self) -> CircuitState {
+        self.beacon_node_rest_client.circuit_state()
     }
 }
-
-pub fn int_to_bytes4(int: u32) -> Vec<u8> {
-    let mut bytes = BytesMut::with_capacity(4);
-    bytes.put_u32_le(int);
-    bytes.to_vec()
-}
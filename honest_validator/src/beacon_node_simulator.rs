@@ -0,0 +1,486 @@
+use crate::beacon_node::{BeaconStateResponse, DutiesRequest, DutyInfo, SyncingResponse};
+use crate::duties::compute_duties;
+use bls::{PublicKeyBytes, Signature};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use ssz_types::VariableList;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use tokio::runtime::Builder;
+use tokio::sync::oneshot;
+use tree_hash::{SignedRoot, TreeHash};
+use typenum::Unsigned;
+use types::beacon_state::{self, BeaconState};
+use types::chain_spec::ChainSpec;
+use types::config::{Config, MinimalConfig};
+use types::helper_functions::compute_start_slot_at_epoch;
+use types::primitives::{Epoch, Slot, H256};
+use types::state_transition::block_processing::process_attestation;
+use types::state_transition::{self, process_block, process_slots, BlockSignatureStrategy};
+use types::types::{Attestation, BeaconBlock, BeaconBlockBody, SignedBeaconBlock};
+
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    NotFound,
+    InvalidRequest(String),
+    SlotOutOfRange,
+    Bind(String),
+    StateTransition(state_transition::Error),
+    BeaconState(beacon_state::Error),
+}
+
+impl From<state_transition::Error> for Error {
+    fn from(error: state_transition::Error) -> Error {
+        Error::StateTransition(error)
+    }
+}
+
+impl From<beacon_state::Error> for Error {
+    fn from(error: beacon_state::Error) -> Error {
+        Error::BeaconState(error)
+    }
+}
+
+/// Single-fork chain kept in memory: every imported block has to extend the head.
+pub struct SimulatedChain<C: Config> {
+    head: BeaconState<C>,
+    // Head state advanced through empty slots, reused while the head does not change
+    skip_state: BeaconState<C>,
+    blocks: Vec<BeaconBlock<C>>,
+    attestation_pool: Vec<Attestation<C>>,
+    spec: ChainSpec,
+}
+
+impl<C: Config> SimulatedChain<C> {
+    /// Chain with the constants of the preset `C`.
+    pub fn new(genesis_state: BeaconState<C>) -> SimulatedChain<C> {
+        SimulatedChain {
+            skip_state: genesis_state.clone(),
+            head: genesis_state,
+            blocks: Vec::new(),
+            attestation_pool: Vec::new(),
+            spec: ChainSpec::from_config::<C>(),
+        }
+    }
+
+    pub fn head_state(&self) -> &BeaconState<C> {
+        &self.head
+    }
+
+    /// Blocks imported since genesis, oldest first.
+    pub fn blocks(&self) -> &[BeaconBlock<C>] {
+        &self.blocks
+    }
+
+    /// Valid attestations waiting to be included in a block.
+    pub fn attestation_pool(&self) -> &[Attestation<C>] {
+        &self.attestation_pool
+    }
+
+    pub fn get_duties(
+        &mut self,
+        pubkeys: &[PublicKeyBytes],
+        epoch: Epoch,
+    ) -> Result<Vec<DutyInfo>, Error> {
+        let state = self.state_at_slot(compute_start_slot_at_epoch::<C>(epoch))?;
+        Ok(compute_duties(&self.spec, &state, pubkeys, epoch)?)
+    }
+
+    /// Unsigned block for `slot` on top of the head, including every pooled attestation that is
+    /// valid at `slot`.
+    pub fn produce_block(
+        &mut self,
+        slot: Slot,
+        randao_reveal: &Signature,
+    ) -> Result<BeaconBlock<C>, Error> {
+        if slot <= self.head.slot {
+            return Err(Error::SlotOutOfRange);
+        }
+        let mut state = self.state_at_slot(slot)?;
+
+        let mut attestation_state = state.clone();
+        let attestations: Vec<Attestation<C>> = self
+            .attestation_pool
+            .iter()
+            .filter(|attestation| {
+                process_attestation(&mut attestation_state, attestation, false, &self.spec).is_ok()
+            })
+            .take(C::MaxAttestations::to_usize())
+            .cloned()
+            .collect();
+
+        let mut block = BeaconBlock {
+            slot,
+            parent_root: H256::from_slice(&state.latest_block_header.signed_root()),
+            state_root: H256::zero(),
+            body: BeaconBlockBody {
+                randao_reveal: randao_reveal.clone(),
+                eth1_data: state.eth1_data.clone(),
+                graffiti: [0; 32],
+                proposer_slashings: VariableList::default(),
+                attester_slashings: VariableList::default(),
+                attestations: VariableList::from(attestations),
+                deposits: VariableList::default(),
+                voluntary_exits: VariableList::default(),
+                transfers: VariableList::default(),
+            },
+            signature: Signature::empty_signature(),
+        };
+        process_block(
+            &mut state,
+            &block,
+            BlockSignatureStrategy::NoVerification,
+            &self.spec,
+        )?;
+        block.state_root = H256::from_slice(&state.tree_hash_root());
+        Ok(block)
+    }
+
+    /// Applies a signed `block` that extends the head and makes it the new head.
+    pub fn import_block(&mut self, block: BeaconBlock<C>) -> Result<(), Error> {
+        if block.slot <= self.head.slot {
+            return Err(Error::SlotOutOfRange);
+        }
+        let mut state = self.state_at_slot(block.slot)?;
+        process_block(
+            &mut state,
+            &block,
+            BlockSignatureStrategy::VerifyBulk,
+            &self.spec,
+        )?;
+
+        let slots_per_epoch = C::SlotsPerEpoch::to_u64();
+        self.attestation_pool.retain(|attestation| {
+            !block.body.attestations.contains(attestation)
+                && attestation.data.slot + slots_per_epoch >= state.slot
+        });
+        self.head = state.clone();
+        self.skip_state = state;
+        self.blocks.push(block);
+        Ok(())
+    }
+
+    /// Pools `attestation` for inclusion if it would be valid in a block at the earliest slot it
+    /// can be included in.
+    pub fn add_attestation(&mut self, attestation: Attestation<C>) -> Result<(), Error> {
+        let inclusion_slot = attestation.data.slot + self.spec.min_attestation_inclusion_delay;
+        let mut state = self.state_at_slot(inclusion_slot)?;
+        process_attestation(&mut state, &attestation, true, &self.spec)?;
+        if !self.attestation_pool.contains(&attestation) {
+            self.attestation_pool.push(attestation);
+        }
+        Ok(())
+    }
+
+    /// Head state advanced through empty slots to `slot`, or the head state itself if it is at
+    /// `slot` or past it already.
+    fn state_at_slot(&mut self, slot: Slot) -> Result<BeaconState<C>, Error> {
+        if self.skip_state.slot > slot {
+            self.skip_state = self.head.clone();
+        }
+        if self.skip_state.slot < slot {
+            process_slots(&mut self.skip_state, slot, &self.spec)?;
+        }
+        Ok(self.skip_state.clone())
+    }
+}
+
+/// Stand-in for a beacon node, serving the HTTP API the validator client uses from a
+/// `SimulatedChain` on a background thread. The server stops when the simulator is dropped.
+pub struct BeaconNodeSimulator {
+    pub chain: Arc<Mutex<SimulatedChain<MinimalConfig>>>,
+    address: SocketAddr,
+    shutdown: Option<oneshot::Sender<()>>,
+    server_thread: Option<JoinHandle<()>>,
+}
+
+impl BeaconNodeSimulator {
+    /// Starts serving a chain beginning at `genesis_state` on `address`. Port 0 picks a free port.
+    pub fn start(
+        genesis_state: BeaconState<MinimalConfig>,
+        address: &SocketAddr,
+    ) -> Result<BeaconNodeSimulator, Error> {
+        let chain = Arc::new(Mutex::new(SimulatedChain::new(genesis_state)));
+        let server_chain = Arc::clone(&chain);
+        let make_service = make_service_fn(move |_| {
+            let chain = Arc::clone(&server_chain);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    handle_request(Arc::clone(&chain), request)
+                }))
+            }
+        });
+        // The server gets a runtime of its own, so it keeps serving while the caller blocks.
+        let runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| Error::Bind(e.to_string()))?;
+        let server = {
+            let _runtime_context = runtime.enter();
+            Server::try_bind(address)
+                .map_err(|e| Error::Bind(e.to_string()))?
+                .serve(make_service)
+        };
+        let address = server.local_addr();
+
+        let (shutdown, shutdown_signal) = oneshot::channel::<()>();
+        let server = server.with_graceful_shutdown(async {
+            shutdown_signal.await.ok();
+        });
+        let server_thread = thread::spawn(move || {
+            if let Err(e) = runtime.block_on(server) {
+                error!("Beacon node simulator error: {}", e);
+            }
+        });
+
+        Ok(BeaconNodeSimulator {
+            chain,
+            address,
+            shutdown: Some(shutdown),
+            server_thread: Some(server_thread),
+        })
+    }
+
+    /// Base URL of the API, as accepted by `BasicBeaconNode::new`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    /// Blocks the current thread for as long as the server runs.
+    pub fn wait(mut self) {
+        if let Some(server_thread) = self.server_thread.take() {
+            let _ = server_thread.join();
+        }
+    }
+}
+
+impl Drop for BeaconNodeSimulator {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(server_thread) = self.server_thread.take() {
+            let _ = server_thread.join();
+        }
+    }
+}
+
+async fn handle_request(
+    chain: Arc<Mutex<SimulatedChain<MinimalConfig>>>,
+    request: Request<Body>,
+) -> Result<Response<Body>, hyper::Error> {
+    let (parts, body) = request.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+    let mut chain = chain.lock().expect("simulated chain lock is not poisoned");
+    let result = route(
+        &mut chain,
+        &parts.method,
+        parts.uri.path(),
+        parts.uri.query(),
+        &body,
+    );
+    Ok(match result {
+        Ok(json) => response(StatusCode::OK, json),
+        Err(Error::NotFound) => response(StatusCode::NOT_FOUND, String::new()),
+        Err(e) => response(StatusCode::BAD_REQUEST, format!("{:?}", e)),
+    })
+}
+
+fn route(
+    chain: &mut SimulatedChain<MinimalConfig>,
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    body: &[u8],
+) -> Result<String, Error> {
+    match (method, path) {
+        (&Method::GET, "/beacon/state") => {
+            let state = chain.head_state();
+            Ok(to_json(&BeaconStateResponse {
+                root: format!("0x{}", hex::encode(state.tree_hash_root())),
+                beacon_state: Some(state.clone()),
+            }))
+        }
+        (&Method::GET, "/node/syncing") => Ok(to_json(&SyncingResponse { is_syncing: false })),
+        (&Method::POST, "/validator/duties") => {
+            let request: DutiesRequest = from_json(body)?;
+            Ok(to_json(&chain.get_duties(&request.pubkeys, request.epoch)?))
+        }
+        (&Method::GET, "/validator/block") => {
+            let slot = query_param(query, "slot")?
+                .parse::<Slot>()
+                .map_err(|e| Error::InvalidRequest(e.to_string()))?;
+            let randao_reveal =
+                hex::decode(query_param(query, "randao_reveal")?.trim_start_matches("0x"))
+                    .map_err(|e| Error::InvalidRequest(e.to_string()))
+                    .and_then(|bytes| {
+                        Signature::from_bytes(&bytes)
+                            .map_err(|e| Error::InvalidRequest(format!("{:?}", e)))
+                    })?;
+            Ok(to_json(&chain.produce_block(slot, &randao_reveal)?))
+        }
+        (&Method::POST, "/validator/block") => {
+            let signed_block: SignedBeaconBlock<MinimalConfig> = from_json(body)?;
+            let mut block = signed_block.message;
+            block.signature = signed_block.signature;
+            chain.import_block(block).map(|()| String::new())
+        }
+        (&Method::POST, "/validator/attestation") => {
+            let attestation: Attestation<MinimalConfig> = from_json(body)?;
+            chain.add_attestation(attestation).map(|()| String::new())
+        }
+        _ => Err(Error::NotFound),
+    }
+}
+
+fn response(status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Result<&'a str, Error> {
+    query
+        .unwrap_or("")
+        .split('&')
+        .find_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            if parts.next() == Some(name) {
+                parts.next()
+            } else {
+                None
+            }
+        })
+        .ok_or_else(|| Error::InvalidRequest(format!("missing query parameter {}", name)))
+}
+
+fn from_json<T: DeserializeOwned>(body: &[u8]) -> Result<T, Error> {
+    serde_json::from_slice(body).map_err(|e| Error::InvalidRequest(e.to_string()))
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("API responses serialize to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beacon_node::{BasicBeaconNode, BeaconNode};
+    use types::genesis::{interop_genesis_state, interop_keypair};
+
+    const VALIDATOR_COUNT: u64 = 64;
+
+    fn start_simulator() -> BeaconNodeSimulator {
+        let genesis_state = interop_genesis_state::<MinimalConfig>(VALIDATOR_COUNT, 0).unwrap();
+        BeaconNodeSimulator::start(genesis_state, &([127, 0, 0, 1], 0).into()).unwrap()
+    }
+
+    fn pubkeys() -> Vec<PublicKeyBytes> {
+        (0..VALIDATOR_COUNT)
+            .map(|index| PublicKeyBytes::from_bytes(&interop_keypair(index).pk.as_bytes()).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn should_serve_head_state() {
+        let simulator = start_simulator();
+        let beacon_node = BasicBeaconNode::new(simulator.url());
+
+        let state = beacon_node.get_state().await.unwrap();
+
+        assert_eq!(&state, simulator.chain.lock().unwrap().head_state());
+    }
+
+    #[tokio::test]
+    async fn should_serve_duties_of_requested_validators() {
+        let simulator = start_simulator();
+        let beacon_node: BasicBeaconNode<MinimalConfig> = BasicBeaconNode::new(simulator.url());
+
+        let duties = beacon_node.get_duties(pubkeys(), 1).await.unwrap();
+
+        assert_eq!(duties.len(), VALIDATOR_COUNT as usize);
+        let slots_per_epoch = <MinimalConfig as Config>::SlotsPerEpoch::to_u64();
+        let mut proposal_slots: Vec<Slot> = duties
+            .iter()
+            .filter_map(|duty| duty.block_proposal_slot)
+            .collect();
+        proposal_slots.sort();
+        proposal_slots.dedup();
+        assert_eq!(
+            proposal_slots,
+            (slots_per_epoch..2 * slots_per_epoch).collect::<Vec<Slot>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_import_block_signed_by_proposer() {
+        let simulator = start_simulator();
+        let beacon_node: BasicBeaconNode<MinimalConfig> = BasicBeaconNode::new(simulator.url());
+        let slot = 1;
+        let proposer_pubkey = beacon_node
+            .get_duties(pubkeys(), 0)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|duty| duty.block_proposal_slot == Some(slot))
+            .unwrap()
+            .validator_pubkey;
+        let proposer = (0..VALIDATOR_COUNT)
+            .map(interop_keypair)
+            .find(|keypair| format!("0x{}", hex::encode(keypair.pk.as_bytes())) == proposer_pubkey)
+            .unwrap();
+        let state = beacon_node.get_state().await.unwrap();
+
+        let domain = state.get_domain(MinimalConfig::domain_randao(), Some(0));
+        let randao_reveal = Signature::new(&0u64.tree_hash_root(), domain, &proposer.sk);
+        let block = beacon_node.get_block(slot, &randao_reveal).await.unwrap();
+        let domain = state.get_domain(MinimalConfig::domain_beacon_proposer(), Some(0));
+        let signed_block = SignedBeaconBlock {
+            signature: Signature::new(&block.signed_root(), domain, &proposer.sk),
+            message: block,
+        };
+        beacon_node.publish_block(signed_block).await.unwrap();
+
+        let chain = simulator.chain.lock().unwrap();
+        assert_eq!(chain.head_state().slot, slot);
+        assert_eq!(chain.blocks().len(), 1);
+    }
+
+    #[test]
+    fn should_reject_block_not_signed_by_proposer() {
+        let genesis_state = interop_genesis_state::<MinimalConfig>(VALIDATOR_COUNT, 0).unwrap();
+        let mut chain = SimulatedChain::new(genesis_state);
+        let mut block = chain
+            .produce_block(1, &Signature::empty_signature())
+            .unwrap();
+        block.signature = Signature::empty_signature();
+
+        assert_eq!(
+            chain.import_block(block),
+            Err(Error::StateTransition(
+                state_transition::Error::InvalidSignature
+            ))
+        );
+        assert_eq!(chain.head_state().slot, 0);
+    }
+
+    #[test]
+    fn should_answer_unknown_routes_with_not_found() {
+        let genesis_state = interop_genesis_state::<MinimalConfig>(VALIDATOR_COUNT, 0).unwrap();
+        let mut chain = SimulatedChain::new(genesis_state);
+
+        assert_eq!(
+            route(&mut chain, &Method::GET, "/node/version", None, &[]),
+            Err(Error::NotFound)
+        );
+    }
+}
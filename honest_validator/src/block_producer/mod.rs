@@ -1,58 +1,387 @@
-use crate::beacon_node::{BasicBeaconNode, BeaconNode, Error};
-use bls::PublicKeyBytes;
+use crate::beacon_node::BeaconNode;
+use crate::eth1_data_source::Eth1DataSource;
+use crate::slashing_protection::SlashingProtection;
 use bls::{SecretKey, Signature};
-use hex;
-use std::{thread, time};
-use tree_hash::TreeHash;
+use log::error;
+use std::cmp::Reverse;
+use tree_hash::{SignedRoot, TreeHash};
+use typenum::Unsigned;
 use types::beacon_state::BeaconState;
-use types::config::{Config as EthConfig, MinimalConfig};
-use types::primitives::{Epoch, Slot, ValidatorIndex, H256};
-use types::types::{BeaconBlock, Eth1Data, SignedBeaconBlock};
+use types::chain_spec::ChainSpec;
+use types::config::Config as EthConfig;
+use types::helper_functions::{compute_epoch_at_slot, integer_squareroot};
+use types::primitives::{Epoch, Slot, H256};
+use types::types::{Eth1Data, SignedBeaconBlock};
 
-pub fn produce_block(
-    beacon_node: &BasicBeaconNode,
-    state: &BeaconState<MinimalConfig>,
+/// Proposes a block for `slot`: reveals RANDAO for the epoch, asks the beacon node for an unsigned
+/// block, fills in the eth1 vote and the configured graffiti, signs the block and publishes it.
+/// Returns `None` if any step fails or the block would be slashable.
+#[allow(clippy::too_many_arguments)]
+pub async fn produce_block<C: EthConfig, B: BeaconNode<C>, E: Eth1DataSource + ?Sized>(
+    beacon_node: &B,
+    spec: &ChainSpec,
+    slashing_protection: &SlashingProtection,
+    eth1_data_source: &E,
+    graffiti: Option<[u8; 32]>,
+    pubkey: &str,
+    state: &BeaconState<C>,
     privkey: SecretKey,
     slot: Slot,
-) -> BeaconBlock<MinimalConfig> {
-    let domain = beacon_node.get_domain(
-        &state,
-        MinimalConfig::domain_beacon_proposer(),
-        Some(beacon_node.get_current_epoch(&state).clone()),
-    );
-    let root = state.block_roots.last().unwrap();
-    let parent_block = beacon_node.get_block(slot, hex::encode(root)).unwrap();
-    let mut new_block = beacon_node.get_block(slot, hex::encode(root)).unwrap();
-    let mut block_confirmed = false;
-    if parent_block.slot < slot {
-        new_block.parent_root = H256::from_slice(&parent_block.tree_hash_root()[..]);
-        new_block.body.randao_reveal =
-            Signature::new(&new_block.tree_hash_root()[..], domain, &privkey);
-        new_block.body.eth1_data = state.eth1_data.clone();
-    }
-    new_block
+) -> Option<SignedBeaconBlock<C>> {
+    let epoch: Epoch = compute_epoch_at_slot::<C>(slot);
+    let randao_reveal = get_randao_reveal(spec, state, epoch, &privkey);
+
+    let mut block = match beacon_node.get_block(slot, &randao_reveal).await {
+        Ok(block) => block,
+        Err(e) => {
+            error!("Failed to fetch block for slot {}: {:?}", slot, e);
+            return None;
+        }
+    };
+    if block.slot != slot {
+        error!(
+            "Beacon node returned block for slot {}, expected {}",
+            block.slot, slot
+        );
+        return None;
+    }
+    block.body.eth1_data = get_eth1_vote(spec, state, slot, eth1_data_source);
+    if let Some(graffiti) = graffiti {
+        block.body.graffiti = graffiti;
+    }
+
+    let signing_root = block.signed_root();
+    if let Err(e) = slashing_protection.check_and_insert_block(
+        pubkey,
+        slot,
+        H256::from_slice(&signing_root[..]),
+    ) {
+        error!("Refusing to sign slashable block: {:?}", e);
+        return None;
+    }
+    let domain = state.get_domain(spec.domain_beacon_proposer, Some(epoch));
+    let signed_block = SignedBeaconBlock {
+        signature: Signature::new(&signing_root[..], domain, &privkey),
+        message: block,
+    };
+
+    match beacon_node.publish_block(signed_block.clone()).await {
+        Ok(()) => Some(signed_block),
+        Err(e) => {
+            error!("Failed to publish block: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Signature over the epoch number, mixed into the RANDAO of the state by the block.
+pub fn get_randao_reveal<C: EthConfig>(
+    spec: &ChainSpec,
+    state: &BeaconState<C>,
+    epoch: Epoch,
+    privkey: &SecretKey,
+) -> Signature {
+    let domain = state.get_domain(spec.domain_randao, Some(epoch));
+    Signature::new(&epoch.tree_hash_root()[..], domain, privkey)
+}
+
+/// Eth1 vote of a block proposed at `slot`, following the honest validator algorithm: the most
+/// common vote of the current voting period among the blocks the validator considers valid, ties
+/// broken by the smallest distance. Without valid votes, the block at the follow distance is chosen.
+pub fn get_eth1_vote<C: EthConfig, E: Eth1DataSource + ?Sized>(
+    spec: &ChainSpec,
+    state: &BeaconState<C>,
+    slot: Slot,
+    eth1_data_source: &E,
+) -> Eth1Data {
+    let follow_distance = spec.eth1_follow_distance;
+    // An unknown block is treated as if it was just outside of the new votes window.
+    let previous_eth1_distance = eth1_data_source
+        .get_distance(&state.eth1_data)
+        .unwrap_or(2 * follow_distance);
+    let new_eth1_data = get_eth1_data_range(eth1_data_source, follow_distance, 2 * follow_distance);
+    let all_eth1_data =
+        get_eth1_data_range(eth1_data_source, follow_distance, previous_eth1_distance);
+
+    let slots_per_eth1_voting_period = C::SlotsPerEth1VotingPeriod::to_u64();
+    let period_tail =
+        slot % slots_per_eth1_voting_period >= integer_squareroot(slots_per_eth1_voting_period);
+    let votes_to_consider = if period_tail {
+        &all_eth1_data
+    } else {
+        &new_eth1_data
+    };
+
+    let valid_votes: Vec<&Eth1Data> = state
+        .eth1_data_votes
+        .iter()
+        .filter(|vote| votes_to_consider.contains(vote))
+        .collect();
+    valid_votes
+        .iter()
+        .max_by_key(|vote| {
+            let count = valid_votes.iter().filter(|other| other == vote).count();
+            let index = votes_to_consider
+                .iter()
+                .position(|eth1_data| eth1_data == **vote);
+            (count, Reverse(index))
+        })
+        .map(|vote| (*vote).clone())
+        .or_else(|| eth1_data_source.get_eth1_data(follow_distance))
+        .unwrap_or_else(|| state.eth1_data.clone())
 }
 
-// pub fn get_eth1_vote(state: BeaconState<MinimalConfig>, previous_eth1_distance: u64) -> Eth1Data {
-//     //let ETH1_FOLLOW_DISTANCE = state.eth1_
-//     new_eth1_data = [state.e for distance in range(ETH1_FOLLOW_DISTANCE, 2 * ETH1_FOLLOW_DISTANCE)]
-//     all_eth1_data = [get_eth1_data(distance) for distance in range(ETH1_FOLLOW_DISTANCE, previous_eth1_distance)]
-
-//     period_tail = state.slot % SLOTS_PER_ETH1_VOTING_PERIOD >= integer_squareroot(SLOTS_PER_ETH1_VOTING_PERIOD)
-//     if period_tail:
-//         votes_to_consider = all_eth1_data
-//     else:
-//         votes_to_consider = new_eth1_data
-
-//     valid_votes = [vote for vote in state.eth1_data_votes if vote in votes_to_consider]
-
-//     return max(
-//         valid_votes,
-//         key=lambda v: (valid_votes.count(v), -all_eth1_data.index(v)),  # Tiebreak by smallest distance
-//         default=get_eth1_data(ETH1_FOLLOW_DISTANCE),
-//     )
-// }
-
-// pub fn get_eth1_data(state: BeaconState<MinimalConfig>, distance: u64) -> Eth1Data {
-//     let eth_data = state.eth1_data_votes;
-// }
+fn get_eth1_data_range<E: Eth1DataSource + ?Sized>(
+    eth1_data_source: &E,
+    from_distance: u64,
+    to_distance: u64,
+) -> Vec<Eth1Data> {
+    (from_distance..to_distance)
+        .filter_map(|distance| eth1_data_source.get_eth1_data(distance))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth1_data_source::InMemoryEth1DataSource;
+    use crate::mock_beacon_node::MockBeaconNode;
+    use bls::{Keypair, PublicKey};
+    use ssz_types::VariableList;
+    use types::config::MinimalConfig;
+    use types::types::{BeaconBlock, BeaconBlockBody};
+
+    fn keypair() -> Keypair {
+        let sk = SecretKey::from_bytes(&[3; 48]).unwrap();
+        Keypair {
+            pk: PublicKey::from_secret_key(&sk),
+            sk,
+        }
+    }
+
+    fn unsigned_block(slot: Slot) -> BeaconBlock<MinimalConfig> {
+        BeaconBlock {
+            slot,
+            parent_root: H256::from_slice(&[1; 32]),
+            state_root: H256::from_slice(&[2; 32]),
+            body: BeaconBlockBody {
+                randao_reveal: Signature::empty_signature(),
+                eth1_data: Default::default(),
+                graffiti: [0; 32],
+                proposer_slashings: Default::default(),
+                attester_slashings: Default::default(),
+                attestations: Default::default(),
+                deposits: Default::default(),
+                voluntary_exits: Default::default(),
+                transfers: Default::default(),
+            },
+            signature: Signature::empty_signature(),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_sign_and_publish_block() {
+        let keypair = keypair();
+        let state = BeaconState::default();
+        let beacon_node = MockBeaconNode::new(state.clone()).with_block(unsigned_block(9));
+        let slashing_protection = SlashingProtection::in_memory();
+
+        let signed_block = produce_block(
+            &beacon_node,
+            &spec(),
+            &slashing_protection,
+            &InMemoryEth1DataSource::default(),
+            None,
+            "0x01",
+            &state,
+            keypair.sk.clone(),
+            9,
+        )
+        .await
+        .unwrap();
+
+        let randao_domain = state.get_domain(MinimalConfig::domain_randao(), Some(1));
+        assert!(signed_block.message.body.randao_reveal.verify(
+            &1u64.tree_hash_root()[..],
+            randao_domain,
+            &keypair.pk
+        ));
+        let proposer_domain = state.get_domain(MinimalConfig::domain_beacon_proposer(), Some(1));
+        assert!(signed_block.signature.verify(
+            &signed_block.message.signed_root()[..],
+            proposer_domain,
+            &keypair.pk
+        ));
+        assert_eq!(
+            *beacon_node.published_blocks.lock().unwrap(),
+            vec![signed_block.clone()]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_not_sign_double_proposal() {
+        let keypair = keypair();
+        let state = BeaconState::default();
+        let slashing_protection = SlashingProtection::in_memory();
+        let beacon_node = MockBeaconNode::new(state.clone()).with_block(unsigned_block(9));
+        assert!(produce_block(
+            &beacon_node,
+            &spec(),
+            &slashing_protection,
+            &InMemoryEth1DataSource::default(),
+            None,
+            "0x01",
+            &state,
+            keypair.sk.clone(),
+            9
+        )
+        .await
+        .is_some());
+
+        let mut other_block = unsigned_block(9);
+        other_block.state_root = H256::from_slice(&[3; 32]);
+        let beacon_node = MockBeaconNode::new(state.clone()).with_block(other_block);
+        assert!(produce_block(
+            &beacon_node,
+            &spec(),
+            &slashing_protection,
+            &InMemoryEth1DataSource::default(),
+            None,
+            "0x01",
+            &state,
+            keypair.sk,
+            9
+        )
+        .await
+        .is_none());
+        assert!(beacon_node.published_blocks.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_replace_graffiti_of_beacon_node() {
+        let keypair = keypair();
+        let state = BeaconState::default();
+        let mut block = unsigned_block(9);
+        block.body.graffiti = [1; 32];
+        let beacon_node = MockBeaconNode::new(state.clone()).with_block(block);
+
+        let signed_block = produce_block(
+            &beacon_node,
+            &spec(),
+            &SlashingProtection::in_memory(),
+            &InMemoryEth1DataSource::default(),
+            Some([2; 32]),
+            "0x01",
+            &state,
+            keypair.sk,
+            9,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(signed_block.message.body.graffiti, [2; 32]);
+    }
+
+    #[tokio::test]
+    async fn should_refuse_block_for_other_slot() {
+        let keypair = keypair();
+        let state = BeaconState::default();
+        let beacon_node = MockBeaconNode::new(state.clone()).with_block(unsigned_block(8));
+        let slashing_protection = SlashingProtection::in_memory();
+
+        assert!(produce_block(
+            &beacon_node,
+            &spec(),
+            &slashing_protection,
+            &InMemoryEth1DataSource::default(),
+            None,
+            "0x01",
+            &state,
+            keypair.sk,
+            9
+        )
+        .await
+        .is_none());
+    }
+
+    fn spec() -> ChainSpec {
+        ChainSpec::from_config::<MinimalConfig>()
+    }
+
+    fn eth1_chain(length: u8) -> InMemoryEth1DataSource {
+        InMemoryEth1DataSource::new(
+            (0..length)
+                .map(|number| Eth1Data {
+                    deposit_root: H256::zero(),
+                    deposit_count: u64::from(number),
+                    block_hash: H256::from_slice(&[number; 32]),
+                })
+                .collect(),
+        )
+    }
+
+    fn state_with_votes(eth1_data: Eth1Data, votes: Vec<Eth1Data>) -> BeaconState<MinimalConfig> {
+        BeaconState {
+            eth1_data,
+            eth1_data_votes: VariableList::new(votes).unwrap(),
+            ..BeaconState::default()
+        }
+    }
+
+    #[test]
+    fn should_default_to_block_at_follow_distance() {
+        let source = eth1_chain(40);
+        let state = state_with_votes(source.get_eth1_data(35).unwrap(), vec![]);
+
+        let vote = get_eth1_vote(&spec(), &state, 0, &source);
+        assert_eq!(vote, source.get_eth1_data(16).unwrap());
+    }
+
+    #[test]
+    fn should_keep_state_eth1_data_without_eth1_chain() {
+        let state = state_with_votes(eth1_chain(1).get_eth1_data(0).unwrap(), vec![]);
+
+        let vote = get_eth1_vote(&spec(), &state, 0, &InMemoryEth1DataSource::default());
+        assert_eq!(vote, state.eth1_data);
+    }
+
+    #[test]
+    fn should_join_most_common_valid_vote() {
+        let source = eth1_chain(40);
+        let too_recent = source.get_eth1_data(3).unwrap();
+        let popular = source.get_eth1_data(20).unwrap();
+        let closer = source.get_eth1_data(17).unwrap();
+        let state = state_with_votes(
+            source.get_eth1_data(35).unwrap(),
+            vec![
+                too_recent.clone(),
+                too_recent.clone(),
+                too_recent,
+                closer.clone(),
+                popular.clone(),
+                popular.clone(),
+            ],
+        );
+        assert_eq!(get_eth1_vote(&spec(), &state, 0, &source), popular);
+
+        let tied_state = state_with_votes(
+            state.eth1_data.clone(),
+            vec![popular.clone(), closer.clone()],
+        );
+        assert_eq!(get_eth1_vote(&spec(), &tied_state, 0, &source), closer);
+    }
+
+    #[test]
+    fn should_only_consider_blocks_newer_than_state_in_period_tail() {
+        let source = eth1_chain(40);
+        // Outside of the new votes window, but still newer than the block in the state.
+        let old_vote = source.get_eth1_data(34).unwrap();
+        let state = state_with_votes(source.get_eth1_data(35).unwrap(), vec![old_vote.clone()]);
+
+        assert_eq!(
+            get_eth1_vote(&spec(), &state, 0, &source),
+            source.get_eth1_data(16).unwrap()
+        );
+        assert_eq!(get_eth1_vote(&spec(), &state, 4, &source), old_vote);
+    }
+}
@@ -0,0 +1,641 @@
+use clap::{Arg, ArgMatches};
+use hyper::Uri;
+use log::LevelFilter;
+use serde::Deserialize;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const DEFAULT_BEACON_NODE: &str = "http://localhost:5052";
+
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// A setting has a value the client can not use. `name` is the command line flag of the
+    /// setting, which is also its key in the config file. Config files that can not be read or
+    /// parsed are reported as the `config` setting.
+    InvalidSetting { name: &'static str, reason: String },
+}
+
+fn invalid_setting(name: &'static str, reason: String) -> Error {
+    Error::InvalidSetting { name, reason }
+}
+
+/// Spec presets the client is compiled for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Preset {
+    Mainnet,
+    Minimal,
+    InternalTest,
+}
+
+impl Preset {
+    pub const NAMES: [&'static str; 3] = ["mainnet", "minimal", "internal_test"];
+
+    pub fn from_name(name: &str) -> Option<Preset> {
+        match name {
+            "mainnet" => Some(Preset::Mainnet),
+            "minimal" => Some(Preset::Minimal),
+            "internal_test" => Some(Preset::InternalTest),
+            _ => None,
+        }
+    }
+}
+
+/// Settings of the validator client, with defaults applied and every value validated.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ValidatorConfig {
+    pub preset: Preset,
+    pub chain_spec: Option<PathBuf>,
+    pub beacon_nodes: Vec<String>,
+    pub datadir: PathBuf,
+    pub keystores_dir: PathBuf,
+    pub secrets_dir: PathBuf,
+    pub slashing_protection_db: PathBuf,
+    pub eth1_data: Option<PathBuf>,
+    pub graffiti: Option<[u8; 32]>,
+    pub log_level: LevelFilter,
+    pub metrics_address: Option<SocketAddr>,
+    /// The client runs until stopped if not set.
+    pub run_duration: Option<Duration>,
+}
+
+impl ValidatorConfig {
+    /// Settings given by the flags of `args`, then by the environment variables looked up by `env`,
+    /// falling back to the config file named by `--config` and then to the defaults.
+    pub fn load(
+        matches: &ArgMatches,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<ValidatorConfig, Error> {
+        let config_file = matches
+            .value_of("config")
+            .map(String::from)
+            .or_else(|| env(&env_var("config")));
+        let file = match config_file {
+            Some(path) => PartialConfig::from_file(Path::new(&path))?,
+            None => PartialConfig::default(),
+        };
+        file.merge(PartialConfig::from_env(env))
+            .merge(PartialConfig::from_matches(matches))
+            .validate()
+    }
+}
+
+/// Settings of a single source, `None` where the source leaves them unset. The config file uses
+/// the names of the command line flags as keys.
+#[derive(Clone, Default, PartialEq, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct PartialConfig {
+    pub spec: Option<String>,
+    pub chain_spec: Option<PathBuf>,
+    pub beacon_nodes: Option<Vec<String>>,
+    pub datadir: Option<PathBuf>,
+    pub keystores: Option<PathBuf>,
+    pub secrets: Option<PathBuf>,
+    pub slashing_protection_db: Option<PathBuf>,
+    pub eth1_data: Option<PathBuf>,
+    pub graffiti: Option<String>,
+    pub log_level: Option<String>,
+    pub metrics_address: Option<String>,
+    pub run_duration: Option<String>,
+}
+
+impl PartialConfig {
+    pub fn from_file(path: &Path) -> Result<PartialConfig, Error> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| invalid_setting("config", format!("{}: {}", path.display(), e)))?;
+        PartialConfig::from_toml_str(&contents)
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<PartialConfig, Error> {
+        toml::from_str(contents).map_err(|e| invalid_setting("config", e.to_string()))
+    }
+
+    /// Settings given on the command line.
+    pub fn from_matches(matches: &ArgMatches) -> PartialConfig {
+        let string = |name| matches.value_of(name).map(String::from);
+        let path = |name| matches.value_of(name).map(PathBuf::from);
+        PartialConfig {
+            spec: string("spec"),
+            chain_spec: path("chain-spec"),
+            beacon_nodes: matches
+                .values_of("beacon-nodes")
+                .map(|urls| urls.map(String::from).collect()),
+            datadir: path("datadir"),
+            keystores: path("keystores"),
+            secrets: path("secrets"),
+            slashing_protection_db: path("slashing-protection-db"),
+            eth1_data: path("eth1-data"),
+            graffiti: string("graffiti"),
+            log_level: string("log-level"),
+            metrics_address: string("metrics-address"),
+            run_duration: string("run-duration"),
+        }
+    }
+
+    /// Settings given by the environment variables named by `env_var`, as looked up by `env`.
+    /// Beacon nodes are separated by commas, as on the command line.
+    pub fn from_env(env: impl Fn(&str) -> Option<String>) -> PartialConfig {
+        let string = |flag| env(&env_var(flag));
+        let path = |flag| string(flag).map(PathBuf::from);
+        PartialConfig {
+            spec: string("spec"),
+            chain_spec: path("chain-spec"),
+            beacon_nodes: string("beacon-nodes")
+                .map(|urls| urls.split(',').map(String::from).collect()),
+            datadir: path("datadir"),
+            keystores: path("keystores"),
+            secrets: path("secrets"),
+            slashing_protection_db: path("slashing-protection-db"),
+            eth1_data: path("eth1-data"),
+            graffiti: string("graffiti"),
+            log_level: string("log-level"),
+            metrics_address: string("metrics-address"),
+            run_duration: string("run-duration"),
+        }
+    }
+
+    /// Settings of `self`, overridden by those `higher` sets.
+    pub fn merge(self, higher: PartialConfig) -> PartialConfig {
+        PartialConfig {
+            spec: higher.spec.or(self.spec),
+            chain_spec: higher.chain_spec.or(self.chain_spec),
+            beacon_nodes: higher.beacon_nodes.or(self.beacon_nodes),
+            datadir: higher.datadir.or(self.datadir),
+            keystores: higher.keystores.or(self.keystores),
+            secrets: higher.secrets.or(self.secrets),
+            slashing_protection_db: higher
+                .slashing_protection_db
+                .or(self.slashing_protection_db),
+            eth1_data: higher.eth1_data.or(self.eth1_data),
+            graffiti: higher.graffiti.or(self.graffiti),
+            log_level: higher.log_level.or(self.log_level),
+            metrics_address: higher.metrics_address.or(self.metrics_address),
+            run_duration: higher.run_duration.or(self.run_duration),
+        }
+    }
+
+    /// Fills in the defaults of unset settings. Keystores, secrets and the slashing protection
+    /// database are kept in the data directory unless given explicitly.
+    pub fn validate(self) -> Result<ValidatorConfig, Error> {
+        let preset = match self.spec {
+            Some(name) => Preset::from_name(&name).ok_or_else(|| {
+                invalid_setting(
+                    "spec",
+                    format!(
+                        "unknown spec type {}, expected one of {}",
+                        name,
+                        Preset::NAMES.join(", ")
+                    ),
+                )
+            })?,
+            None => Preset::Minimal,
+        };
+
+        let beacon_nodes = self
+            .beacon_nodes
+            .unwrap_or_else(|| vec![String::from(DEFAULT_BEACON_NODE)]);
+        if beacon_nodes.is_empty() {
+            return Err(invalid_setting(
+                "beacon-nodes",
+                String::from("at least one beacon node is required"),
+            ));
+        }
+        for url in &beacon_nodes {
+            validate_beacon_node_url(url).map_err(|e| invalid_setting("beacon-nodes", e))?;
+        }
+
+        let datadir = self.datadir.unwrap_or_else(|| PathBuf::from("."));
+        let keystores_dir = self.keystores.unwrap_or_else(|| datadir.join("keystores"));
+        let secrets_dir = self.secrets.unwrap_or_else(|| datadir.join("secrets"));
+        let slashing_protection_db = self
+            .slashing_protection_db
+            .unwrap_or_else(|| datadir.join("slashing_protection.json"));
+
+        let graffiti = match self.graffiti {
+            Some(graffiti) => {
+                Some(parse_graffiti(&graffiti).map_err(|e| invalid_setting("graffiti", e))?)
+            }
+            None => None,
+        };
+        let log_level = match self.log_level {
+            Some(level) => level.parse().map_err(|_| {
+                invalid_setting(
+                    "log-level",
+                    format!(
+                        "unknown log level {}, expected one of off, error, warn, info, debug, trace",
+                        level
+                    ),
+                )
+            })?,
+            None => LevelFilter::Info,
+        };
+        let metrics_address = match self.metrics_address {
+            Some(address) => Some(address.parse().map_err(|_| {
+                invalid_setting(
+                    "metrics-address",
+                    format!("{} is not an address such as 127.0.0.1:5064", address),
+                )
+            })?),
+            None => None,
+        };
+        let run_duration = match self.run_duration {
+            Some(duration) => {
+                Some(parse_duration(&duration).map_err(|e| invalid_setting("run-duration", e))?)
+            }
+            None => None,
+        };
+
+        Ok(ValidatorConfig {
+            preset,
+            chain_spec: self.chain_spec,
+            beacon_nodes,
+            datadir,
+            keystores_dir,
+            secrets_dir,
+            slashing_protection_db,
+            eth1_data: self.eth1_data,
+            graffiti,
+            log_level,
+            metrics_address,
+            run_duration,
+        })
+    }
+}
+
+/// Environment variable of the setting with the command line flag `flag`.
+fn env_var(flag: &str) -> String {
+    format!("VALIDATOR_{}", flag.to_uppercase().replace('-', "_"))
+}
+
+/// Flags of the settings read by `ValidatorConfig::load`. Each one falls back to the environment
+/// variable named by `env_var`, which `load` reads itself rather than through clap.
+pub fn args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("config")
+            .long("config")
+            .value_name("FILE")
+            .help("TOML file with the settings below, keyed by flag name. Environment variables and flags take precedence over it. [env: VALIDATOR_CONFIG]")
+            .takes_value(true),
+        Arg::with_name("spec")
+            .short("s")
+            .long("spec")
+            .value_name("CONFIGURATION")
+            .help("Specifies the eth2 spec type of the chain. [default: minimal] [env: VALIDATOR_SPEC]")
+            .takes_value(true)
+            .possible_values(&Preset::NAMES),
+        Arg::with_name("chain-spec")
+            .long("chain-spec")
+            .value_name("FILE")
+            .help("eth2 YAML config file of the chain. Defaults to the constants of the spec type. [env: VALIDATOR_CHAIN_SPEC]")
+            .takes_value(true),
+        Arg::with_name("beacon-nodes")
+            .short("b")
+            .long("beacon-nodes")
+            .visible_alias("beacon-node")
+            .value_name("URLS")
+            .help("Comma separated HTTP API endpoints of the beacon nodes, the first ones are preferred. [default: http://localhost:5052] [env: VALIDATOR_BEACON_NODES]")
+            .takes_value(true)
+            .use_delimiter(true),
+        Arg::with_name("datadir")
+            .short("d")
+            .long("datadir")
+            .value_name("DIR")
+            .help("Directory holding the keystores, secrets and slashing protection database unless given separately. [default: .] [env: VALIDATOR_DATADIR]")
+            .takes_value(true),
+        Arg::with_name("keystores")
+            .long("keystores")
+            .value_name("DIR")
+            .help("Directory with the EIP-2335 keystores of the validators. [default: DATADIR/keystores] [env: VALIDATOR_KEYSTORES]")
+            .takes_value(true),
+        Arg::with_name("secrets")
+            .long("secrets")
+            .value_name("DIR")
+            .help("Directory with the keystore passwords, one file per 0x-prefixed public key. [default: DATADIR/secrets] [env: VALIDATOR_SECRETS]")
+            .takes_value(true),
+        Arg::with_name("slashing-protection-db")
+            .long("slashing-protection-db")
+            .value_name("FILE")
+            .help("File recording every message signed by the validators. [default: DATADIR/slashing_protection.json] [env: VALIDATOR_SLASHING_PROTECTION_DB]")
+            .takes_value(true),
+        Arg::with_name("eth1-data")
+            .long("eth1-data")
+            .value_name("FILE")
+            .help("JSON file with the eth1 chain to vote for, oldest block first. [env: VALIDATOR_ETH1_DATA]")
+            .takes_value(true),
+        Arg::with_name("graffiti")
+            .long("graffiti")
+            .value_name("GRAFFITI")
+            .help("Up to 32 bytes included in proposed blocks, as text or 0x-prefixed hex. [env: VALIDATOR_GRAFFITI]")
+            .takes_value(true),
+        Arg::with_name("log-level")
+            .long("log-level")
+            .value_name("LEVEL")
+            .help("One of off, error, warn, info, debug or trace. [default: info] [env: VALIDATOR_LOG_LEVEL]")
+            .takes_value(true),
+        Arg::with_name("metrics-address")
+            .long("metrics-address")
+            .value_name("ADDRESS")
+            .help("Address to serve Prometheus metrics on, such as 127.0.0.1:5064. Disabled if omitted. [env: VALIDATOR_METRICS_ADDRESS]")
+            .takes_value(true),
+        Arg::with_name("run-duration")
+            .long("run-duration")
+            .value_name("DURATION")
+            .help("Stops the client after the epoch in which this much time has passed, such as 90s, 30m, 2h or 1d. Runs until stopped if omitted. [env: VALIDATOR_RUN_DURATION]")
+            .takes_value(true),
+    ]
+}
+
+/// The client talks to beacon nodes over plain HTTP only.
+fn validate_beacon_node_url(url: &str) -> Result<(), String> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|e| format!("{} is not a valid URL: {}", url, e))?;
+    match uri.scheme_str() {
+        Some("http") if uri.host().is_some() => Ok(()),
+        Some("http") => Err(format!("{} has no host", url)),
+        _ => Err(format!("{} is not an http:// URL", url)),
+    }
+}
+
+/// Text is used as is and `0x` prefixed values are decoded as hex, both are padded with zeros.
+fn parse_graffiti(value: &str) -> Result<[u8; 32], String> {
+    let bytes = match value.strip_prefix("0x") {
+        Some(digits) => {
+            hex::decode(digits).map_err(|e| format!("{} is not valid hex: {}", value, e))?
+        }
+        None => value.as_bytes().to_vec(),
+    };
+    if bytes.len() > 32 {
+        return Err(format!(
+            "{} is {} bytes long, at most 32 are allowed",
+            value,
+            bytes.len()
+        ));
+    }
+    let mut graffiti = [0; 32];
+    graffiti[..bytes.len()].copy_from_slice(&bytes);
+    Ok(graffiti)
+}
+
+/// Whole number of seconds, minutes, hours or days, such as `90s` or `2h`. Plain numbers are
+/// seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("{} is not a duration such as 90s, 30m, 2h or 1d", value);
+    let unit_start = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let unit_seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    if number == 0 {
+        return Err(String::from("must be positive"));
+    }
+    number
+        .checked_mul(unit_seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("{} is too long", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::App;
+    use std::collections::HashMap;
+
+    fn matches_from(argv: &[&str]) -> ArgMatches<'static> {
+        App::new("validator_client")
+            .args(&args())
+            .get_matches_from_safe(argv)
+            .unwrap()
+    }
+
+    #[test]
+    fn should_apply_defaults() {
+        let config = PartialConfig::default().validate().unwrap();
+
+        assert_eq!(config.preset, Preset::Minimal);
+        assert_eq!(config.beacon_nodes, vec![DEFAULT_BEACON_NODE]);
+        assert_eq!(config.keystores_dir, Path::new("./keystores"));
+        assert_eq!(config.secrets_dir, Path::new("./secrets"));
+        assert_eq!(
+            config.slashing_protection_db,
+            Path::new("./slashing_protection.json")
+        );
+        assert_eq!(config.graffiti, None);
+        assert_eq!(config.log_level, LevelFilter::Info);
+        assert_eq!(config.metrics_address, None);
+        assert_eq!(config.run_duration, None);
+    }
+
+    #[test]
+    fn should_read_config_file() {
+        let file = PartialConfig::from_toml_str(
+            r#"
+            spec = "mainnet"
+            beacon-nodes = ["http://10.0.0.1:5052", "http://10.0.0.2:5052"]
+            datadir = "/var/lib/validator"
+            secrets = "/run/secrets"
+            graffiti = "hello"
+            log-level = "debug"
+            metrics-address = "127.0.0.1:5064"
+            run-duration = "2h"
+            "#,
+        )
+        .unwrap();
+        let config = file.validate().unwrap();
+
+        assert_eq!(config.preset, Preset::Mainnet);
+        assert_eq!(config.beacon_nodes.len(), 2);
+        assert_eq!(
+            config.keystores_dir,
+            Path::new("/var/lib/validator/keystores")
+        );
+        assert_eq!(config.secrets_dir, Path::new("/run/secrets"));
+        assert_eq!(&config.graffiti.unwrap()[..6], b"hello\0");
+        assert_eq!(config.log_level, LevelFilter::Debug);
+        assert_eq!(config.metrics_address, Some(([127, 0, 0, 1], 5064).into()));
+        assert_eq!(config.run_duration, Some(Duration::from_secs(7200)));
+    }
+
+    #[test]
+    fn should_reject_unknown_keys_in_config_file() {
+        match PartialConfig::from_toml_str("beacon-node = \"http://localhost:5052\"") {
+            Err(Error::InvalidSetting { name, reason }) => {
+                assert_eq!(name, "config");
+                assert!(reason.contains("beacon-node"), "{}", reason);
+            }
+            other => panic!("expected an invalid file, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_prefer_flags_over_config_file() {
+        let file = PartialConfig::from_toml_str(
+            r#"
+            spec = "mainnet"
+            log-level = "debug"
+            beacon-nodes = ["http://10.0.0.1:5052"]
+            "#,
+        )
+        .unwrap();
+        let flags = PartialConfig::from_matches(&matches_from(&[
+            "validator_client",
+            "--log-level",
+            "warn",
+            "--beacon-nodes",
+            "http://10.0.0.2:5052,http://10.0.0.3:5052",
+        ]));
+
+        let config = file.merge(flags).validate().unwrap();
+
+        assert_eq!(config.preset, Preset::Mainnet);
+        assert_eq!(config.log_level, LevelFilter::Warn);
+        assert_eq!(
+            config.beacon_nodes,
+            vec!["http://10.0.0.2:5052", "http://10.0.0.3:5052"]
+        );
+    }
+
+    fn env(vars: &[(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<&str, &str> = vars.iter().cloned().collect();
+        move |name| vars.get(name).map(|value| String::from(*value))
+    }
+
+    #[test]
+    fn should_read_environment_variables() {
+        let config = PartialConfig::from_env(env(&[
+            (
+                "VALIDATOR_BEACON_NODES",
+                "http://10.0.0.1:5052,http://10.0.0.2:5052",
+            ),
+            ("VALIDATOR_SLASHING_PROTECTION_DB", "/var/lib/history.json"),
+            ("VALIDATOR_RUN_DURATION", "1h"),
+            ("RUN_DURATION", "2h"),
+        ]));
+
+        assert_eq!(
+            config,
+            PartialConfig {
+                beacon_nodes: Some(vec![
+                    String::from("http://10.0.0.1:5052"),
+                    String::from("http://10.0.0.2:5052"),
+                ]),
+                slashing_protection_db: Some(PathBuf::from("/var/lib/history.json")),
+                run_duration: Some(String::from("1h")),
+                ..PartialConfig::default()
+            }
+        );
+    }
+
+    #[test]
+    fn should_prefer_environment_over_config_file() {
+        let file = PartialConfig::from_toml_str("run-duration = \"3h\"").unwrap();
+        let environment = env(&[("VALIDATOR_RUN_DURATION", "1h")]);
+
+        let from_env = file
+            .clone()
+            .merge(PartialConfig::from_env(&environment))
+            .merge(PartialConfig::from_matches(&matches_from(&[
+                "validator_client",
+            ])))
+            .validate()
+            .unwrap();
+        let from_flag = file
+            .merge(PartialConfig::from_env(&environment))
+            .merge(PartialConfig::from_matches(&matches_from(&[
+                "validator_client",
+                "--run-duration",
+                "2h",
+            ])))
+            .validate()
+            .unwrap();
+
+        assert_eq!(from_env.run_duration, Some(Duration::from_secs(3600)));
+        assert_eq!(from_flag.run_duration, Some(Duration::from_secs(7200)));
+    }
+
+    #[test]
+    fn should_name_invalid_setting() {
+        let cases: Vec<(&'static str, PartialConfig)> = vec![
+            (
+                "spec",
+                PartialConfig {
+                    spec: Some(String::from("testnet")),
+                    ..PartialConfig::default()
+                },
+            ),
+            (
+                "beacon-nodes",
+                PartialConfig {
+                    beacon_nodes: Some(vec![String::from("https://10.0.0.1:5052")]),
+                    ..PartialConfig::default()
+                },
+            ),
+            (
+                "beacon-nodes",
+                PartialConfig {
+                    beacon_nodes: Some(vec![]),
+                    ..PartialConfig::default()
+                },
+            ),
+            (
+                "graffiti",
+                PartialConfig {
+                    graffiti: Some("x".repeat(33)),
+                    ..PartialConfig::default()
+                },
+            ),
+            (
+                "log-level",
+                PartialConfig {
+                    log_level: Some(String::from("verbose")),
+                    ..PartialConfig::default()
+                },
+            ),
+            (
+                "metrics-address",
+                PartialConfig {
+                    metrics_address: Some(String::from("localhost")),
+                    ..PartialConfig::default()
+                },
+            ),
+            (
+                "run-duration",
+                PartialConfig {
+                    run_duration: Some(String::from("5 weeks")),
+                    ..PartialConfig::default()
+                },
+            ),
+        ];
+
+        for (setting, config) in cases {
+            match config.validate() {
+                Err(Error::InvalidSetting { name, .. }) => assert_eq!(name, setting),
+                other => panic!("expected {} to be invalid, got {:?}", setting, other),
+            }
+        }
+    }
+
+    #[test]
+    fn should_parse_hex_graffiti() {
+        let graffiti = parse_graffiti("0x0102").unwrap();
+        assert_eq!(&graffiti[..3], &[1, 2, 0]);
+        assert!(parse_graffiti("0xzz").is_err());
+    }
+
+    #[test]
+    fn should_parse_durations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use bls::{Keypair, PublicKey, PublicKeyBytes, Signature};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use tree_hash::{SignedRoot, TreeHash};
+use types::chain_spec::ChainSpec;
+use types::helper_functions::compute_domain;
+use types::primitives::{Domain, Gwei, H256};
+use types::types::DepositData;
+
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    AmountTooLow(Gwei),
+    InvalidPublicKey,
+    InvalidSignature,
+    IOError(String),
+}
+
+/// Version of the eth2.0-deposit-cli whose `deposit_data.json` format is written, the launchpad
+/// refuses files of older versions.
+pub const DEPOSIT_CLI_VERSION: &str = "1.2.0";
+
+/// Network name written to `deposit_data.json`. The launchpad networks (mainnet, pyrmont, prater)
+/// follow spec v1.0, whose deposit domain mixes in the fork data root and whose signing roots are
+/// computed over `SigningData`. Deposits signed with the `compute_domain` and `signed_root` of this
+/// spec version are refused there, so no launchpad network is claimed.
+pub const ETH2_NETWORK_NAME: &str = "unknown";
+
+/// Entry in the format of the `deposit_data.json` file of the staking launchpad. Only chains
+/// following this spec version accept the deposits, see `ETH2_NETWORK_NAME`.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct LaunchpadDepositData {
+    pub pubkey: String,
+    pub withdrawal_credentials: String,
+    pub amount: Gwei,
+    pub signature: String,
+    pub deposit_message_root: String,
+    pub deposit_data_root: String,
+    pub fork_version: String,
+    pub eth2_network_name: String,
+    pub deposit_cli_version: String,
+}
+
+impl LaunchpadDepositData {
+    pub fn new(deposit_data: &DepositData, fork_version: [u8; 4]) -> LaunchpadDepositData {
+        LaunchpadDepositData {
+            pubkey: hex::encode(deposit_data.pubkey.as_bytes()),
+            withdrawal_credentials: hex::encode(deposit_data.withdrawal_credentials.as_bytes()),
+            amount: deposit_data.amount,
+            signature: hex::encode(deposit_data.signature.as_bytes()),
+            deposit_message_root: hex::encode(deposit_data.signed_root()),
+            deposit_data_root: hex::encode(deposit_data.tree_hash_root()),
+            fork_version: hex::encode(fork_version),
+            eth2_network_name: ETH2_NETWORK_NAME.to_owned(),
+            deposit_cli_version: DEPOSIT_CLI_VERSION.to_owned(),
+        }
+    }
+}
+
+/// Credentials letting the owner of `withdrawal_pubkey` withdraw the deposited funds.
+pub fn bls_withdrawal_credentials(spec: &ChainSpec, withdrawal_pubkey: &PublicKey) -> H256 {
+    H256::from_slice(&bls::get_withdrawal_credentials(
+        withdrawal_pubkey,
+        spec.bls_withdrawal_prefix,
+    ))
+}
+
+/// Deposit of `amount` for the validator `signing_keypair`, signed with its own key as a proof of
+/// possession.
+pub fn new_deposit_data(
+    spec: &ChainSpec,
+    signing_keypair: &Keypair,
+    withdrawal_credentials: H256,
+    amount: Gwei,
+) -> Result<DepositData, Error> {
+    if amount < spec.min_deposit_amount {
+        return Err(Error::AmountTooLow(amount));
+    }
+    let mut deposit_data = DepositData {
+        pubkey: PublicKeyBytes::from_bytes(&signing_keypair.pk.as_bytes())
+            .map_err(|_| Error::InvalidPublicKey)?,
+        withdrawal_credentials,
+        amount,
+        signature: Signature::empty_signature(),
+    };
+    deposit_data.signature = Signature::new(
+        &deposit_data.signed_root(),
+        deposit_domain(spec),
+        &signing_keypair.sk,
+    );
+    Ok(deposit_data)
+}
+
+pub fn verify_deposit_data(spec: &ChainSpec, deposit_data: &DepositData) -> Result<(), Error> {
+    let pubkey = PublicKey::from_bytes(deposit_data.pubkey.as_bytes().as_slice())
+        .map_err(|_| Error::InvalidPublicKey)?;
+    if deposit_data
+        .signature
+        .verify(&deposit_data.signed_root(), deposit_domain(spec), &pubkey)
+    {
+        Ok(())
+    } else {
+        Err(Error::InvalidSignature)
+    }
+}
+
+pub fn write_deposit_data_file(
+    spec: &ChainSpec,
+    path: &Path,
+    deposits: &[DepositData],
+) -> Result<(), Error> {
+    let entries: Vec<LaunchpadDepositData> = deposits
+        .iter()
+        .map(|deposit_data| LaunchpadDepositData::new(deposit_data, spec.genesis_fork_version))
+        .collect();
+    let file = File::create(path).map_err(|e| Error::IOError(e.to_string()))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &entries)
+        .map_err(|e| Error::IOError(e.to_string()))
+}
+
+/// Deposits are signed over the genesis fork, so they stay valid across forks.
+fn deposit_domain(spec: &ChainSpec) -> Domain {
+    compute_domain(spec.domain_deposit, spec.genesis_fork_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bls::SecretKey;
+    use types::config::MinimalConfig;
+
+    fn spec() -> ChainSpec {
+        ChainSpec::from_config::<MinimalConfig>()
+    }
+
+    fn keypair(byte: u8) -> Keypair {
+        let sk = SecretKey::from_bytes(&[byte; 48]).unwrap();
+        Keypair {
+            pk: PublicKey::from_secret_key(&sk),
+            sk,
+        }
+    }
+
+    #[test]
+    fn should_sign_verifiable_deposit() {
+        let signing_keypair = keypair(1);
+        let withdrawal_credentials = bls_withdrawal_credentials(&spec(), &keypair(2).pk);
+        assert_eq!(withdrawal_credentials.as_bytes()[0], 0);
+
+        let deposit_data = new_deposit_data(
+            &spec(),
+            &signing_keypair,
+            withdrawal_credentials,
+            32_000_000_000,
+        )
+        .unwrap();
+        assert_eq!(verify_deposit_data(&spec(), &deposit_data), Ok(()));
+
+        let mut tampered = deposit_data.clone();
+        tampered.amount = 1_000_000_000;
+        assert_eq!(
+            verify_deposit_data(&spec(), &tampered),
+            Err(Error::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn should_refuse_deposit_below_minimum() {
+        assert_eq!(
+            new_deposit_data(&spec(), &keypair(1), H256::zero(), 999_999_999),
+            Err(Error::AmountTooLow(999_999_999))
+        );
+    }
+
+    #[test]
+    fn should_convert_to_launchpad_format() {
+        let deposit_data =
+            new_deposit_data(&spec(), &keypair(1), H256::zero(), 32_000_000_000).unwrap();
+        let entry = LaunchpadDepositData::new(&deposit_data, [0, 0, 0, 1]);
+        assert_eq!(entry.pubkey, hex::encode(keypair(1).pk.as_bytes()));
+        assert_eq!(entry.withdrawal_credentials, hex::encode([0; 32]));
+        assert_eq!(entry.fork_version, "00000001");
+        assert_eq!(entry.eth2_network_name, ETH2_NETWORK_NAME);
+        assert_eq!(entry.deposit_cli_version, DEPOSIT_CLI_VERSION);
+        assert_eq!(
+            entry.deposit_data_root,
+            hex::encode(deposit_data.tree_hash_root())
+        );
+
+        let json = serde_json::to_value(&entry).unwrap();
+        assert_eq!(json["amount"], 32_000_000_000u64);
+        assert!(!entry.signature.starts_with("0x"));
+    }
+
+    #[test]
+    fn should_sign_over_genesis_fork_version_of_chain() {
+        let testnet = ChainSpec {
+            genesis_fork_version: [0, 0, 0, 1],
+            ..spec()
+        };
+        let deposit_data =
+            new_deposit_data(&testnet, &keypair(1), H256::zero(), 32_000_000_000).unwrap();
+
+        assert_eq!(verify_deposit_data(&testnet, &deposit_data), Ok(()));
+        assert_eq!(
+            verify_deposit_data(&spec(), &deposit_data),
+            Err(Error::InvalidSignature)
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use crate::beacon_node::DutyInfo;
+use bls::PublicKeyBytes;
+use std::collections::HashMap;
+use typenum::Unsigned;
+use types::beacon_state::{BeaconState, Error};
+use types::chain_spec::ChainSpec;
+use types::config::Config as EthConfig;
+use types::helper_functions::compute_start_slot_at_epoch;
+use types::primitives::{CommitteeIndex, Epoch, Slot, ValidatorIndex};
+
+/// Computes the duties of `pubkeys` at `epoch` from `state` alone, without asking the beacon node.
+///
+/// Proposers depend on effective balances, which may change at epoch boundaries, so block
+/// proposals are only filled in when `epoch` is the current epoch of `state`. Validators unknown to
+/// `state` get no duties.
+pub fn compute_duties<C: EthConfig>(
+    spec: &ChainSpec,
+    state: &BeaconState<C>,
+    pubkeys: &[PublicKeyBytes],
+    epoch: Epoch,
+) -> Result<Vec<DutyInfo>, Error> {
+    let start_slot = compute_start_slot_at_epoch::<C>(epoch);
+    let slots = start_slot..start_slot + C::SlotsPerEpoch::to_u64();
+
+    let mut attestations: HashMap<ValidatorIndex, (Slot, CommitteeIndex, u64)> = HashMap::new();
+    for slot in slots.clone() {
+        for committee_index in 0..state.get_committee_count_at_slot(slot, spec) {
+            let committee = state.get_beacon_committee(slot, committee_index, spec)?;
+            for (position, validator_index) in committee.into_iter().enumerate() {
+                attestations.insert(validator_index, (slot, committee_index, position as u64));
+            }
+        }
+    }
+
+    let mut proposals: HashMap<ValidatorIndex, Slot> = HashMap::new();
+    if epoch == state.get_current_epoch() {
+        for slot in slots {
+            let proposer = state.get_beacon_proposer_index_at_slot(slot, spec)?;
+            proposals.entry(proposer).or_insert(slot);
+        }
+    }
+
+    let mut duties = Vec::new();
+    for pubkey in pubkeys {
+        let validator_index = match state
+            .validators
+            .iter()
+            .position(|validator| validator.pubkey.as_bytes() == pubkey.as_bytes())
+        {
+            Some(index) => index as ValidatorIndex,
+            None => continue,
+        };
+        if let Some((slot, committee_index, position)) = attestations.get(&validator_index) {
+            duties.push(DutyInfo {
+                validator_pubkey: format!("0x{}", hex::encode(pubkey.as_bytes())),
+                attestation_slot: *slot,
+                attestation_committee_index: *committee_index,
+                attestation_committee_position: *position,
+                block_proposal_slot: proposals.get(&validator_index).copied(),
+            });
+        }
+    }
+    Ok(duties)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bls::{PublicKey, SecretKey};
+    use ssz_types::{FixedVector, VariableList};
+    use types::config::MinimalConfig;
+    use types::consts::FAR_FUTURE_EPOCH;
+    use types::helper_functions::compute_shuffled_index;
+    use types::primitives::H256;
+    use types::types::Validator;
+
+    fn spec() -> ChainSpec {
+        ChainSpec::from_config::<MinimalConfig>()
+    }
+
+    fn pubkey(index: u8) -> PublicKey {
+        PublicKey::from_secret_key(&SecretKey::from_bytes(&[index + 1; 48]).unwrap())
+    }
+
+    fn pubkey_bytes(index: u8) -> PublicKeyBytes {
+        PublicKeyBytes::from_bytes(&pubkey(index).as_bytes()).unwrap()
+    }
+
+    fn state(slot: Slot, validator_count: u8) -> BeaconState<MinimalConfig> {
+        let validators = (0..validator_count)
+            .map(|index| Validator {
+                pubkey: pubkey(index),
+                effective_balance: 32_000_000_000,
+                exit_epoch: FAR_FUTURE_EPOCH,
+                withdrawable_epoch: FAR_FUTURE_EPOCH,
+                ..Validator::default()
+            })
+            .collect();
+        let mixes = (0..64).map(H256::from_low_u64_le).collect();
+        BeaconState {
+            slot,
+            validators: VariableList::new(validators).unwrap(),
+            randao_mixes: FixedVector::new(mixes).unwrap(),
+            ..BeaconState::default()
+        }
+    }
+
+    #[test]
+    fn should_shuffle_into_permutation() {
+        let seed = [7; 32];
+        for count in 1..40 {
+            let mut shuffled: Vec<u64> = (0..count)
+                .map(|index| compute_shuffled_index(index, count, &seed, &spec()).unwrap())
+                .collect();
+            shuffled.sort();
+            assert_eq!(shuffled, (0..count).collect::<Vec<u64>>());
+        }
+        assert_eq!(compute_shuffled_index(5, 5, &seed, &spec()), None);
+    }
+
+    #[test]
+    fn should_assign_every_active_validator_to_one_committee_per_epoch() {
+        let state = state(8, 100);
+        let mut assigned = Vec::new();
+        for slot in 8..16 {
+            assert_eq!(state.get_committee_count_at_slot(slot, &spec()), 3);
+            for index in 0..3 {
+                assigned.extend(state.get_beacon_committee(slot, index, &spec()).unwrap());
+            }
+            assert_eq!(
+                state.get_beacon_committee(slot, 3, &spec()),
+                Err(Error::IndexOutOfRange)
+            );
+        }
+        assigned.sort();
+        assert_eq!(assigned, (0..100).collect::<Vec<ValidatorIndex>>());
+    }
+
+    #[test]
+    fn should_compute_duties_matching_committees() {
+        let state = state(8, 16);
+        let pubkeys: Vec<PublicKeyBytes> = (0..16).map(pubkey_bytes).collect();
+        let duties = compute_duties(&spec(), &state, &pubkeys, 1).unwrap();
+        assert_eq!(duties.len(), 16);
+
+        let mut proposals = 0;
+        for (index, duty) in duties.iter().enumerate() {
+            assert_eq!(
+                duty.validator_pubkey,
+                format!("0x{}", hex::encode(pubkeys[index].as_bytes()))
+            );
+            let committee = state
+                .get_beacon_committee(
+                    duty.attestation_slot,
+                    duty.attestation_committee_index,
+                    &spec(),
+                )
+                .unwrap();
+            assert_eq!(
+                committee[duty.attestation_committee_position as usize],
+                index as ValidatorIndex
+            );
+            if let Some(slot) = duty.block_proposal_slot {
+                assert_eq!(
+                    state.get_beacon_proposer_index_at_slot(slot, &spec()),
+                    Ok(index as ValidatorIndex)
+                );
+                proposals += 1;
+            }
+        }
+        assert!(proposals > 0);
+    }
+
+    #[test]
+    fn should_skip_proposals_outside_current_epoch() {
+        let state = state(8, 16);
+        let duties =
+            compute_duties(&spec(), &state, &[pubkey_bytes(0), pubkey_bytes(20)], 2).unwrap();
+        assert_eq!(duties.len(), 1);
+        assert_eq!(duties[0].block_proposal_slot, None);
+        assert_eq!(
+            state.get_beacon_proposer_index_at_slot(16, &spec()),
+            Err(Error::SlotOutOfRange)
+        );
+    }
+
+    #[test]
+    fn should_fail_without_randao_mixes() {
+        let state = BeaconState::<MinimalConfig> {
+            randao_mixes: FixedVector::default(),
+            ..state(0, 16)
+        };
+        assert_eq!(
+            compute_duties(&spec(), &state, &[pubkey_bytes(0)], 0),
+            Err(Error::IndexOutOfRange)
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use types::types::Eth1Data;
+
+/// Provider of the `Eth1Data` candidates validators vote for.
+///
+/// Distances are counted in blocks back from the head of the eth1 chain, so distance 0 is the head.
+pub trait Eth1DataSource: Send + Sync {
+    /// Deposit root, deposit count and block hash of the block `distance` blocks behind the head,
+    /// `None` if the chain is not that long.
+    fn get_eth1_data(&self, distance: u64) -> Option<Eth1Data>;
+
+    /// Distance of the block referenced by `eth1_data`, `None` if the block is unknown.
+    fn get_distance(&self, eth1_data: &Eth1Data) -> Option<u64>;
+}
+
+/// Eth1 chain held in memory, ordered from the oldest block to the head.
+#[derive(Default)]
+pub struct InMemoryEth1DataSource {
+    pub blocks: Vec<Eth1Data>,
+}
+
+impl InMemoryEth1DataSource {
+    pub fn new(blocks: Vec<Eth1Data>) -> InMemoryEth1DataSource {
+        InMemoryEth1DataSource { blocks }
+    }
+
+    /// Reads the chain from a JSON array of `Eth1Data`, oldest block first.
+    pub fn from_file(path: &Path) -> Result<InMemoryEth1DataSource, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let blocks = serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string())?;
+        Ok(InMemoryEth1DataSource::new(blocks))
+    }
+}
+
+impl Eth1DataSource for InMemoryEth1DataSource {
+    fn get_eth1_data(&self, distance: u64) -> Option<Eth1Data> {
+        let len = self.blocks.len() as u64;
+        if distance >= len {
+            return None;
+        }
+        self.blocks.get((len - 1 - distance) as usize).cloned()
+    }
+
+    fn get_distance(&self, eth1_data: &Eth1Data) -> Option<u64> {
+        self.blocks
+            .iter()
+            .rposition(|block| block.block_hash == eth1_data.block_hash)
+            .map(|index| (self.blocks.len() - 1 - index) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::primitives::H256;
+
+    const CHAIN: &str = r#"
+        [
+          {
+            "deposit_root": "0x0101010101010101010101010101010101010101010101010101010101010101",
+            "deposit_count": 1,
+            "block_hash": "0x0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a0a"
+          },
+          {
+            "deposit_root": "0x0202020202020202020202020202020202020202020202020202020202020202",
+            "deposit_count": 2,
+            "block_hash": "0x0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b"
+          }
+        ]"#;
+
+    #[test]
+    fn should_index_blocks_by_distance_from_head() {
+        let source = InMemoryEth1DataSource::new(serde_json::from_str(CHAIN).unwrap());
+        let head = source.get_eth1_data(0).unwrap();
+        assert_eq!(head.deposit_count, 2);
+        assert_eq!(source.get_eth1_data(1).unwrap().deposit_count, 1);
+        assert_eq!(source.get_eth1_data(2), None);
+        assert_eq!(source.get_distance(&head), Some(0));
+        let unknown = Eth1Data {
+            block_hash: H256::from_slice(&[0xff; 32]),
+            ..Eth1Data::default()
+        };
+        assert_eq!(source.get_distance(&unknown), None);
+    }
+}
@@ -0,0 +1,306 @@
+use crate::beacon_node::{BeaconNode, DutyInfo, Error};
+use crate::rest_client::CircuitState;
+use async_trait::async_trait;
+use bls::{PublicKeyBytes, Signature};
+use futures::future::{self, BoxFuture};
+use log::warn;
+use std::sync::Mutex;
+use types::beacon_state::BeaconState;
+use types::config::Config as EthConfig;
+use types::primitives::{Epoch, Slot};
+use types::types::{Attestation, BeaconBlock, SignedBeaconBlock, VoluntaryExit};
+
+/// State of a beacon node as of the last health check or request, best first.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Health {
+    Synced,
+    Syncing,
+    Unreachable,
+}
+
+/// Beacon node backed by several redundant beacon nodes. Queries go to the healthiest node that
+/// answers them, while blocks, attestations and exits are published to every reachable node.
+pub struct FallbackBeaconNode<B> {
+    beacon_nodes: Vec<B>,
+    health: Mutex<Vec<Health>>,
+}
+
+impl<B> FallbackBeaconNode<B> {
+    /// Beacon nodes of the same health are preferred in the given order.
+    pub fn new(beacon_nodes: Vec<B>) -> FallbackBeaconNode<B> {
+        let health = Mutex::new(vec![Health::Synced; beacon_nodes.len()]);
+        FallbackBeaconNode {
+            beacon_nodes,
+            health,
+        }
+    }
+
+    pub fn health(&self) -> Vec<Health> {
+        self.health
+            .lock()
+            .expect("health lock is not poisoned")
+            .clone()
+    }
+
+    /// Asks every beacon node whether it is synced.
+    pub async fn check_health<C: EthConfig>(&self)
+    where
+        B: BeaconNode<C>,
+    {
+        let responses = future::join_all(
+            self.beacon_nodes
+                .iter()
+                .map(|beacon_node| beacon_node.is_syncing()),
+        )
+        .await;
+        let health = responses
+            .into_iter()
+            .map(|response| match response {
+                Ok(false) => Health::Synced,
+                Ok(true) => Health::Syncing,
+                Err(_) => Health::Unreachable,
+            })
+            .collect();
+        *self.health.lock().expect("health lock is not poisoned") = health;
+    }
+
+    /// Indices of the beacon nodes, healthiest first.
+    fn preferred_order(&self) -> Vec<usize> {
+        let health = self.health();
+        let mut order: Vec<usize> = (0..self.beacon_nodes.len()).collect();
+        order.sort_by_key(|&index| health[index]);
+        order
+    }
+
+    fn record_failure(&self, index: usize, error: &Error) {
+        warn!("Beacon node {} failed: {:?}", index, error);
+        if error.is_retryable() {
+            self.health.lock().expect("health lock is not poisoned")[index] = Health::Unreachable;
+        }
+    }
+
+    /// Result of the first beacon node that answers `request` successfully, or the error of the
+    /// last one if none does.
+    async fn first_success<'a, T, F>(&'a self, request: F) -> Result<T, Error>
+    where
+        F: Fn(&'a B) -> BoxFuture<'a, Result<T, Error>>,
+    {
+        let mut last_error = Error::NoBeaconNodes;
+        for index in self.preferred_order() {
+            match request(&self.beacon_nodes[index]).await {
+                Ok(result) => return Ok(result),
+                Err(e) => {
+                    self.record_failure(index, &e);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    /// Sends `request` to every beacon node that is not known to be unreachable, or to all of them
+    /// if none is reachable, at the same time. Succeeds if any beacon node accepts it.
+    async fn broadcast<'a, F>(&'a self, request: F) -> Result<(), Error>
+    where
+        F: Fn(&'a B) -> BoxFuture<'a, Result<(), Error>>,
+    {
+        let health = self.health();
+        let reachable: Vec<usize> = (0..self.beacon_nodes.len())
+            .filter(|&index| health[index] != Health::Unreachable)
+            .collect();
+        let targets = if reachable.is_empty() {
+            (0..self.beacon_nodes.len()).collect()
+        } else {
+            reachable
+        };
+
+        let results = future::join_all(
+            targets
+                .iter()
+                .map(|&index| request(&self.beacon_nodes[index])),
+        )
+        .await;
+        let mut result = Err(Error::NoBeaconNodes);
+        for (index, response) in targets.into_iter().zip(results) {
+            match response {
+                Ok(()) => result = Ok(()),
+                Err(e) => {
+                    self.record_failure(index, &e);
+                    if result.is_err() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl<C: EthConfig, B: BeaconNode<C>> BeaconNode<C> for FallbackBeaconNode<B> {
+    /// Checks the health of the beacon nodes first, the state is fetched once per epoch.
+    async fn get_state(&self) -> Result<BeaconState<C>, Error> {
+        self.check_health().await;
+        self.first_success(|beacon_node| beacon_node.get_state())
+            .await
+    }
+
+    async fn is_syncing(&self) -> Result<bool, Error> {
+        self.first_success(|beacon_node| beacon_node.is_syncing())
+            .await
+    }
+
+    async fn get_duties(
+        &self,
+        validators: Vec<PublicKeyBytes>,
+        epoch: Epoch,
+    ) -> Result<Vec<DutyInfo>, Error> {
+        self.first_success(|beacon_node| beacon_node.get_duties(validators.clone(), epoch))
+            .await
+    }
+
+    async fn publish_attestation(&self, attestation: Attestation<C>) -> Result<(), Error> {
+        self.broadcast(|beacon_node| beacon_node.publish_attestation(attestation.clone()))
+            .await
+    }
+
+    async fn get_block(
+        &self,
+        slot: Slot,
+        randao_reveal: &Signature,
+    ) -> Result<BeaconBlock<C>, Error> {
+        self.first_success(|beacon_node| beacon_node.get_block(slot, randao_reveal))
+            .await
+    }
+
+    async fn publish_block(&self, block: SignedBeaconBlock<C>) -> Result<(), Error> {
+        self.broadcast(|beacon_node| beacon_node.publish_block(block.clone()))
+            .await
+    }
+
+    async fn publish_voluntary_exit(&self, exit: VoluntaryExit) -> Result<(), Error> {
+        self.broadcast(|beacon_node| beacon_node.publish_voluntary_exit(exit.clone()))
+            .await
+    }
+
+    /// Open only if the circuits of all beacon nodes are open.
+    fn circuit_state(&self) -> CircuitState {
+        let states: Vec<CircuitState> = self
+            .beacon_nodes
+            .iter()
+            .map(|beacon_node| beacon_node.circuit_state())
+            .collect();
+        if states.contains(&CircuitState::Closed) {
+            CircuitState::Closed
+        } else if states.contains(&CircuitState::HalfOpen) {
+            CircuitState::HalfOpen
+        } else {
+            CircuitState::Open
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beacon_node::BasicBeaconNode;
+    use crate::beacon_node_simulator::BeaconNodeSimulator;
+    use crate::mock_beacon_node::MockBeaconNode;
+    use crate::rest_client::{RestClient, RetryPolicy};
+    use types::config::MinimalConfig;
+    use types::genesis::interop_genesis_state;
+
+    fn unreachable_beacon_node() -> BasicBeaconNode<MinimalConfig> {
+        let rest_client = RestClient::new(String::from("http://127.0.0.1:1"))
+            .unwrap()
+            .with_retry_policy(RetryPolicy::no_retries());
+        BasicBeaconNode::from_rest_client(rest_client)
+    }
+
+    fn state_at_slot(slot: Slot) -> BeaconState<MinimalConfig> {
+        BeaconState {
+            slot,
+            ..BeaconState::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn should_fail_over_to_reachable_node() {
+        let genesis_state = interop_genesis_state::<MinimalConfig>(64, 0).unwrap();
+        let simulator =
+            BeaconNodeSimulator::start(genesis_state, &([127, 0, 0, 1], 0).into()).unwrap();
+        let beacon_node = FallbackBeaconNode::new(vec![
+            unreachable_beacon_node(),
+            BasicBeaconNode::new(simulator.url()),
+        ]);
+
+        let state = beacon_node.get_state().await.unwrap();
+
+        assert_eq!(&state, simulator.chain.lock().unwrap().head_state());
+        assert_eq!(
+            beacon_node.health(),
+            vec![Health::Unreachable, Health::Synced]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_report_error_of_last_node_if_all_fail() {
+        let beacon_node =
+            FallbackBeaconNode::new(vec![unreachable_beacon_node(), unreachable_beacon_node()]);
+
+        match beacon_node.get_state().await {
+            Err(Error::Request(e)) => assert!(e.is_retryable()),
+            other => panic!("expected a request error, got {:?}", other),
+        }
+        assert_eq!(
+            FallbackBeaconNode::<MockBeaconNode<MinimalConfig>>::new(vec![])
+                .get_state()
+                .await,
+            Err(Error::NoBeaconNodes)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_prefer_synced_node() {
+        let beacon_node = FallbackBeaconNode::new(vec![
+            MockBeaconNode::new(state_at_slot(1)).with_syncing(true),
+            MockBeaconNode::new(state_at_slot(2)),
+        ]);
+
+        let state = beacon_node.get_state().await.unwrap();
+
+        assert_eq!(state.slot, 2);
+        assert_eq!(beacon_node.health(), vec![Health::Syncing, Health::Synced]);
+    }
+
+    #[tokio::test]
+    async fn should_fall_back_to_syncing_node() {
+        let beacon_node = FallbackBeaconNode::new(vec![
+            MockBeaconNode::new(state_at_slot(1)).with_syncing(true),
+            MockBeaconNode::default(),
+        ]);
+
+        let state = beacon_node.get_state().await.unwrap();
+
+        assert_eq!(state.slot, 1);
+    }
+
+    #[tokio::test]
+    async fn should_broadcast_to_all_nodes() {
+        let beacon_node = FallbackBeaconNode::new(vec![
+            MockBeaconNode::<MinimalConfig>::default(),
+            MockBeaconNode::default().with_syncing(true),
+        ]);
+        let exit = VoluntaryExit {
+            epoch: 1,
+            validator_index: 2,
+            signature: Signature::empty_signature(),
+        };
+
+        beacon_node.publish_voluntary_exit(exit).await.unwrap();
+
+        for mock in beacon_node.beacon_nodes.iter() {
+            assert_eq!(mock.published_voluntary_exits.lock().unwrap().len(), 1);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Hierarchical derivation of BLS keys from a BIP-39 mnemonic, see
+//! <https://eips.ethereum.org/EIPS/eip-2333> and <https://eips.ethereum.org/EIPS/eip-2334>.
+
+use bip39::{Language, Mnemonic, MnemonicType, Seed};
+use bls::{Keypair, PublicKey, SecretKey};
+use hkdf::Hkdf;
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256};
+use ssz::Encode;
+
+pub const PURPOSE: u32 = 12381;
+pub const COIN_TYPE: u32 = 3600;
+
+const MIN_SEED_LENGTH: usize = 32;
+const LAMPORT_CHUNKS: usize = 255;
+const HKDF_MOD_R_L: usize = 48;
+const KEYGEN_SALT: &[u8] = b"BLS-SIG-KEYGEN-SALT-";
+/// Order of the BLS12-381 curve.
+const CURVE_ORDER: &str =
+    "52435875175126190479447740508185965837690552500527637822603658699938581184513";
+
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    SeedTooShort(usize),
+    InvalidPath(String),
+    InvalidMnemonic(String),
+    InvalidSecretKey(String),
+}
+
+/// Path of the signing key of validator `index`.
+pub fn signing_key_path(index: u32) -> String {
+    format!("m/{}/{}/{}/0/0", PURPOSE, COIN_TYPE, index)
+}
+
+/// Path of the withdrawal key of validator `index`, the parent of its signing key.
+pub fn withdrawal_key_path(index: u32) -> String {
+    format!("m/{}/{}/{}/0", PURPOSE, COIN_TYPE, index)
+}
+
+/// Fresh 24 word English mnemonic.
+pub fn new_mnemonic() -> String {
+    Mnemonic::new(MnemonicType::Words24, Language::English).into_phrase()
+}
+
+pub fn mnemonic_to_seed(phrase: &str, password: &str) -> Result<Vec<u8>, Error> {
+    let mnemonic = Mnemonic::from_phrase(phrase.trim(), Language::English)
+        .map_err(|e| Error::InvalidMnemonic(e.to_string()))?;
+    Ok(Seed::new(&mnemonic, password).as_bytes().to_vec())
+}
+
+pub fn derive_master_sk(seed: &[u8]) -> Result<SecretKey, Error> {
+    to_secret_key(&master_sk(seed)?)
+}
+
+pub fn derive_child_sk(parent_sk: &SecretKey, index: u32) -> Result<SecretKey, Error> {
+    let parent = BigUint::from_bytes_be(&parent_sk.as_ssz_bytes());
+    to_secret_key(&child_sk(&parent, index))
+}
+
+/// Walks `path` (e.g. `m/12381/3600/0/0/0`) down from the master key of `seed`.
+pub fn derive_keypair(seed: &[u8], path: &str) -> Result<Keypair, Error> {
+    let sk = parse_path(path)?
+        .into_iter()
+        .try_fold(master_sk(seed)?, |sk, index| Ok(child_sk(&sk, index)))
+        .and_then(|sk| to_secret_key(&sk))?;
+    let pk = PublicKey::from_secret_key(&sk);
+    Ok(Keypair { sk, pk })
+}
+
+fn parse_path(path: &str) -> Result<Vec<u32>, Error> {
+    let mut nodes = path.split('/');
+    if nodes.next() != Some("m") {
+        return Err(Error::InvalidPath(String::from(path)));
+    }
+    nodes
+        .map(|node| {
+            node.parse::<u32>()
+                .map_err(|_| Error::InvalidPath(String::from(path)))
+        })
+        .collect()
+}
+
+fn master_sk(seed: &[u8]) -> Result<BigUint, Error> {
+    if seed.len() < MIN_SEED_LENGTH {
+        return Err(Error::SeedTooShort(seed.len()));
+    }
+    Ok(hkdf_mod_r(seed))
+}
+
+fn child_sk(parent_sk: &BigUint, index: u32) -> BigUint {
+    hkdf_mod_r(&parent_sk_to_lamport_pk(parent_sk, index))
+}
+
+fn hkdf_mod_r(ikm: &[u8]) -> BigUint {
+    let curve_order = CURVE_ORDER.parse::<BigUint>().unwrap();
+    let mut ikm = ikm.to_vec();
+    ikm.push(0);
+    let mut info = Vec::new();
+    info.extend_from_slice(&(HKDF_MOD_R_L as u16).to_be_bytes());
+
+    let mut salt = KEYGEN_SALT.to_vec();
+    loop {
+        salt = Sha256::digest(&salt).to_vec();
+        let mut okm = [0; HKDF_MOD_R_L];
+        Hkdf::<Sha256>::new(Some(&salt), &ikm)
+            .expand(&info, &mut okm)
+            .expect("48 bytes is a valid output length");
+        let sk = BigUint::from_bytes_be(&okm) % &curve_order;
+        if sk != BigUint::from(0u32) {
+            return sk;
+        }
+    }
+}
+
+fn parent_sk_to_lamport_pk(parent_sk: &BigUint, index: u32) -> Vec<u8> {
+    let salt = index.to_be_bytes();
+    let ikm = to_bytes32(parent_sk);
+    let not_ikm: Vec<u8> = ikm.iter().map(|byte| !byte).collect();
+
+    let mut lamport_pk = Vec::with_capacity(2 * LAMPORT_CHUNKS * 32);
+    for lamport_sk in [
+        ikm_to_lamport_sk(&ikm, &salt),
+        ikm_to_lamport_sk(&not_ikm, &salt),
+    ]
+    .iter()
+    {
+        for chunk in lamport_sk.chunks(32) {
+            lamport_pk.extend_from_slice(&Sha256::digest(chunk));
+        }
+    }
+    Sha256::digest(&lamport_pk).to_vec()
+}
+
+fn ikm_to_lamport_sk(ikm: &[u8], salt: &[u8]) -> Vec<u8> {
+    let mut okm = vec![0; LAMPORT_CHUNKS * 32];
+    Hkdf::<Sha256>::new(Some(salt), ikm)
+        .expand(&[], &mut okm)
+        .expect("255 chunks is the maximum HKDF output length");
+    okm
+}
+
+fn to_bytes32(value: &BigUint) -> Vec<u8> {
+    let bytes = value.to_bytes_be();
+    let mut padded = vec![0; 32 - bytes.len()];
+    padded.extend_from_slice(&bytes);
+    padded
+}
+
+fn to_secret_key(value: &BigUint) -> Result<SecretKey, Error> {
+    let mut bytes = vec![0; 16];
+    bytes.extend_from_slice(&to_bytes32(value));
+    SecretKey::from_bytes(&bytes).map_err(|e| Error::InvalidSecretKey(format!("{:?}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+    const SEED: &str = "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04";
+
+    fn big(value: &str) -> BigUint {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn should_derive_seed_from_mnemonic() {
+        let seed = mnemonic_to_seed(MNEMONIC, "TREZOR").unwrap();
+        assert_eq!(hex::encode(seed), SEED);
+        assert!(mnemonic_to_seed("abandon about", "").is_err());
+    }
+
+    #[test]
+    fn should_derive_eip2333_test_case_0() {
+        let seed = hex::decode(SEED).unwrap();
+        let master = master_sk(&seed).unwrap();
+        assert_eq!(
+            master,
+            big("6083874454709270928345386274498605044986640685124978867557563392430687146096")
+        );
+        assert_eq!(
+            child_sk(&master, 0),
+            big("20397789859736650942317412262472558107875392172444076792671091975210932703118")
+        );
+    }
+
+    #[test]
+    fn should_derive_eip2333_test_case_1() {
+        let seed = hex::decode("3141592653589793238462643383279502884197169399375105820974944592")
+            .unwrap();
+        let master = master_sk(&seed).unwrap();
+        assert_eq!(
+            master,
+            big("29757020647961307431480504535336562678282505419141012933316116377660817309383")
+        );
+        assert_eq!(
+            child_sk(&master, 3_141_592_653),
+            big("25457201688850691947727629385191704516744796114925897962676248250929345014287")
+        );
+    }
+
+    #[test]
+    fn should_derive_keys_along_path() {
+        let seed = hex::decode(SEED).unwrap();
+        let master = derive_master_sk(&seed).unwrap();
+        let child = derive_child_sk(&master, 0).unwrap();
+        assert_eq!(derive_keypair(&seed, "m/0").unwrap().sk, child);
+        assert_eq!(derive_keypair(&seed, "m").unwrap().sk, master);
+        assert_eq!(signing_key_path(7), "m/12381/3600/7/0/0");
+        assert_eq!(withdrawal_key_path(7), "m/12381/3600/7/0");
+    }
+
+    #[test]
+    fn should_reject_invalid_input() {
+        assert_eq!(
+            derive_master_sk(&[0; 31]).unwrap_err(),
+            Error::SeedTooShort(31)
+        );
+        let seed = hex::decode(SEED).unwrap();
+        assert!(derive_keypair(&seed, "12381/3600").is_err());
+        assert!(derive_keypair(&seed, "m/12381/x").is_err());
+    }
+}
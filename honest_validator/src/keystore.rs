@@ -0,0 +1,562 @@
+//! Encrypted BLS keystores, see <https://eips.ethereum.org/EIPS/eip-2335>.
+
+use aes_ctr::cipher::stream::{NewStreamCipher, SyncStreamCipher};
+use aes_ctr::Aes128Ctr;
+use bls::{Keypair, PublicKey, SecretKey};
+use hmac::Hmac;
+use log::info;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ssz::Encode;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use subtle::ConstantTimeEq;
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
+
+pub const KEYSTORE_VERSION: u32 = 4;
+
+const DECRYPTION_KEY_LENGTH: u32 = 32;
+const SECRET_KEY_LENGTH: usize = 32;
+const SALT_LENGTH: usize = 32;
+const IV_LENGTH: usize = 16;
+
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    IncorrectPassword,
+    PublicKeyMismatch,
+    UnsupportedVersion(u32),
+    KdfMismatch,
+    InvalidKdfParams,
+    InvalidIvLength(usize),
+    InvalidSecretKey(String),
+    InvalidJson(String),
+    IOError(String),
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keystore {
+    pub crypto: Crypto,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub pubkey: String,
+    pub path: String,
+    pub uuid: Uuid,
+    pub version: u32,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Crypto {
+    pub kdf: KdfModule,
+    pub checksum: ChecksumModule,
+    pub cipher: CipherModule,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KdfModule {
+    pub function: KdfFunction,
+    pub params: Kdf,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KdfFunction {
+    Scrypt,
+    Pbkdf2,
+}
+
+/// Parameters of the key derivation function, told apart by their fields.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Kdf {
+    Scrypt(ScryptParams),
+    Pbkdf2(Pbkdf2Params),
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScryptParams {
+    pub dklen: u32,
+    pub n: u32,
+    pub p: u32,
+    pub r: u32,
+    #[serde(with = "hex_bytes")]
+    pub salt: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pbkdf2Params {
+    pub dklen: u32,
+    pub c: u32,
+    pub prf: Prf,
+    #[serde(with = "hex_bytes")]
+    pub salt: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum Prf {
+    #[serde(rename = "hmac-sha256")]
+    HmacSha256,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChecksumModule {
+    pub function: ChecksumFunction,
+    pub params: EmptyParams,
+    #[serde(with = "hex_bytes")]
+    pub message: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum ChecksumFunction {
+    #[serde(rename = "sha256")]
+    Sha256,
+}
+
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmptyParams {}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CipherModule {
+    pub function: CipherFunction,
+    pub params: CipherParams,
+    #[serde(with = "hex_bytes")]
+    pub message: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum CipherFunction {
+    #[serde(rename = "aes-128-ctr")]
+    Aes128Ctr,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CipherParams {
+    #[serde(with = "hex_bytes")]
+    pub iv: Vec<u8>,
+}
+
+impl Kdf {
+    /// Scrypt with the parameters recommended by EIP-2335 and a random salt.
+    pub fn scrypt() -> Kdf {
+        Kdf::Scrypt(ScryptParams {
+            dklen: DECRYPTION_KEY_LENGTH,
+            n: 262_144,
+            p: 1,
+            r: 8,
+            salt: random_bytes(SALT_LENGTH),
+        })
+    }
+
+    /// PBKDF2 with the parameters recommended by EIP-2335 and a random salt.
+    pub fn pbkdf2() -> Kdf {
+        Kdf::Pbkdf2(Pbkdf2Params {
+            dklen: DECRYPTION_KEY_LENGTH,
+            c: 262_144,
+            prf: Prf::HmacSha256,
+            salt: random_bytes(SALT_LENGTH),
+        })
+    }
+
+    pub fn function(&self) -> KdfFunction {
+        match self {
+            Kdf::Scrypt(_) => KdfFunction::Scrypt,
+            Kdf::Pbkdf2(_) => KdfFunction::Pbkdf2,
+        }
+    }
+
+    fn derive_key(&self, password: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Kdf::Scrypt(params) => {
+                if params.dklen < DECRYPTION_KEY_LENGTH || !params.n.is_power_of_two() {
+                    return Err(Error::InvalidKdfParams);
+                }
+                let log_n = params.n.trailing_zeros() as u8;
+                let scrypt_params = scrypt::ScryptParams::new(log_n, params.r, params.p)
+                    .map_err(|_| Error::InvalidKdfParams)?;
+                let mut key = vec![0; params.dklen as usize];
+                scrypt::scrypt(password, &params.salt, &scrypt_params, &mut key)
+                    .map_err(|_| Error::InvalidKdfParams)?;
+                Ok(key)
+            }
+            Kdf::Pbkdf2(params) => {
+                if params.dklen < DECRYPTION_KEY_LENGTH || params.c == 0 {
+                    return Err(Error::InvalidKdfParams);
+                }
+                let mut key = vec![0; params.dklen as usize];
+                pbkdf2::pbkdf2::<Hmac<Sha256>>(password, &params.salt, params.c, &mut key);
+                Ok(key)
+            }
+        }
+    }
+}
+
+impl Keystore {
+    /// Encrypts the secret key of `keypair` with `password`, recording its derivation `path`.
+    pub fn encrypt(
+        keypair: &Keypair,
+        password: &str,
+        path: &str,
+        kdf: Kdf,
+    ) -> Result<Keystore, Error> {
+        let decryption_key = kdf.derive_key(&process_password(password))?;
+        let iv = random_bytes(IV_LENGTH);
+        let mut cipher_message = secret_key_to_bytes(&keypair.sk);
+        aes_128_ctr(&decryption_key[..16], &iv, &mut cipher_message)?;
+        let checksum = checksum(&decryption_key, &cipher_message);
+
+        Ok(Keystore {
+            crypto: Crypto {
+                kdf: KdfModule {
+                    function: kdf.function(),
+                    params: kdf,
+                    message: String::new(),
+                },
+                checksum: ChecksumModule {
+                    function: ChecksumFunction::Sha256,
+                    params: EmptyParams::default(),
+                    message: checksum,
+                },
+                cipher: CipherModule {
+                    function: CipherFunction::Aes128Ctr,
+                    params: CipherParams { iv },
+                    message: cipher_message,
+                },
+            },
+            description: None,
+            pubkey: hex::encode(keypair.pk.as_bytes()),
+            path: String::from(path),
+            uuid: Uuid::new_v4(),
+            version: KEYSTORE_VERSION,
+        })
+    }
+
+    /// Raw 32 byte secret stored in the keystore, after verifying the password against the checksum.
+    pub fn decrypt_secret(&self, password: &str) -> Result<Vec<u8>, Error> {
+        if self.version != KEYSTORE_VERSION {
+            return Err(Error::UnsupportedVersion(self.version));
+        }
+        if self.crypto.kdf.function != self.crypto.kdf.params.function() {
+            return Err(Error::KdfMismatch);
+        }
+        let decryption_key = self
+            .crypto
+            .kdf
+            .params
+            .derive_key(&process_password(password))?;
+        // Compared in constant time, so the time taken does not tell how much of it matched.
+        let checksum = checksum(&decryption_key, &self.crypto.cipher.message);
+        if !bool::from(checksum.ct_eq(&self.crypto.checksum.message)) {
+            return Err(Error::IncorrectPassword);
+        }
+        let mut secret = self.crypto.cipher.message.clone();
+        aes_128_ctr(
+            &decryption_key[..16],
+            &self.crypto.cipher.params.iv,
+            &mut secret,
+        )?;
+        Ok(secret)
+    }
+
+    pub fn decrypt_keypair(&self, password: &str) -> Result<Keypair, Error> {
+        let sk = secret_key_from_bytes(&self.decrypt_secret(password)?)?;
+        let pk = PublicKey::from_secret_key(&sk);
+        if hex::encode(pk.as_bytes()) != self.pubkey.trim_start_matches("0x").to_lowercase() {
+            return Err(Error::PublicKeyMismatch);
+        }
+        Ok(Keypair { sk, pk })
+    }
+
+    pub fn from_file(path: &Path) -> Result<Keystore, Error> {
+        let file = File::open(path).map_err(|e| io_error(path, e))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| Error::InvalidJson(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn to_file(&self, path: &Path) -> Result<(), Error> {
+        let file = File::create(path).map_err(|e| io_error(path, e))?;
+        serde_json::to_writer_pretty(file, self).map_err(|e| Error::InvalidJson(e.to_string()))
+    }
+}
+
+/// Decrypts every `*.json` keystore in `keystores_dir`, in file name order. The password of each
+/// keystore is read from the file in `secrets_dir` named after its 0x-prefixed public key.
+pub fn load_keypairs(keystores_dir: &Path, secrets_dir: &Path) -> Result<Vec<Keypair>, Error> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(keystores_dir).map_err(|e| io_error(keystores_dir, e))? {
+        let path = entry.map_err(|e| io_error(keystores_dir, e))?.path();
+        if path.is_file()
+            && path.extension().and_then(|extension| extension.to_str()) == Some("json")
+        {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut keypairs = Vec::with_capacity(paths.len());
+    for path in paths {
+        let keystore = Keystore::from_file(&path)?;
+        let password_path =
+            secrets_dir.join(format!("0x{}", keystore.pubkey.trim_start_matches("0x")));
+        let password =
+            fs::read_to_string(&password_path).map_err(|e| io_error(&password_path, e))?;
+        let keypair = keystore.decrypt_keypair(password.trim_end_matches(&['\r', '\n'][..]))?;
+        info!("Loaded keystore {}", path.display());
+        keypairs.push(keypair);
+    }
+    Ok(keypairs)
+}
+
+/// Passwords are NFKD normalized and stripped of control codes before being fed to the KDF.
+fn process_password(password: &str) -> Vec<u8> {
+    password
+        .nfkd()
+        .filter(|c| !is_control_code(*c))
+        .collect::<String>()
+        .into_bytes()
+}
+
+fn is_control_code(c: char) -> bool {
+    let code = c as u32;
+    code <= 0x1f || (0x7f..=0x9f).contains(&code)
+}
+
+fn checksum(decryption_key: &[u8], cipher_message: &[u8]) -> Vec<u8> {
+    let mut pre_image = decryption_key[16..32].to_vec();
+    pre_image.extend_from_slice(cipher_message);
+    Sha256::digest(&pre_image).to_vec()
+}
+
+fn aes_128_ctr(key: &[u8], iv: &[u8], message: &mut [u8]) -> Result<(), Error> {
+    let mut cipher = Aes128Ctr::new_var(key, iv).map_err(|_| Error::InvalidIvLength(iv.len()))?;
+    cipher.apply_keystream(message);
+    Ok(())
+}
+
+fn secret_key_from_bytes(secret: &[u8]) -> Result<SecretKey, Error> {
+    if secret.len() != SECRET_KEY_LENGTH {
+        return Err(Error::InvalidSecretKey(format!(
+            "expected {} bytes, got {}",
+            SECRET_KEY_LENGTH,
+            secret.len()
+        )));
+    }
+    let mut bytes = vec![0; 48 - SECRET_KEY_LENGTH];
+    bytes.extend_from_slice(secret);
+    SecretKey::from_bytes(&bytes).map_err(|e| Error::InvalidSecretKey(format!("{:?}", e)))
+}
+
+fn secret_key_to_bytes(secret_key: &SecretKey) -> Vec<u8> {
+    let bytes = secret_key.as_ssz_bytes();
+    bytes[bytes.len() - SECRET_KEY_LENGTH..].to_vec()
+}
+
+fn random_bytes(length: usize) -> Vec<u8> {
+    let mut bytes = vec![0; length];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+fn io_error(path: &Path, error: std::io::Error) -> Error {
+    Error::IOError(format!("{}: {}", path.display(), error))
+}
+
+/// EIP-2335 encodes bytes as hex strings without the 0x prefix.
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let string = String::deserialize(deserializer)?;
+        hex::decode(&string).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &str = "\u{1d531}\u{1d522}\u{1d530}\u{1d531}\u{1d52d}\u{1d51e}\u{1d530}\u{1d530}\u{1d534}\u{1d52c}\u{1d52f}\u{1d521}\u{1f511}";
+    const SECRET: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+
+    const PBKDF2_KEYSTORE: &str = r#"
+        {
+          "crypto": {
+            "kdf": {
+              "function": "pbkdf2",
+              "params": {
+                "dklen": 32,
+                "c": 262144,
+                "prf": "hmac-sha256",
+                "salt": "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3"
+              },
+              "message": ""
+            },
+            "checksum": {
+              "function": "sha256",
+              "params": {},
+              "message": "8a9f5d9912ed7e75ea794bc5a89bca5f193721d30868ade6f73043c6ea6febf1"
+            },
+            "cipher": {
+              "function": "aes-128-ctr",
+              "params": {
+                "iv": "264daa3f303d7259501c93d997d84fe6"
+              },
+              "message": "cee03fde2af33149775b7223e7845e4fb2c8ae1792e5f99fe9ecf474cc8c16ad"
+            }
+          },
+          "description": "This is a test keystore that uses PBKDF2 to secure the secret.",
+          "pubkey": "9612d7a727c9d0a22e185a1c768478dfe919cada9266988cb32359c11f2b7b27f4ae4040902382ae2910c15e2b420d07",
+          "path": "m/12381/60/0/0",
+          "uuid": "64625def-3331-4eea-ab6f-782f3ed16a83",
+          "version": 4
+        }"#;
+
+    const SCRYPT_KEYSTORE: &str = r#"
+        {
+          "crypto": {
+            "kdf": {
+              "function": "scrypt",
+              "params": {
+                "dklen": 32,
+                "n": 262144,
+                "p": 1,
+                "r": 8,
+                "salt": "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3"
+              },
+              "message": ""
+            },
+            "checksum": {
+              "function": "sha256",
+              "params": {},
+              "message": "d2217fe5f3e9a1e34581ef8a78f7c9928e436d36dacc5e846690a5581e8ea484"
+            },
+            "cipher": {
+              "function": "aes-128-ctr",
+              "params": {
+                "iv": "264daa3f303d7259501c93d997d84fe6"
+              },
+              "message": "06ae90d55fe0a6e9c5c3bc5b170827b2e5cce3929ed3f116c2811e6366dfe20f"
+            }
+          },
+          "description": "This is a test keystore that uses scrypt to secure the secret.",
+          "pubkey": "9612d7a727c9d0a22e185a1c768478dfe919cada9266988cb32359c11f2b7b27f4ae4040902382ae2910c15e2b420d07",
+          "path": "m/12381/60/3141592653/589793238",
+          "uuid": "1d85ae20-35c5-4611-98e8-aa14a633906f",
+          "version": 4
+        }"#;
+
+    fn cheap_scrypt() -> Kdf {
+        Kdf::Scrypt(ScryptParams {
+            dklen: 32,
+            n: 16,
+            p: 1,
+            r: 8,
+            salt: random_bytes(SALT_LENGTH),
+        })
+    }
+
+    #[test]
+    fn should_decrypt_pbkdf2_test_vector() {
+        let keystore: Keystore = serde_json::from_str(PBKDF2_KEYSTORE).unwrap();
+        assert_eq!(keystore.crypto.kdf.function, KdfFunction::Pbkdf2);
+        assert_eq!(
+            hex::encode(keystore.decrypt_secret(PASSWORD).unwrap()),
+            SECRET
+        );
+        assert_eq!(
+            keystore.decrypt_secret("testpassword"),
+            Err(Error::IncorrectPassword)
+        );
+    }
+
+    #[test]
+    fn should_decrypt_scrypt_test_vector() {
+        let keystore: Keystore = serde_json::from_str(SCRYPT_KEYSTORE).unwrap();
+        assert_eq!(keystore.crypto.kdf.function, KdfFunction::Scrypt);
+        assert_eq!(
+            hex::encode(keystore.decrypt_secret(PASSWORD).unwrap()),
+            SECRET
+        );
+        assert_eq!(
+            keystore.decrypt_secret("testpassword"),
+            Err(Error::IncorrectPassword)
+        );
+    }
+
+    #[test]
+    fn should_round_trip_keypair() {
+        let keypair = Keypair::random();
+        let keystore =
+            Keystore::encrypt(&keypair, "password", "m/12381/3600/0/0/0", cheap_scrypt()).unwrap();
+        let json = serde_json::to_string(&keystore).unwrap();
+        let parsed: Keystore = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, keystore);
+
+        let decrypted = parsed.decrypt_keypair("password").unwrap();
+        assert_eq!(decrypted.sk, keypair.sk);
+        assert_eq!(decrypted.pk, keypair.pk);
+        assert_eq!(
+            parsed.decrypt_keypair("Password").unwrap_err(),
+            Error::IncorrectPassword
+        );
+    }
+
+    #[test]
+    fn should_strip_control_codes_from_password() {
+        let keypair = Keypair::random();
+        let keystore = Keystore::encrypt(&keypair, "pass\u{7f}word\n", "", cheap_scrypt()).unwrap();
+        assert!(keystore.decrypt_secret("password").is_ok());
+    }
+
+    #[test]
+    fn should_reject_mismatched_kdf() {
+        let mut keystore: Keystore = serde_json::from_str(PBKDF2_KEYSTORE).unwrap();
+        keystore.crypto.kdf.function = KdfFunction::Scrypt;
+        assert_eq!(keystore.decrypt_secret(PASSWORD), Err(Error::KdfMismatch));
+    }
+
+    #[test]
+    fn should_load_keystores_with_passwords_from_secrets_dir() {
+        let dir = std::env::temp_dir().join(format!("keystores-{}", Uuid::new_v4()));
+        let keystores_dir = dir.join("keystores");
+        let secrets_dir = dir.join("secrets");
+        fs::create_dir_all(&keystores_dir).unwrap();
+        fs::create_dir_all(&secrets_dir).unwrap();
+
+        let keypair = Keypair::random();
+        let keystore = Keystore::encrypt(&keypair, "secret", "", cheap_scrypt()).unwrap();
+        keystore
+            .to_file(&keystores_dir.join("keystore-0.json"))
+            .unwrap();
+        fs::write(
+            secrets_dir.join(format!("0x{}", keystore.pubkey)),
+            "secret\n",
+        )
+        .unwrap();
+
+        let keypairs = load_keypairs(&keystores_dir, &secrets_dir);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(keypairs.unwrap()[0].pk, keypair.pk);
+    }
+}
@@ -3,6 +3,19 @@ mod macros;
 
 pub mod attestation_producer;
 pub mod beacon_node;
+pub mod beacon_node_simulator;
 pub mod block_producer;
+pub mod config;
+pub mod deposit;
+pub mod duties;
+pub mod eth1_data_source;
+pub mod fallback_beacon_node;
+pub mod key_derivation;
+pub mod keystore;
+pub mod metrics;
+pub mod mock_beacon_node;
 pub mod rest_client;
+pub mod slashing_protection;
+pub mod slot_clock;
 pub mod validator_service;
+pub mod voluntary_exit;
@@ -1,45 +1,474 @@
 extern crate framework_honest_validator as hv;
 
-use clap::{App, Arg};
-use hv::validator_service::{KeysPair, Service};
-use std::fs::File;
-use std::io::BufReader;
-use types::config::MinimalConfig;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use hv::beacon_node::{BasicBeaconNode, BeaconNode};
+use hv::config::{self, Error, Preset, ValidatorConfig};
+use hv::deposit;
+use hv::eth1_data_source::InMemoryEth1DataSource;
+use hv::fallback_beacon_node::FallbackBeaconNode;
+use hv::key_derivation;
+use hv::keystore::{self, Kdf, Keystore};
+use hv::metrics::{self, Metrics};
+use hv::slashing_protection::{Interchange, SlashingProtection};
+use hv::validator_service::Service;
+use hv::voluntary_exit;
+use log::{error, info, LevelFilter};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::watch;
+use types::chain_spec::ChainSpec;
+use types::config::{Config, MainnetConfig, MinimalConfig, QuickConfig};
+use types::primitives::H256;
 
-enum AppConfiguration {
-    InternalTest,
-    Unsupported,
-}
-
-fn main() {
+#[tokio::main]
+async fn main() {
     println!("Honest validator says hello!");
     let matches = App::new("Honest Validator Client")
         .version("0.1.0")
         .author("Arnas Jozonis, Aurintas Bubinas, Rasa Šmigelskytė")
         .about("Eth 2.0 Validator Client")
-        .arg(
-            Arg::with_name("spec")
-                .short("s")
-                .long("spec")
-                .value_name("CONFIGURATION")
-                .help("Specifies the default eth2 spec type.")
-                .takes_value(true)
-                .possible_values(&["mainnet", "minimal", "internal_test"]),
+        .args(&config::args())
+        .subcommand(
+            SubCommand::with_name("slashing-protection")
+                .about("Imports or exports the EIP-3076 slashing protection interchange file.")
+                .subcommand(
+                    SubCommand::with_name("import")
+                        .about("Merges an interchange file into the slashing protection database.")
+                        .arg(
+                            Arg::with_name("FILE")
+                                .help("Interchange file to import.")
+                                .required(true)
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("Writes the slashing protection database to an interchange file.")
+                        .arg(
+                            Arg::with_name("FILE")
+                                .help("Destination of the interchange file.")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("genesis-validators-root")
+                                .long("genesis-validators-root")
+                                .value_name("ROOT")
+                                .help("Genesis validators root of the chain, required unless known from a previous import.")
+                                .takes_value(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("keys")
+                .about("Manages the validator keys.")
+                .subcommand(
+                    SubCommand::with_name("generate")
+                        .about("Derives validator keys from a mnemonic into keystores, along with their deposit data.")
+                        .arg(
+                            Arg::with_name("count")
+                                .long("count")
+                                .value_name("N")
+                                .help("Number of validator keys to generate.")
+                                .takes_value(true)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("first-index")
+                                .long("first-index")
+                                .value_name("INDEX")
+                                .help("Index of the first validator in the EIP-2334 key path.")
+                                .takes_value(true)
+                                .default_value("0"),
+                        )
+                        .arg(
+                            Arg::with_name("mnemonic-file")
+                                .long("mnemonic-file")
+                                .value_name("FILE")
+                                .help("File with an existing mnemonic. A new mnemonic is created if omitted.")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("deposit-data")
+                                .long("deposit-data")
+                                .value_name("FILE")
+                                .help("Destination of the deposit data of the new validators.")
+                                .takes_value(true)
+                                .default_value("deposit_data.json"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("exit")
+                .about("Voluntarily exits a validator. The exit can not be undone.")
+                .arg(
+                    Arg::with_name("pubkey")
+                        .long("pubkey")
+                        .value_name("PUBKEY")
+                        .help("0x-prefixed public key of the validator to exit.")
+                        .takes_value(true)
+                        .required(true),
+                ),
         )
         .get_matches();
 
-    let app_cfg = match matches.value_of("spec") {
-        Some("internal_test") => AppConfiguration::InternalTest,
-        _ => AppConfiguration::Unsupported,
+    // The level is lowered to the configured one once the config is loaded, errors in it are
+    // logged at the default level.
+    env_logger::Builder::new()
+        .filter_level(LevelFilter::Trace)
+        .init();
+    log::set_max_level(LevelFilter::Info);
+    let config = match ValidatorConfig::load(&matches, |name| env::var(name).ok()) {
+        Ok(config) => config,
+        Err(Error::InvalidSetting { name, reason }) => {
+            error!("Invalid setting {}: {}", name, reason);
+            std::process::exit(1);
+        }
     };
+    log::set_max_level(config.log_level);
+
+    match config.preset {
+        Preset::Mainnet => run::<MainnetConfig>(&matches, &config).await,
+        Preset::InternalTest => run::<QuickConfig>(&matches, &config).await,
+        Preset::Minimal => run::<MinimalConfig>(&matches, &config).await,
+    }
+}
 
-    let cfg = match app_cfg {
-        AppConfiguration::InternalTest => MinimalConfig::default(),
-        AppConfiguration::Unsupported => MinimalConfig::default(),
+/// Runs the selected command against a chain using the `C` preset.
+async fn run<C: Config + Serialize + DeserializeOwned>(
+    matches: &ArgMatches<'_>,
+    config: &ValidatorConfig,
+) {
+    let keystores_dir = &config.keystores_dir;
+    let secrets_dir = &config.secrets_dir;
+    let spec = match &config.chain_spec {
+        Some(path) => match ChainSpec::from_file::<C>(path) {
+            Ok(spec) => spec,
+            Err(e) => {
+                error!("Invalid setting chain-spec: {}: {:?}", path.display(), e);
+                std::process::exit(1);
+            }
+        },
+        None => ChainSpec::from_config::<C>(),
     };
-    let file = File::open("mock_data/mock_validators.json").unwrap();
-    let buf_reader = BufReader::new(file);
-    let validators: Vec<KeysPair> = serde_json::from_reader(buf_reader).unwrap();
-    let service: Service<MinimalConfig> = Service::new(cfg, validators);
-    service.start().unwrap();
+
+    if let ("keys", Some(sub_matches)) = matches.subcommand() {
+        if let Err(e) = run_keys_command(sub_matches, &spec, keystores_dir, secrets_dir) {
+            error!("Keys command failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let ("exit", Some(sub_matches)) = matches.subcommand() {
+        let beacon_node = fallback_beacon_node::<C>(config);
+        if let Err(e) =
+            run_exit_command(sub_matches, &beacon_node, &spec, keystores_dir, secrets_dir).await
+        {
+            error!("Exit command failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let slashing_protection = match SlashingProtection::open(&config.slashing_protection_db) {
+        Ok(slashing_protection) => slashing_protection,
+        Err(e) => {
+            error!(
+                "Invalid setting slashing-protection-db: {}: {:?}",
+                config.slashing_protection_db.display(),
+                e
+            );
+            std::process::exit(1);
+        }
+    };
+
+    if let ("slashing-protection", Some(sub_matches)) = matches.subcommand() {
+        if let Err(e) = run_slashing_protection_command(sub_matches, &slashing_protection) {
+            error!("Slashing protection command failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let validators = match keystore::load_keypairs(keystores_dir, secrets_dir) {
+        Ok(keypairs) => keypairs,
+        Err(e) => {
+            error!("Failed to load validator keystores: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+    let beacon_node = fallback_beacon_node(config);
+    let mut service: Service<C, FallbackBeaconNode<BasicBeaconNode<C>>> =
+        Service::new(C::default(), beacon_node, slashing_protection, validators)
+            .with_chain_spec(spec);
+    if let Some(path) = &config.eth1_data {
+        let eth1_data_source = match InMemoryEth1DataSource::from_file(path) {
+            Ok(eth1_data_source) => eth1_data_source,
+            Err(e) => {
+                error!("Invalid setting eth1-data: {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+        service = service.with_eth1_data_source(Box::new(eth1_data_source));
+    }
+    if let Some(graffiti) = config.graffiti {
+        service = service.with_graffiti(graffiti);
+    }
+    if let Some(run_duration) = config.run_duration {
+        service = service.with_run_duration(run_duration);
+    }
+    if let Some(address) = &config.metrics_address {
+        let metrics = Arc::new(Metrics::default());
+        match metrics::serve(Arc::clone(&metrics), address) {
+            Ok(address) => info!("Serving metrics on http://{}/metrics", address),
+            Err(e) => {
+                error!("Invalid setting metrics-address: {}: {}", address, e);
+                std::process::exit(1);
+            }
+        }
+        service = service.with_metrics(metrics);
+    }
+    service = service.with_shutdown(handle_shutdown_signals());
+    if let Err(e) = service.start().await {
+        error!("Validator service failed: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Turns the first SIGINT or SIGTERM into a graceful shutdown of the service, a second one exits
+/// immediately.
+fn handle_shutdown_signals() -> watch::Receiver<bool> {
+    let (sender, receiver) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        println!("Shutting down after the duties in progress, signal again to exit immediately.");
+        let _ = sender.send(true);
+        shutdown_signal().await;
+        std::process::exit(1);
+    });
+    receiver
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("SIGTERM handler is installed");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+fn fallback_beacon_node<C: Config>(
+    config: &ValidatorConfig,
+) -> FallbackBeaconNode<BasicBeaconNode<C>> {
+    let beacon_nodes = config
+        .beacon_nodes
+        .iter()
+        .map(|url| BasicBeaconNode::new(url.clone()))
+        .collect();
+    FallbackBeaconNode::new(beacon_nodes)
+}
+
+fn run_slashing_protection_command(
+    matches: &ArgMatches,
+    slashing_protection: &SlashingProtection,
+) -> Result<(), String> {
+    match matches.subcommand() {
+        ("import", Some(import_matches)) => {
+            let path = import_matches.value_of("FILE").unwrap();
+            let file = File::open(path).map_err(|e| e.to_string())?;
+            let interchange: Interchange =
+                serde_json::from_reader(BufReader::new(file)).map_err(|e| e.to_string())?;
+            slashing_protection
+                .import_interchange(&interchange)
+                .map_err(|e| format!("{:?}", e))?;
+            println!(
+                "Imported signing history of {} validators",
+                interchange.data.len()
+            );
+            Ok(())
+        }
+        ("export", Some(export_matches)) => {
+            let path = export_matches.value_of("FILE").unwrap();
+            let genesis_validators_root = match export_matches.value_of("genesis-validators-root") {
+                Some(root) => Some(parse_root(root)?),
+                None => None,
+            };
+            let interchange = slashing_protection
+                .export_interchange(genesis_validators_root)
+                .map_err(|e| format!("{:?}", e))?;
+            let file = File::create(path).map_err(|e| e.to_string())?;
+            serde_json::to_writer_pretty(BufWriter::new(file), &interchange)
+                .map_err(|e| e.to_string())?;
+            println!(
+                "Exported signing history of {} validators",
+                interchange.data.len()
+            );
+            Ok(())
+        }
+        _ => Err(String::from("expected `import` or `export`")),
+    }
+}
+
+fn run_keys_command(
+    matches: &ArgMatches,
+    spec: &ChainSpec,
+    keystores_dir: &Path,
+    secrets_dir: &Path,
+) -> Result<(), String> {
+    let generate_matches = match matches.subcommand() {
+        ("generate", Some(generate_matches)) => generate_matches,
+        _ => return Err(String::from("expected `generate`")),
+    };
+    let count: u32 = generate_matches
+        .value_of("count")
+        .unwrap()
+        .parse()
+        .map_err(|_| String::from("count must be a number"))?;
+    let first_index: u32 = generate_matches
+        .value_of("first-index")
+        .unwrap()
+        .parse()
+        .map_err(|_| String::from("first index must be a number"))?;
+    let last_index = first_index
+        .checked_add(count)
+        .ok_or_else(|| format!("first index plus count must not exceed {}", u32::MAX))?;
+    let phrase = match generate_matches.value_of("mnemonic-file") {
+        Some(path) => fs::read_to_string(path).map_err(|e| e.to_string())?,
+        None => {
+            let phrase = key_derivation::new_mnemonic();
+            println!(
+                "Write down this mnemonic, it is the only way to recover the keys:\n\n{}\n",
+                phrase
+            );
+            phrase
+        }
+    };
+    let seed = key_derivation::mnemonic_to_seed(&phrase, "").map_err(|e| format!("{:?}", e))?;
+
+    fs::create_dir_all(keystores_dir).map_err(|e| e.to_string())?;
+    fs::create_dir_all(secrets_dir).map_err(|e| e.to_string())?;
+    let mut deposits = Vec::new();
+    for index in first_index..last_index {
+        let signing_key_path = key_derivation::signing_key_path(index);
+        let signing_keypair = key_derivation::derive_keypair(&seed, &signing_key_path)
+            .map_err(|e| format!("{:?}", e))?;
+        let withdrawal_keypair =
+            key_derivation::derive_keypair(&seed, &key_derivation::withdrawal_key_path(index))
+                .map_err(|e| format!("{:?}", e))?;
+
+        let password: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(48)
+            .collect();
+        let keystore = Keystore::encrypt(
+            &signing_keypair,
+            &password,
+            &signing_key_path,
+            Kdf::scrypt(),
+        )
+        .map_err(|e| format!("{:?}", e))?;
+        let keystore_path = keystores_dir.join(format!(
+            "keystore-{}.json",
+            signing_key_path.replace('/', "_")
+        ));
+        if keystore_path.exists() {
+            return Err(format!("{} already exists", keystore_path.display()));
+        }
+        keystore
+            .to_file(&keystore_path)
+            .map_err(|e| format!("{:?}", e))?;
+        fs::write(secrets_dir.join(format!("0x{}", keystore.pubkey)), password)
+            .map_err(|e| e.to_string())?;
+        println!("Created keystore {}", keystore_path.display());
+
+        let withdrawal_credentials =
+            deposit::bls_withdrawal_credentials(spec, &withdrawal_keypair.pk);
+        let deposit_data = deposit::new_deposit_data(
+            spec,
+            &signing_keypair,
+            withdrawal_credentials,
+            spec.max_effective_balance,
+        )
+        .and_then(|deposit_data| {
+            deposit::verify_deposit_data(spec, &deposit_data).map(|()| deposit_data)
+        })
+        .map_err(|e| format!("{:?}", e))?;
+        deposits.push(deposit_data);
+    }
+
+    let deposit_data_path = generate_matches.value_of("deposit-data").unwrap();
+    deposit::write_deposit_data_file(spec, Path::new(deposit_data_path), &deposits)
+        .map_err(|e| format!("{:?}", e))?;
+    println!("Wrote deposit data to {}", deposit_data_path);
+    Ok(())
+}
+
+const EXIT_CONFIRMATION: &str = "Exit my validator";
+
+async fn run_exit_command<C: Config, B: BeaconNode<C>>(
+    matches: &ArgMatches<'_>,
+    beacon_node: &B,
+    spec: &ChainSpec,
+    keystores_dir: &Path,
+    secrets_dir: &Path,
+) -> Result<(), String> {
+    let pubkey = matches.value_of("pubkey").unwrap().to_lowercase();
+    let keypair = keystore::load_keypairs(keystores_dir, secrets_dir)
+        .map_err(|e| format!("{:?}", e))?
+        .into_iter()
+        .find(|keypair| format!("0x{}", hex::encode(keypair.pk.as_bytes())) == pubkey)
+        .ok_or_else(|| format!("no keystore found for {}", pubkey))?;
+    let state = beacon_node
+        .get_state()
+        .await
+        .map_err(|e| format!("can not get beacon state: {:?}", e))?;
+    let exit = voluntary_exit::prepare_voluntary_exit(spec, &state, &keypair)
+        .map_err(|e| format!("{:?}", e))?;
+
+    println!(
+        "Validator {} ({}) will exit at epoch {}.",
+        exit.validator_index, pubkey, exit.epoch
+    );
+    println!("Exiting is irreversible, the validator will not be able to rejoin.");
+    print!("Type \"{}\" to confirm: ", EXIT_CONFIRMATION);
+    io::stdout().flush().map_err(|e| e.to_string())?;
+    let mut confirmation = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut confirmation)
+        .map_err(|e| e.to_string())?;
+    if confirmation.trim_end() != EXIT_CONFIRMATION {
+        return Err(String::from("exit not confirmed"));
+    }
+
+    beacon_node
+        .publish_voluntary_exit(exit)
+        .await
+        .map_err(|e| format!("{:?}", e))?;
+    println!("Voluntary exit published");
+    Ok(())
+}
+
+fn parse_root(root: &str) -> Result<H256, String> {
+    let bytes = hex::decode(root.trim_start_matches("0x")).map_err(|e| e.to_string())?;
+    if bytes.len() != 32 {
+        return Err(format!("root must be 32 bytes long, got {}", bytes.len()));
+    }
+    Ok(H256::from_slice(&bytes))
 }
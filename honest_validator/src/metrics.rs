@@ -0,0 +1,129 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::error;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Counters of the work done by the validator service.
+#[derive(Default, Debug)]
+pub struct Metrics {
+    pub blocks_proposed: AtomicU64,
+    pub block_proposals_failed: AtomicU64,
+    pub attestations_published: AtomicU64,
+    pub attestations_failed: AtomicU64,
+    pub missed_slots: AtomicU64,
+    pub beacon_node_errors: AtomicU64,
+}
+
+impl Metrics {
+    pub fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counters in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let counters = [
+            (
+                "validator_blocks_proposed_total",
+                "Blocks signed and published.",
+                &self.blocks_proposed,
+            ),
+            (
+                "validator_block_proposals_failed_total",
+                "Block proposal duties that failed.",
+                &self.block_proposals_failed,
+            ),
+            (
+                "validator_attestations_published_total",
+                "Attestations signed and published.",
+                &self.attestations_published,
+            ),
+            (
+                "validator_attestations_failed_total",
+                "Attestation duties that failed.",
+                &self.attestations_failed,
+            ),
+            (
+                "validator_missed_slots_total",
+                "Slots skipped because the clock was ahead or no beacon node was available.",
+                &self.missed_slots,
+            ),
+            (
+                "validator_beacon_node_errors_total",
+                "Failures to fetch the beacon state or duties of an epoch.",
+                &self.beacon_node_errors,
+            ),
+        ];
+        let mut text = String::new();
+        for (name, help, counter) in counters.iter() {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} counter", name);
+            let _ = writeln!(text, "{} {}", name, counter.load(Ordering::Relaxed));
+        }
+        text
+    }
+}
+
+/// Serves `metrics` at `/metrics` on `address` from a task of the current tokio runtime. Returns
+/// the address bound, port 0 picks a free port.
+pub fn serve(metrics: Arc<Metrics>, address: &SocketAddr) -> Result<SocketAddr, String> {
+    let make_service = make_service_fn(move |_| {
+        let metrics = Arc::clone(&metrics);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle_request(Arc::clone(&metrics), request)
+            }))
+        }
+    });
+    let server = Server::try_bind(address)
+        .map_err(|e| e.to_string())?
+        .serve(make_service);
+    let address = server.local_addr();
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("Metrics server error: {}", e);
+        }
+    });
+    Ok(address)
+}
+
+async fn handle_request(
+    metrics: Arc<Metrics>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(Body::from(metrics.render())),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(response.expect("response parts are valid"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Client;
+
+    #[tokio::test]
+    async fn should_serve_counters() {
+        let metrics = Arc::new(Metrics::default());
+        Metrics::increment(&metrics.blocks_proposed);
+        Metrics::increment(&metrics.blocks_proposed);
+        let address = serve(Arc::clone(&metrics), &([127, 0, 0, 1], 0).into()).unwrap();
+
+        let uri = format!("http://{}/metrics", address).parse().unwrap();
+        let response = Client::new().get(uri).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(text.contains("validator_blocks_proposed_total 2\n"));
+        assert!(text.contains("# TYPE validator_attestations_published_total counter\n"));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::beacon_node::{BeaconNode, DutyInfo, Error};
+use async_trait::async_trait;
+use bls::{PublicKeyBytes, Signature};
+use hex;
+use std::sync::Mutex;
+use types::beacon_state::BeaconState;
+use types::config::Config as EthConfig;
+use types::primitives::{Epoch, Slot};
+use types::types::{Attestation, BeaconBlock, SignedBeaconBlock, VoluntaryExit};
+
+/// In-memory beacon node, serving a fixed state and duties and recording everything published to it.
+#[derive(Default)]
+pub struct MockBeaconNode<C: EthConfig> {
+    pub state: Option<BeaconState<C>>,
+    pub duties: Vec<DutyInfo>,
+    pub block: Option<BeaconBlock<C>>,
+    pub syncing: bool,
+    /// Number of upcoming `get_state` calls failing as if the beacon node was down.
+    pub state_failures: Mutex<u32>,
+    pub published_attestations: Mutex<Vec<Attestation<C>>>,
+    pub published_blocks: Mutex<Vec<SignedBeaconBlock<C>>>,
+    pub published_voluntary_exits: Mutex<Vec<VoluntaryExit>>,
+}
+
+impl<C: EthConfig> MockBeaconNode<C> {
+    pub fn new(state: BeaconState<C>) -> MockBeaconNode<C> {
+        MockBeaconNode {
+            state: Some(state),
+            ..MockBeaconNode::default()
+        }
+    }
+
+    pub fn with_duties(mut self, duties: Vec<DutyInfo>) -> MockBeaconNode<C> {
+        self.duties = duties;
+        self
+    }
+
+    pub fn with_syncing(mut self, syncing: bool) -> MockBeaconNode<C> {
+        self.syncing = syncing;
+        self
+    }
+
+    pub fn with_state_failures(self, count: u32) -> MockBeaconNode<C> {
+        *self.state_failures.lock().unwrap() = count;
+        self
+    }
+
+    pub fn with_block(mut self, block: BeaconBlock<C>) -> MockBeaconNode<C> {
+        self.block = Some(block);
+        self
+    }
+}
+
+#[async_trait]
+impl<C: EthConfig> BeaconNode<C> for MockBeaconNode<C> {
+    async fn get_state(&self) -> Result<BeaconState<C>, Error> {
+        let mut state_failures = self.state_failures.lock().unwrap();
+        if *state_failures > 0 {
+            *state_failures -= 1;
+            return Err(Error::StateUnavailable);
+        }
+        self.state.clone().ok_or(Error::StateUnavailable)
+    }
+
+    async fn is_syncing(&self) -> Result<bool, Error> {
+        Ok(self.syncing)
+    }
+
+    async fn get_duties(
+        &self,
+        validators: Vec<PublicKeyBytes>,
+        _epoch: Epoch,
+    ) -> Result<Vec<DutyInfo>, Error> {
+        let pubkeys: Vec<String> = validators
+            .iter()
+            .map(|pubkey| format!("0x{}", hex::encode(pubkey.as_bytes())))
+            .collect();
+        Ok(self
+            .duties
+            .iter()
+            .filter(|duty| pubkeys.contains(&duty.validator_pubkey))
+            .cloned()
+            .collect())
+    }
+
+    async fn publish_attestation(&self, attestation: Attestation<C>) -> Result<(), Error> {
+        self.published_attestations
+            .lock()
+            .unwrap()
+            .push(attestation);
+        Ok(())
+    }
+
+    async fn get_block(
+        &self,
+        _slot: Slot,
+        randao_reveal: &Signature,
+    ) -> Result<BeaconBlock<C>, Error> {
+        let mut block = self.block.clone().ok_or(Error::BlockUnavailable)?;
+        block.body.randao_reveal = randao_reveal.clone();
+        Ok(block)
+    }
+
+    async fn publish_block(&self, block: SignedBeaconBlock<C>) -> Result<(), Error> {
+        self.published_blocks.lock().unwrap().push(block);
+        Ok(())
+    }
+
+    async fn publish_voluntary_exit(&self, exit: VoluntaryExit) -> Result<(), Error> {
+        self.published_voluntary_exits.lock().unwrap().push(exit);
+        Ok(())
+    }
+}
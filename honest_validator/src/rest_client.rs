@@ -1,169 +1,491 @@
-use hyper::client::Client;
+use hyper::body::{self, Body};
+use hyper::client::{Client, HttpConnector};
 use hyper::header::HeaderValue;
-use hyper::{self, Body, Method, Request, Uri};
-type HttpConnector = hyper::client::HttpConnector;
-
-use futures::future::{ok, Future};
-use futures::stream::Stream;
-use tokio_core::reactor::Core;
+use hyper::{self, Method, Request, Uri};
 
+use log::warn;
+use rand::Rng;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use std::cell::RefCell;
-use std::rc::Rc;
-use std::str;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-pub struct RestClient {
-    base_url: String,
-    http: Rc<Client<HttpConnector>>,
-    core: Rc<RefCell<Core>>,
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /// The base URL and the resource path do not form a valid URI.
+    InvalidUri(String),
+    /// The request body could not be serialized.
+    Encode(String),
+    /// The request could not be sent or the response could not be received.
+    Transport(String),
+    /// The beacon node answered with a status other than 2xx.
+    Status { status: u16, body: String },
+    /// The response body of `path` is not the expected JSON.
+    Decode { path: String, message: String },
+    /// The beacon node did not answer in time.
+    Timeout,
+    /// Requests are suspended after repeated failures of the beacon node.
+    CircuitOpen,
+}
+
+impl Error {
+    /// Whether the same request may succeed if sent again later.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Transport(_) | Error::Timeout | Error::CircuitOpen => true,
+            Error::Status { status, .. } => *status == 429 || *status >= 500,
+            Error::InvalidUri(_) | Error::Encode(_) | Error::Decode { .. } => false,
+        }
+    }
 }
 
-impl Clone for RestClient {
-    fn clone(&self) -> Self {
-        Self {
-            base_url: self.base_url.clone(),
-            core: Rc::clone(&self.core),
-            http: Rc::clone(&self.http),
+/// How GET requests are repeated after retryable errors. Other requests are never repeated, as
+/// they may not be idempotent.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(250),
+            max_backoff: Duration::from_secs(4),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn no_retries() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 0,
+            ..RetryPolicy::default()
         }
     }
+
+    /// Delay before retry number `retry`, counted from 0: the backoff doubles with every retry up
+    /// to `max_backoff`, and a random half of it is skipped so that clients do not retry in step.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .checked_mul(2u32.saturating_pow(retry))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff));
+        let millis = backoff.as_millis() as u64;
+        Duration::from_millis(millis / 2 + rand::thread_rng().gen_range(0, millis / 2 + 1))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CircuitState {
+    /// Requests are sent as usual.
+    Closed,
+    /// The beacon node failed repeatedly, requests fail without being sent until the cooldown ends.
+    Open,
+    /// The cooldown has ended, the next request checks whether the beacon node has recovered.
+    HalfOpen,
+}
+
+/// Stops sending requests to a beacon node after `failure_threshold` consecutive retryable
+/// failures, for `cooldown`.
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> CircuitBreaker {
+        CircuitBreaker::new(5, Duration::from_secs(12))
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            failure_threshold,
+            cooldown,
+            consecutive_failures: 0,
+            opened_at: None,
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match self.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.cooldown => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.opened_at = None;
+    }
+
+    /// A failure while half open reopens the circuit straight away.
+    pub fn record_failure(&mut self) {
+        self.consecutive_failures += 1;
+        if self.state() == CircuitState::HalfOpen
+            || self.consecutive_failures >= self.failure_threshold
+        {
+            self.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+/// JSON client of a beacon node HTTP API. Clones share the connection pool and the circuit
+/// breaker, so a client can be handed to every task that talks to the same beacon node.
+#[derive(Clone)]
+pub struct RestClient {
+    base_url: String,
+    http: Client<HttpConnector>,
+    timeout: Duration,
+    retry_policy: RetryPolicy,
+    circuit_breaker: Arc<Mutex<CircuitBreaker>>,
 }
 
 impl RestClient {
     pub fn new(base_url: String) -> Option<RestClient> {
-        let core = Core::new().unwrap();
-        let http = Client::builder().build(HttpConnector::new(4));
         Some(RestClient {
-            core: Rc::new(RefCell::new(core)),
-            http: Rc::new(http),
+            http: Client::new(),
             base_url,
+            timeout: DEFAULT_TIMEOUT,
+            retry_policy: RetryPolicy::default(),
+            circuit_breaker: Arc::new(Mutex::new(CircuitBreaker::default())),
         })
     }
 
-    pub fn post<TResult, TBody>(&self, resource_uri: &str, body: Option<TBody>) -> Option<TResult>
+    /// Time a single attempt of a request may take, including reading the response.
+    pub fn with_timeout(mut self, timeout: Duration) -> RestClient {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> RestClient {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, circuit_breaker: CircuitBreaker) -> RestClient {
+        self.circuit_breaker = Arc::new(Mutex::new(circuit_breaker));
+        self
+    }
+
+    pub fn circuit_state(&self) -> CircuitState {
+        self.circuit_breaker().state()
+    }
+
+    /// Posts `body` as JSON to `resource_uri` and decodes the JSON response. An empty response
+    /// decodes as `null`, so requests that are only acknowledged can expect `()`.
+    pub async fn post<TResult, TBody>(
+        &self,
+        resource_uri: &str,
+        body: &TBody,
+    ) -> Result<TResult, Error>
     where
-        TResult: DeserializeOwned + Default,
+        TResult: DeserializeOwned,
         TBody: Serialize,
     {
-        let host = self.base_url.clone();
-        println!("posting: {}", host.clone() + resource_uri);
-        let uri: Uri = (host + resource_uri).parse().unwrap();
-        match self.post_request(uri, body) {
-            Some(response) => response,
-            None => Some(TResult::default()),
-        }
+        let json = serde_json::to_string(body).map_err(|e| Error::Encode(e.to_string()))?;
+        self.request(Method::POST, resource_uri, json, RetryPolicy::no_retries())
+            .await
+    }
+
+    pub async fn get<TResult>(&self, resource_uri: &str) -> Result<TResult, Error>
+    where
+        TResult: DeserializeOwned,
+    {
+        self.request(Method::GET, resource_uri, String::new(), self.retry_policy)
+            .await
     }
 
-    fn post_request<TResult, TBody>(
+    async fn request<TResult>(
         &self,
-        resource_uri: Uri,
-        body: Option<TBody>,
-    ) -> Option<TResult>
+        method: Method,
+        resource_uri: &str,
+        body: String,
+        retry_policy: RetryPolicy,
+    ) -> Result<TResult, Error>
     where
-        TResult: DeserializeOwned + Default,
-        TBody: Serialize,
+        TResult: DeserializeOwned,
     {
-        let mut core_ref = self.core.try_borrow_mut().unwrap();
-        let client = &self.http;
-        let req_body = match body {
-            Some(b) => {
-                let json = serde_json::to_string(&b).unwrap();
-                Body::from(json)
+        if self.circuit_state() == CircuitState::Open {
+            return Err(Error::CircuitOpen);
+        }
+        let mut retry = 0;
+        loop {
+            let result = self.send(method.clone(), resource_uri, body.clone()).await;
+            match &result {
+                Err(e) if e.is_retryable() => self.circuit_breaker().record_failure(),
+                _ => self.circuit_breaker().record_success(),
             }
-            None => Body::empty(),
-        };
+            match result {
+                Err(ref e)
+                    if e.is_retryable()
+                        && retry < retry_policy.max_retries
+                        && self.circuit_state() != CircuitState::Open =>
+                {
+                    warn!("Request to {} failed, retrying: {:?}", resource_uri, e);
+                    tokio::time::sleep(retry_policy.backoff(retry)).await;
+                    retry += 1;
+                }
+                _ => return result,
+            }
+        }
+    }
 
-        let mut req = Request::new(req_body);
-        *req.method_mut() = Method::POST;
-        *req.uri_mut() = resource_uri.clone();
+    async fn send<TResult>(
+        &self,
+        method: Method,
+        resource_uri: &str,
+        body: String,
+    ) -> Result<TResult, Error>
+    where
+        TResult: DeserializeOwned,
+    {
+        let uri = format!("{}{}", self.base_url, resource_uri)
+            .parse::<Uri>()
+            .map_err(|e| Error::InvalidUri(e.to_string()))?;
+        let mut req = Request::new(Body::from(body));
+        *req.method_mut() = method;
+        *req.uri_mut() = uri;
         req.headers_mut().insert(
             hyper::header::CONTENT_TYPE,
             HeaderValue::from_static("application/json"),
         );
 
-        let work = client.request(req).and_then(|res| {
-            res.into_body()
-                .fold(Vec::new(), |mut v, chunk| {
-                    v.extend(&chunk[..]);
-                    ok::<_, hyper::Error>(v)
-                })
-                .map(move |chunks| {
-                    if chunks.is_empty() {
-                        None
-                    } else {
-                        let result = match serde_json::from_slice(&chunks) {
-                            Ok(res) => res,
-                            Err(e) => {
-                                println!("Error in parsing response json: {}", e);
-                                Some(TResult::default())
-                            }
-                        };
-                        result
-                    }
-                })
+        let work = async {
+            let res = self.http.request(req).await?;
+            let status = res.status();
+            body::to_bytes(res.into_body())
+                .await
+                .map(|chunks| (status, chunks))
+        };
+        let (status, chunks) = tokio::time::timeout(self.timeout, work)
+            .await
+            .map_err(|_| Error::Timeout)?
+            .map_err(|e| Error::Transport(e.to_string()))?;
+
+        if !status.is_success() {
+            return Err(Error::Status {
+                status: status.as_u16(),
+                body: String::from_utf8_lossy(&chunks).into_owned(),
+            });
+        }
+        let json: &[u8] = if chunks.is_empty() { b"null" } else { &chunks };
+        serde_json::from_slice(json).map_err(|e| Error::Decode {
+            path: resource_uri.to_string(),
+            message: e.to_string(),
+        })
+    }
+
+    fn circuit_breaker(&self) -> std::sync::MutexGuard<'_, CircuitBreaker> {
+        self.circuit_breaker
+            .lock()
+            .expect("circuit breaker lock is not poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beacon_node::DutyInfo;
+    use crate::beacon_node_simulator::BeaconNodeSimulator;
+    use serde::de::IgnoredAny;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use types::genesis::interop_genesis_state;
+
+    const NO_BACKOFF: RetryPolicy = RetryPolicy {
+        max_retries: 2,
+        initial_backoff: Duration::from_millis(0),
+        max_backoff: Duration::from_millis(0),
+    };
+
+    /// Answers every request with `status` and an empty body. Returns the URL of the server and
+    /// the number of requests it has received.
+    fn serve_status(status: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut buffer = [0; 4096];
+                let _ = stream.read(&mut buffer);
+                counter.fetch_add(1, Ordering::SeqCst);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                );
+            }
         });
+        (url, requests)
+    }
 
-        match core_ref.run(work) {
-            Ok(future_item) => future_item,
-            Err(e) => {
-                println!("API error: {}", e);
-                None
+    fn start_simulator() -> BeaconNodeSimulator {
+        let genesis_state = interop_genesis_state(64, 0).unwrap();
+        BeaconNodeSimulator::start(genesis_state, &([127, 0, 0, 1], 0).into()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_report_status_of_failed_request() {
+        let simulator = start_simulator();
+        let client = RestClient::new(simulator.url()).unwrap();
+
+        let result = client.get::<IgnoredAny>("/node/version").await;
+
+        let error = result.unwrap_err();
+        assert_eq!(
+            error,
+            Error::Status {
+                status: 404,
+                body: String::new()
             }
+        );
+        assert!(!error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn should_report_path_of_undecodable_response() {
+        let simulator = start_simulator();
+        let client = RestClient::new(simulator.url()).unwrap();
+
+        let result = client.get::<Vec<DutyInfo>>("/beacon/state").await;
+
+        match result {
+            Err(Error::Decode { path, .. }) => assert_eq!(path, "/beacon/state"),
+            other => panic!("expected a decode error, got {:?}", other),
         }
     }
 
-    pub fn get<TResult>(&self, resource_uri: &str) -> Option<TResult>
-    where
-        TResult: DeserializeOwned,
-    {
-        let host = self.base_url.clone();
-        let uri: Uri = (host + resource_uri).parse().unwrap();
-        self.get_request(uri)
+    #[tokio::test]
+    async fn should_retry_unreachable_node() {
+        let client = RestClient::new(String::from("http://127.0.0.1:1"))
+            .unwrap()
+            .with_retry_policy(RetryPolicy::no_retries());
+
+        let error = client.get::<IgnoredAny>("/beacon/state").await.unwrap_err();
+
+        match error {
+            Error::Transport(_) => assert!(error.is_retryable()),
+            other => panic!("expected a transport error, got {:?}", other),
+        }
     }
 
-    fn get_request<TResult>(&self, uri: Uri) -> Option<TResult>
-    where
-        TResult: DeserializeOwned,
-    {
-        let mut core_ref = self.core.try_borrow_mut().unwrap();
-        let client = &self.http;
-        let mut req = Request::new(Body::empty());
+    #[test]
+    fn should_retry_server_errors_only() {
+        let status = |status| Error::Status {
+            status,
+            body: String::new(),
+        };
+        assert!(status(503).is_retryable());
+        assert!(status(429).is_retryable());
+        assert!(!status(400).is_retryable());
+        assert!(!Error::InvalidUri(String::new()).is_retryable());
+    }
 
-        *req.method_mut() = Method::GET;
-        *req.uri_mut() = uri.clone();
-        req.headers_mut().insert(
-            hyper::header::CONTENT_TYPE,
-            HeaderValue::from_static("application/json"),
+    #[tokio::test]
+    async fn should_retry_failed_get() {
+        let (url, requests) = serve_status("503 Service Unavailable");
+        let client = RestClient::new(url).unwrap().with_retry_policy(NO_BACKOFF);
+
+        let result = client.get::<IgnoredAny>("/beacon/state").await;
+
+        assert_eq!(
+            result.unwrap_err(),
+            Error::Status {
+                status: 503,
+                body: String::new()
+            }
         );
-        let work = client.request(req).and_then(|res| {
-            res.into_body()
-                .fold(Vec::new(), |mut v, chunk| {
-                    v.extend(&chunk[..]);
-                    ok::<_, hyper::Error>(v)
-                })
-                .map(move |chunks| {
-                    if chunks.is_empty() {
-                        None
-                    } else {
-                        let result = match serde_json::from_slice(&chunks) {
-                            Ok(res) => res,
-                            Err(e) => {
-                                println!("Error in parsing response json: {}", e);
-                                None
-                            }
-                        };
-                        result
-                    }
-                })
-        });
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn should_not_retry_failed_post() {
+        let (url, requests) = serve_status("503 Service Unavailable");
+        let client = RestClient::new(url).unwrap().with_retry_policy(NO_BACKOFF);
+
+        let result = client
+            .post::<IgnoredAny, _>("/validator/attestation", &())
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
 
-        match core_ref.run(work) {
-            Ok(future_item) => future_item,
-            Err(e) => {
-                println!("API error: {}", e);
-                None
+    #[tokio::test]
+    async fn should_time_out_hung_request() {
+        // Connections are queued by the OS but never answered
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = RestClient::new(format!("http://{}", listener.local_addr().unwrap()))
+            .unwrap()
+            .with_timeout(Duration::from_millis(100))
+            .with_retry_policy(RetryPolicy::no_retries());
+
+        let result = client.get::<IgnoredAny>("/beacon/state").await;
+
+        assert_eq!(result.unwrap_err(), Error::Timeout);
+    }
+
+    #[tokio::test]
+    async fn should_stop_sending_requests_once_circuit_opens() {
+        let (url, requests) = serve_status("500 Internal Server Error");
+        let client = RestClient::new(url)
+            .unwrap()
+            .with_retry_policy(NO_BACKOFF)
+            .with_circuit_breaker(CircuitBreaker::new(2, Duration::from_secs(60)));
+
+        assert_eq!(
+            client.get::<IgnoredAny>("/beacon/state").await.unwrap_err(),
+            Error::Status {
+                status: 500,
+                body: String::new()
             }
-        }
+        );
+        assert_eq!(client.circuit_state(), CircuitState::Open);
+        assert_eq!(
+            client.get::<IgnoredAny>("/beacon/state").await.unwrap_err(),
+            Error::CircuitOpen
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn should_close_circuit_after_successful_probe() {
+        let mut circuit_breaker = CircuitBreaker::new(1, Duration::from_millis(0));
+        assert_eq!(circuit_breaker.state(), CircuitState::Closed);
+
+        circuit_breaker.record_failure();
+        assert_eq!(circuit_breaker.state(), CircuitState::HalfOpen);
+        circuit_breaker.record_success();
+        assert_eq!(circuit_breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn should_cap_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+        };
+
+        let first = policy.backoff(0);
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+        let capped = policy.backoff(9);
+        assert!(capped >= Duration::from_millis(500) && capped <= Duration::from_millis(1000));
     }
 }
@@ -0,0 +1,260 @@
+//! Slashing protection interchange format, see
+//! <https://eips.ethereum.org/EIPS/eip-3076>.
+
+use super::{InterchangeError, SignedAttestation, SignedBlock, ValidatorHistory};
+use serde::{Deserialize, Serialize};
+use types::primitives::H256;
+
+pub const INTERCHANGE_FORMAT_VERSION: u64 = 5;
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct InterchangeMetadata {
+    #[serde(with = "quoted_u64")]
+    pub interchange_format_version: u64,
+    pub genesis_validators_root: H256,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct InterchangeData {
+    pub pubkey: String,
+    pub signed_blocks: Vec<SignedBlock>,
+    pub signed_attestations: Vec<SignedAttestation>,
+}
+
+impl InterchangeData {
+    pub fn new(pubkey: String, history: &ValidatorHistory) -> InterchangeData {
+        InterchangeData {
+            pubkey,
+            signed_blocks: history.signed_blocks.clone(),
+            signed_attestations: history.signed_attestations.clone(),
+        }
+    }
+
+    pub fn history(&self) -> ValidatorHistory {
+        ValidatorHistory {
+            signed_blocks: self.signed_blocks.clone(),
+            signed_attestations: self.signed_attestations.clone(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Interchange {
+    pub metadata: InterchangeMetadata,
+    pub data: Vec<InterchangeData>,
+}
+
+impl Interchange {
+    pub fn new(genesis_validators_root: H256, data: Vec<InterchangeData>) -> Interchange {
+        Interchange {
+            metadata: InterchangeMetadata {
+                interchange_format_version: INTERCHANGE_FORMAT_VERSION,
+                genesis_validators_root,
+            },
+            data,
+        }
+    }
+
+    pub fn check_version(&self) -> Result<(), InterchangeError> {
+        match self.metadata.interchange_format_version {
+            INTERCHANGE_FORMAT_VERSION => Ok(()),
+            version => Err(InterchangeError::UnsupportedVersion(version)),
+        }
+    }
+}
+
+/// The interchange format encodes all integers as decimal strings.
+pub mod quoted_u64 {
+    use serde::de::{self, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S>(value: &u64, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<u64, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_any(QuotedU64Visitor)
+    }
+
+    struct QuotedU64Visitor;
+
+    impl<'de> Visitor<'de> for QuotedU64Visitor {
+        type Value = u64;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a quoted or plain unsigned integer")
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<u64, E>
+        where
+            E: de::Error,
+        {
+            value.parse().map_err(de::Error::custom)
+        }
+
+        fn visit_u64<E>(self, value: u64) -> Result<u64, E>
+        where
+            E: de::Error,
+        {
+            Ok(value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{NotSafe, SlashingProtection};
+    use super::*;
+
+    const INTERCHANGE: &str = r#"
+        {
+          "metadata": {
+            "interchange_format_version": "5",
+            "genesis_validators_root": "0x04700007fabc8282644aed6d1c7c9e21d38a03a0c4ba193f3afe428824b3a673"
+          },
+          "data": [
+            {
+              "pubkey": "0xb845089a1457f811bfc000588fbb4e713669be8ce060ea6be3c6ece09afc3794106c91ca73acda5e5457122d58723bed",
+              "signed_blocks": [
+                {
+                  "slot": "81952",
+                  "signing_root": "0x4ff6f743a43f3b4f95350831aeaf0a122a1a392922c45d804280284a69eb850b"
+                },
+                {
+                  "slot": "81951"
+                }
+              ],
+              "signed_attestations": [
+                {
+                  "source_epoch": "2290",
+                  "target_epoch": "3007",
+                  "signing_root": "0x587d6a4f59a58fe24f406e0502413e77fe1babddee641fda30034ed37ecc884d"
+                },
+                {
+                  "source_epoch": "2290",
+                  "target_epoch": "3008"
+                }
+              ]
+            }
+          ]
+        }"#;
+
+    const PUBKEY: &str = "0xb845089a1457f811bfc000588fbb4e713669be8ce060ea6be3c6ece09afc3794106c91ca73acda5e5457122d58723bed";
+
+    #[test]
+    fn should_parse_interchange() {
+        let interchange: Interchange = serde_json::from_str(INTERCHANGE).unwrap();
+        assert_eq!(interchange.metadata.interchange_format_version, 5);
+        assert_eq!(interchange.data.len(), 1);
+        assert_eq!(interchange.data[0].signed_blocks[0].slot, 81952);
+        assert_eq!(interchange.data[0].signed_blocks[1].signing_root, None);
+        assert_eq!(
+            interchange.data[0].signed_attestations[1].target_epoch,
+            3008
+        );
+    }
+
+    #[test]
+    fn should_refuse_imported_slashable_messages() {
+        let interchange: Interchange = serde_json::from_str(INTERCHANGE).unwrap();
+        let db = SlashingProtection::in_memory();
+        db.import_interchange(&interchange).unwrap();
+
+        assert!(db
+            .check_and_insert_block(PUBKEY, 81951, H256::from_slice(&[1; 32]))
+            .is_err());
+        assert!(db
+            .check_and_insert_attestation(PUBKEY, 2290, 3008, H256::from_slice(&[1; 32]))
+            .is_err());
+        assert!(db
+            .check_and_insert_attestation(PUBKEY, 2289, 3009, H256::from_slice(&[1; 32]))
+            .is_err());
+        assert!(db
+            .check_and_insert_block(PUBKEY, 81953, H256::from_slice(&[1; 32]))
+            .is_ok());
+    }
+
+    #[test]
+    fn should_round_trip_interchange() {
+        let interchange: Interchange = serde_json::from_str(INTERCHANGE).unwrap();
+        let db = SlashingProtection::in_memory();
+        db.import_interchange(&interchange).unwrap();
+        db.import_interchange(&interchange).unwrap();
+
+        let exported = db.export_interchange(None).unwrap();
+        assert_eq!(exported, interchange);
+        let json = serde_json::to_string(&exported).unwrap();
+        assert!(json.contains(r#""slot":"81952""#));
+    }
+
+    #[test]
+    fn should_refuse_other_chain() {
+        let interchange: Interchange = serde_json::from_str(INTERCHANGE).unwrap();
+        let db = SlashingProtection::in_memory();
+        db.import_interchange(&interchange).unwrap();
+
+        let mut other_chain = interchange.clone();
+        other_chain.metadata.genesis_validators_root = H256::zero();
+        assert!(db.import_interchange(&other_chain).is_err());
+    }
+
+    #[test]
+    fn should_refuse_attestations_below_pruned_history() {
+        let mut interchange: Interchange = serde_json::from_str(INTERCHANGE).unwrap();
+        // Minimal interchange files keep only the latest attestation of each validator.
+        interchange.data[0].signed_attestations.remove(0);
+        let db = SlashingProtection::in_memory();
+        db.import_interchange(&interchange).unwrap();
+        let root = H256::from_slice(&[1; 32]);
+
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 2289, 3007, root),
+            Err(NotSafe::PrunedAttestationSource)
+        );
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 2290, 3007, root),
+            Err(NotSafe::PrunedAttestationTarget)
+        );
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 2290, 3009, root),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn should_export_under_imported_genesis_validators_root() {
+        let interchange: Interchange = serde_json::from_str(INTERCHANGE).unwrap();
+        let db = SlashingProtection::in_memory();
+        db.import_interchange(&interchange).unwrap();
+        let genesis_validators_root = interchange.metadata.genesis_validators_root;
+
+        assert_eq!(
+            db.export_interchange(Some(H256::zero())),
+            Err(InterchangeError::GenesisValidatorsRootMismatch {
+                database: genesis_validators_root,
+                interchange: H256::zero(),
+            })
+        );
+        assert_eq!(
+            db.export_interchange(Some(genesis_validators_root)),
+            Ok(interchange)
+        );
+    }
+
+    #[test]
+    fn should_require_genesis_validators_root_for_export() {
+        let db = SlashingProtection::in_memory();
+        assert_eq!(
+            db.export_interchange(None),
+            Err(InterchangeError::MissingGenesisValidatorsRoot)
+        );
+        assert!(db.export_interchange(Some(H256::zero())).is_ok());
+    }
+}
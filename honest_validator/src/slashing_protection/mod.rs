@@ -0,0 +1,496 @@
+mod interchange;
+
+pub use interchange::{Interchange, InterchangeData, InterchangeMetadata};
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use types::primitives::{Epoch, Slot, H256};
+
+/// Reasons for refusing to sign a message.
+#[derive(PartialEq, Debug)]
+pub enum NotSafe {
+    DoubleBlockProposal,
+    PrunedBlockSlot,
+    DoubleVote,
+    SurroundingVote,
+    SurroundedVote,
+    /// Source epoch below the lowest one recorded, earlier history may have been pruned.
+    PrunedAttestationSource,
+    /// Target epoch not above the lowest one recorded, earlier history may have been pruned.
+    PrunedAttestationTarget,
+    InvalidAttestation,
+    IOError(String),
+}
+
+/// Reasons for refusing to import or export an interchange file.
+#[derive(PartialEq, Debug)]
+pub enum InterchangeError {
+    UnsupportedVersion(u64),
+    GenesisValidatorsRootMismatch { database: H256, interchange: H256 },
+    MissingGenesisValidatorsRoot,
+    Database(NotSafe),
+}
+
+/// A signed block, in the EIP-3076 interchange representation.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SignedBlock {
+    #[serde(with = "interchange::quoted_u64")]
+    pub slot: Slot,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_root: Option<H256>,
+}
+
+/// A signed attestation, in the EIP-3076 interchange representation.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SignedAttestation {
+    #[serde(with = "interchange::quoted_u64")]
+    pub source_epoch: Epoch,
+    #[serde(with = "interchange::quoted_u64")]
+    pub target_epoch: Epoch,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_root: Option<H256>,
+}
+
+/// Everything signed by a single validator key.
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+pub struct ValidatorHistory {
+    pub signed_blocks: Vec<SignedBlock>,
+    pub signed_attestations: Vec<SignedAttestation>,
+}
+
+impl ValidatorHistory {
+    pub fn highest_block_slot(&self) -> Option<Slot> {
+        self.signed_blocks.iter().map(|block| block.slot).max()
+    }
+
+    /// Returns `Ok(true)` if the block is safe to sign and new, `Ok(false)` if exactly
+    /// this block has already been signed.
+    fn check_block(&self, slot: Slot, signing_root: H256) -> Result<bool, NotSafe> {
+        if let Some(previous) = self.signed_blocks.iter().find(|block| block.slot == slot) {
+            return if previous.signing_root == Some(signing_root) {
+                Ok(false)
+            } else {
+                Err(NotSafe::DoubleBlockProposal)
+            };
+        }
+        match self.highest_block_slot() {
+            Some(highest) if slot < highest => Err(NotSafe::PrunedBlockSlot),
+            _ => Ok(true),
+        }
+    }
+
+    pub fn lowest_attestation_source(&self) -> Option<Epoch> {
+        self.signed_attestations
+            .iter()
+            .map(|attestation| attestation.source_epoch)
+            .min()
+    }
+
+    pub fn lowest_attestation_target(&self) -> Option<Epoch> {
+        self.signed_attestations
+            .iter()
+            .map(|attestation| attestation.target_epoch)
+            .min()
+    }
+
+    /// Same as `check_block`, but for attestations. Like block slots, epochs below those of the
+    /// recorded attestations are refused, as an interchange file may hold only the latest ones.
+    fn check_attestation(
+        &self,
+        source_epoch: Epoch,
+        target_epoch: Epoch,
+        signing_root: H256,
+    ) -> Result<bool, NotSafe> {
+        if source_epoch > target_epoch {
+            return Err(NotSafe::InvalidAttestation);
+        }
+        for previous in &self.signed_attestations {
+            if previous.target_epoch == target_epoch {
+                return if previous.signing_root == Some(signing_root) {
+                    Ok(false)
+                } else {
+                    Err(NotSafe::DoubleVote)
+                };
+            }
+            if source_epoch < previous.source_epoch && previous.target_epoch < target_epoch {
+                return Err(NotSafe::SurroundingVote);
+            }
+            if previous.source_epoch < source_epoch && target_epoch < previous.target_epoch {
+                return Err(NotSafe::SurroundedVote);
+            }
+        }
+        match self.lowest_attestation_source() {
+            Some(lowest) if source_epoch < lowest => return Err(NotSafe::PrunedAttestationSource),
+            _ => {}
+        }
+        match self.lowest_attestation_target() {
+            Some(lowest) if target_epoch <= lowest => Err(NotSafe::PrunedAttestationTarget),
+            _ => Ok(true),
+        }
+    }
+
+    /// Drops the records below the highest block slot and the highest attestation target. The
+    /// checks refuse anything below the remaining records, so the latest ones alone refuse every
+    /// message the full history would, and the database does not grow with every signature.
+    fn prune(&mut self) {
+        if let Some(highest) = self.highest_block_slot() {
+            self.signed_blocks.retain(|block| block.slot >= highest);
+        }
+        let highest_target = self
+            .signed_attestations
+            .iter()
+            .map(|attestation| attestation.target_epoch)
+            .max();
+        if let Some(highest) = highest_target {
+            self.signed_attestations
+                .retain(|attestation| attestation.target_epoch >= highest);
+        }
+    }
+
+    /// Adds records from another history, skipping the ones already known.
+    fn merge(&mut self, other: &ValidatorHistory) {
+        for block in &other.signed_blocks {
+            if !self.signed_blocks.contains(block) {
+                self.signed_blocks.push(block.clone());
+            }
+        }
+        for attestation in &other.signed_attestations {
+            if !self.signed_attestations.contains(attestation) {
+                self.signed_attestations.push(attestation.clone());
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
+struct SlashingDatabase {
+    genesis_validators_root: Option<H256>,
+    validators: HashMap<String, ValidatorHistory>,
+}
+
+/// Record of the latest block and attestation signed by the validator client, keyed by the
+/// `0x` prefixed validator public key. Every accepted message is flushed to disk before
+/// its signature is produced. Imported histories are kept whole until the validator signs again.
+pub struct SlashingProtection {
+    path: Option<PathBuf>,
+    database: Mutex<SlashingDatabase>,
+}
+
+impl SlashingProtection {
+    /// Opens the database at `path`, creating an empty one if the file does not exist yet.
+    pub fn open(path: &Path) -> Result<SlashingProtection, NotSafe> {
+        let database = if path.exists() {
+            let file = File::open(path).map_err(|e| NotSafe::IOError(e.to_string()))?;
+            serde_json::from_reader(BufReader::new(file))
+                .map_err(|e| NotSafe::IOError(e.to_string()))?
+        } else {
+            SlashingDatabase::default()
+        };
+        Ok(SlashingProtection {
+            path: Some(path.to_path_buf()),
+            database: Mutex::new(database),
+        })
+    }
+
+    /// Database which is never persisted, useful for tests.
+    pub fn in_memory() -> SlashingProtection {
+        SlashingProtection {
+            path: None,
+            database: Mutex::new(SlashingDatabase::default()),
+        }
+    }
+
+    pub fn validator_history(&self, pubkey: &str) -> Option<ValidatorHistory> {
+        self.database().validators.get(pubkey).cloned()
+    }
+
+    pub fn check_and_insert_block(
+        &self,
+        pubkey: &str,
+        slot: Slot,
+        signing_root: H256,
+    ) -> Result<(), NotSafe> {
+        let mut database = self.database();
+        let validator = database.validators.entry(pubkey.to_owned()).or_default();
+        if validator.check_block(slot, signing_root)? {
+            validator.signed_blocks.push(SignedBlock {
+                slot,
+                signing_root: Some(signing_root),
+            });
+            validator.prune();
+            self.persist(&database)?;
+        }
+        Ok(())
+    }
+
+    pub fn check_and_insert_attestation(
+        &self,
+        pubkey: &str,
+        source_epoch: Epoch,
+        target_epoch: Epoch,
+        signing_root: H256,
+    ) -> Result<(), NotSafe> {
+        let mut database = self.database();
+        let validator = database.validators.entry(pubkey.to_owned()).or_default();
+        if validator.check_attestation(source_epoch, target_epoch, signing_root)? {
+            validator.signed_attestations.push(SignedAttestation {
+                source_epoch,
+                target_epoch,
+                signing_root: Some(signing_root),
+            });
+            validator.prune();
+            self.persist(&database)?;
+        }
+        Ok(())
+    }
+
+    /// Merges the signing history from an EIP-3076 interchange file into the database.
+    pub fn import_interchange(&self, interchange: &Interchange) -> Result<(), InterchangeError> {
+        interchange.check_version()?;
+        let mut database = self.database();
+        let genesis_validators_root = interchange.metadata.genesis_validators_root;
+        match database.genesis_validators_root {
+            Some(root) if root != genesis_validators_root => {
+                return Err(InterchangeError::GenesisValidatorsRootMismatch {
+                    database: root,
+                    interchange: genesis_validators_root,
+                })
+            }
+            _ => database.genesis_validators_root = Some(genesis_validators_root),
+        }
+        for data in &interchange.data {
+            database
+                .validators
+                .entry(data.pubkey.to_lowercase())
+                .or_default()
+                .merge(&data.history());
+        }
+        self.persist(&database).map_err(InterchangeError::Database)
+    }
+
+    /// Exports the whole signing history. The genesis validators root recorded during a
+    /// previous import is used unless `genesis_validators_root` is given, which must then match it.
+    pub fn export_interchange(
+        &self,
+        genesis_validators_root: Option<H256>,
+    ) -> Result<Interchange, InterchangeError> {
+        let database = self.database();
+        let genesis_validators_root =
+            match (database.genesis_validators_root, genesis_validators_root) {
+                (Some(database), Some(interchange)) if database != interchange => {
+                    return Err(InterchangeError::GenesisValidatorsRootMismatch {
+                        database,
+                        interchange,
+                    })
+                }
+                (database, requested) => requested
+                    .or(database)
+                    .ok_or(InterchangeError::MissingGenesisValidatorsRoot)?,
+            };
+        let mut data: Vec<InterchangeData> = database
+            .validators
+            .iter()
+            .map(|(pubkey, history)| InterchangeData::new(pubkey.clone(), history))
+            .collect();
+        data.sort_by(|a, b| a.pubkey.cmp(&b.pubkey));
+        Ok(Interchange::new(genesis_validators_root, data))
+    }
+
+    /// Writes the database to disk and waits until it is stored. Waits for messages being checked
+    /// first, so nothing signed before the call is missing from the file.
+    pub fn flush(&self) -> Result<(), NotSafe> {
+        self.persist(&self.database())
+    }
+
+    /// Held while a message is checked and persisted, so concurrent duties are serialized.
+    fn database(&self) -> MutexGuard<'_, SlashingDatabase> {
+        self.database
+            .lock()
+            .expect("slashing protection lock is not poisoned")
+    }
+
+    fn persist(&self, database: &SlashingDatabase) -> Result<(), NotSafe> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let json = serde_json::to_string(database).map_err(|e| NotSafe::IOError(e.to_string()))?;
+        // Write to a temporary file first, so a crash can never leave a truncated database.
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path).map_err(|e| NotSafe::IOError(e.to_string()))?;
+        file.write_all(json.as_bytes())
+            .and_then(|()| file.sync_all())
+            .map_err(|e| NotSafe::IOError(e.to_string()))?;
+        fs::rename(&tmp_path, path).map_err(|e| NotSafe::IOError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    const PUBKEY: &str = "0xa99a76ed7796f7be22d5b7e85deeb7c5677e88e511e0b337618f8c4eb61349b4bf2d153f649f7b53359fe8b94a38e44c";
+
+    fn root(byte: u8) -> H256 {
+        H256::from_slice(&[byte; 32])
+    }
+
+    #[test]
+    fn should_refuse_double_block_proposal() {
+        let db = SlashingProtection::in_memory();
+        assert_eq!(db.check_and_insert_block(PUBKEY, 10, root(1)), Ok(()));
+        assert_eq!(db.check_and_insert_block(PUBKEY, 10, root(1)), Ok(()));
+        assert_eq!(
+            db.check_and_insert_block(PUBKEY, 10, root(2)),
+            Err(NotSafe::DoubleBlockProposal)
+        );
+        assert_eq!(
+            db.check_and_insert_block(PUBKEY, 9, root(3)),
+            Err(NotSafe::PrunedBlockSlot)
+        );
+        assert_eq!(db.check_and_insert_block(PUBKEY, 11, root(4)), Ok(()));
+        let history = db.validator_history(PUBKEY).unwrap();
+        assert_eq!(history.highest_block_slot(), Some(11));
+        assert_eq!(history.signed_blocks.len(), 1);
+    }
+
+    #[test]
+    fn should_keep_only_latest_messages() {
+        let db = SlashingProtection::in_memory();
+        for epoch in 1..10 {
+            db.check_and_insert_block(PUBKEY, epoch * 8, root(1))
+                .unwrap();
+            db.check_and_insert_attestation(PUBKEY, epoch - 1, epoch, root(1))
+                .unwrap();
+        }
+
+        let history = db.validator_history(PUBKEY).unwrap();
+        assert_eq!(
+            history.signed_blocks,
+            vec![SignedBlock {
+                slot: 72,
+                signing_root: Some(root(1)),
+            }]
+        );
+        assert_eq!(
+            history.signed_attestations,
+            vec![SignedAttestation {
+                source_epoch: 8,
+                target_epoch: 9,
+                signing_root: Some(root(1)),
+            }]
+        );
+        assert_eq!(
+            db.check_and_insert_block(PUBKEY, 64, root(2)),
+            Err(NotSafe::PrunedBlockSlot)
+        );
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 7, 8, root(2)),
+            Err(NotSafe::PrunedAttestationSource)
+        );
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 8, 8, root(2)),
+            Err(NotSafe::PrunedAttestationTarget)
+        );
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 6, 10, root(2)),
+            Err(NotSafe::SurroundingVote)
+        );
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 9, 10, root(2)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn should_refuse_double_vote() {
+        let db = SlashingProtection::in_memory();
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 1, 2, root(1)),
+            Ok(())
+        );
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 1, 2, root(1)),
+            Ok(())
+        );
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 1, 2, root(2)),
+            Err(NotSafe::DoubleVote)
+        );
+    }
+
+    #[test]
+    fn should_refuse_surround_votes() {
+        let db = SlashingProtection::in_memory();
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 3, 4, root(1)),
+            Ok(())
+        );
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 2, 5, root(2)),
+            Err(NotSafe::SurroundingVote)
+        );
+        assert!(db
+            .check_and_insert_attestation(PUBKEY, 1, 10, root(3))
+            .is_err());
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 4, 8, root(4)),
+            Ok(())
+        );
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 5, 7, root(5)),
+            Err(NotSafe::SurroundedVote)
+        );
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 6, 5, root(6)),
+            Err(NotSafe::InvalidAttestation)
+        );
+    }
+
+    #[test]
+    fn should_persist_history() {
+        let path = env::temp_dir().join("should_persist_slashing_protection_history.json");
+        let _ = fs::remove_file(&path);
+        {
+            let db = SlashingProtection::open(&path).unwrap();
+            db.check_and_insert_block(PUBKEY, 5, root(1)).unwrap();
+            db.check_and_insert_attestation(PUBKEY, 0, 1, root(2))
+                .unwrap();
+        }
+        let db = SlashingProtection::open(&path).unwrap();
+        assert_eq!(
+            db.check_and_insert_block(PUBKEY, 5, root(3)),
+            Err(NotSafe::DoubleBlockProposal)
+        );
+        assert_eq!(
+            db.check_and_insert_attestation(PUBKEY, 0, 1, root(4)),
+            Err(NotSafe::DoubleVote)
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn should_flush_database() {
+        let path = env::temp_dir().join("should_flush_slashing_protection_database.json");
+        let _ = fs::remove_file(&path);
+        let db = SlashingProtection::open(&path).unwrap();
+        assert!(!path.exists());
+
+        db.flush().unwrap();
+
+        assert!(path.exists());
+        assert_eq!(
+            SlashingProtection::open(&path)
+                .unwrap()
+                .validator_history(PUBKEY),
+            None
+        );
+        fs::remove_file(&path).unwrap();
+        assert_eq!(SlashingProtection::in_memory().flush(), Ok(()));
+    }
+}
@@ -0,0 +1,205 @@
+use async_trait::async_trait;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use types::primitives::Slot;
+
+/// Source of wall-clock time, expressed in slots counted from genesis.
+///
+/// All durations are measured from the UNIX epoch, so `genesis_duration` is simply the
+/// `genesis_time` of the beacon state.
+#[async_trait]
+pub trait SlotClock: Send + Sync {
+    fn new(genesis_time: u64, slot_duration: Duration) -> Self
+    where
+        Self: Sized;
+
+    /// Current time as seen by this clock, `None` if it can not be read.
+    fn now_duration(&self) -> Option<Duration>;
+
+    /// Resolves once the clock reaches `target`, immediately if it has already passed.
+    async fn wait_until(&self, target: Duration);
+
+    fn genesis_duration(&self) -> Duration;
+
+    fn slot_duration(&self) -> Duration;
+
+    /// Current slot, `None` before genesis.
+    fn now(&self) -> Option<Slot> {
+        let now = self.now_duration()?;
+        let since_genesis = now.checked_sub(self.genesis_duration())?;
+        Some(since_genesis.as_millis() as u64 / self.slot_duration().as_millis() as u64)
+    }
+
+    fn start_of(&self, slot: Slot) -> Duration {
+        let slot_millis = self.slot_duration().as_millis() as u64;
+        self.genesis_duration() + Duration::from_millis(slot_millis * slot)
+    }
+
+    /// Point in time at which attestations for `slot` should be produced, one third into the slot.
+    fn attestation_point_of(&self, slot: Slot) -> Duration {
+        self.start_of(slot) + self.slot_duration() / 3
+    }
+
+    /// Time left until `slot` starts, `None` if the slot has already started.
+    fn duration_to_slot(&self, slot: Slot) -> Option<Duration> {
+        let now = self.now_duration()?;
+        self.start_of(slot).checked_sub(now)
+    }
+
+    async fn wait_until_slot(&self, slot: Slot) {
+        self.wait_until(self.start_of(slot)).await;
+    }
+
+    async fn wait_until_attestation_point(&self, slot: Slot) {
+        self.wait_until(self.attestation_point_of(slot)).await;
+    }
+}
+
+pub struct SystemTimeSlotClock {
+    genesis_duration: Duration,
+    slot_duration: Duration,
+}
+
+#[async_trait]
+impl SlotClock for SystemTimeSlotClock {
+    fn new(genesis_time: u64, slot_duration: Duration) -> Self {
+        SystemTimeSlotClock {
+            genesis_duration: Duration::from_secs(genesis_time),
+            slot_duration,
+        }
+    }
+
+    fn now_duration(&self) -> Option<Duration> {
+        SystemTime::now().duration_since(UNIX_EPOCH).ok()
+    }
+
+    async fn wait_until(&self, target: Duration) {
+        if let Some(remaining) = self.now_duration().and_then(|now| target.checked_sub(now)) {
+            tokio::time::sleep(remaining).await;
+        }
+    }
+
+    fn genesis_duration(&self) -> Duration {
+        self.genesis_duration
+    }
+
+    fn slot_duration(&self) -> Duration {
+        self.slot_duration
+    }
+}
+
+/// Clock which only moves when told to. Waiting on it advances the time instantly,
+/// which lets the service loop run in tests without sleeping.
+pub struct ManualSlotClock {
+    genesis_duration: Duration,
+    slot_duration: Duration,
+    current_time: RwLock<Duration>,
+}
+
+impl ManualSlotClock {
+    pub fn set_current_time(&self, time: Duration) {
+        *self.current_time.write().unwrap() = time;
+    }
+
+    pub fn set_slot(&self, slot: Slot) {
+        self.set_current_time(self.start_of(slot));
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut current_time = self.current_time.write().unwrap();
+        *current_time += duration;
+    }
+}
+
+#[async_trait]
+impl SlotClock for ManualSlotClock {
+    fn new(genesis_time: u64, slot_duration: Duration) -> Self {
+        let genesis_duration = Duration::from_secs(genesis_time);
+        ManualSlotClock {
+            genesis_duration,
+            slot_duration,
+            current_time: RwLock::new(genesis_duration),
+        }
+    }
+
+    fn now_duration(&self) -> Option<Duration> {
+        Some(*self.current_time.read().unwrap())
+    }
+
+    async fn wait_until(&self, target: Duration) {
+        let mut current_time = self.current_time.write().unwrap();
+        if *current_time < target {
+            *current_time = target;
+        }
+    }
+
+    fn genesis_duration(&self) -> Duration {
+        self.genesis_duration
+    }
+
+    fn slot_duration(&self) -> Duration {
+        self.slot_duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENESIS_TIME: u64 = 1_575_910_257;
+
+    fn clock() -> ManualSlotClock {
+        ManualSlotClock::new(GENESIS_TIME, Duration::from_millis(12000))
+    }
+
+    #[test]
+    fn should_start_at_genesis_slot() {
+        let clock = clock();
+        assert_eq!(clock.now(), Some(0));
+    }
+
+    #[test]
+    fn should_not_have_slot_before_genesis() {
+        let clock = clock();
+        clock.set_current_time(Duration::from_secs(GENESIS_TIME - 1));
+        assert_eq!(clock.now(), None);
+        assert_eq!(clock.duration_to_slot(0), Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn should_derive_slot_from_elapsed_time() {
+        let clock = clock();
+        clock.advance(Duration::from_millis(11999));
+        assert_eq!(clock.now(), Some(0));
+        clock.advance(Duration::from_millis(1));
+        assert_eq!(clock.now(), Some(1));
+        clock.set_slot(42);
+        assert_eq!(clock.now(), Some(42));
+        assert_eq!(
+            clock.now_duration(),
+            Some(Duration::from_secs(GENESIS_TIME + 42 * 12))
+        );
+    }
+
+    #[tokio::test]
+    async fn should_wait_until_attestation_point() {
+        let clock = clock();
+        clock.set_slot(3);
+        clock.wait_until_attestation_point(3).await;
+        assert_eq!(clock.now(), Some(3));
+        assert_eq!(
+            clock.now_duration(),
+            Some(Duration::from_secs(GENESIS_TIME + 3 * 12 + 4))
+        );
+        assert_eq!(clock.duration_to_slot(3), None);
+        assert_eq!(clock.duration_to_slot(4), Some(Duration::from_secs(8)));
+    }
+
+    #[tokio::test]
+    async fn should_not_go_back_in_time() {
+        let clock = clock();
+        clock.set_slot(5);
+        clock.wait_until_slot(2).await;
+        assert_eq!(clock.now(), Some(5));
+    }
+}
@@ -1,14 +1,28 @@
 use crate::attestation_producer::AttestationProducer;
-use crate::beacon_node::{BasicBeaconNode, BeaconNode, Error};
+use crate::beacon_node::{BeaconNode, DutyInfo, Error};
 use crate::block_producer::produce_block;
-use bls::{PublicKeyBytes, SecretKey};
+use crate::duties::compute_duties;
+use crate::eth1_data_source::{Eth1DataSource, InMemoryEth1DataSource};
+use crate::metrics::Metrics;
+use crate::rest_client::CircuitState;
+use crate::slashing_protection::SlashingProtection;
+use crate::slot_clock::{SlotClock, SystemTimeSlotClock};
+use bls::{Keypair, PublicKey, PublicKeyBytes, SecretKey};
+use futures::future;
 use hex;
+use log::{error, info, warn};
 use serde::Deserialize;
-use std::{thread, time};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::Duration;
+use tokio::sync::watch;
+use typenum::Unsigned;
+use types::beacon_state::BeaconState;
+use types::chain_spec::ChainSpec;
 use types::config::Config as EthConfig;
-use types::primitives::{Epoch, ValidatorIndex};
-
-const SLOTS_PER_EPOCH: u64 = 8;
+use types::helper_functions::is_active_validator;
+use types::primitives::{Epoch, Slot, ValidatorIndex};
 
 #[derive(Deserialize)]
 pub struct KeysPair {
@@ -16,165 +30,529 @@ pub struct KeysPair {
     public: String,
 }
 
+/// Stage of a validator in the registry of the beacon state.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ValidatorStatus {
+    /// Not in the registry, the deposit of the validator has not been processed yet.
+    Unknown,
+    PendingActivation(ValidatorIndex),
+    Active(ValidatorIndex),
+    /// Exited, the validator has no more duties.
+    Exited(ValidatorIndex),
+}
+
+impl ValidatorStatus {
+    /// Status of the validator at `index` of the registry of `state`, as of `epoch`.
+    fn at_index<C: EthConfig>(state: &BeaconState<C>, index: ValidatorIndex, epoch: Epoch) -> Self {
+        match state.validators.get(index as usize) {
+            None => ValidatorStatus::Unknown,
+            Some(validator) if epoch < validator.activation_epoch => {
+                ValidatorStatus::PendingActivation(index)
+            }
+            Some(validator) if is_active_validator(validator, epoch) => {
+                ValidatorStatus::Active(index)
+            }
+            Some(_) => ValidatorStatus::Exited(index),
+        }
+    }
+
+    pub fn index(self) -> Option<ValidatorIndex> {
+        match self {
+            ValidatorStatus::Unknown => None,
+            ValidatorStatus::PendingActivation(index)
+            | ValidatorStatus::Active(index)
+            | ValidatorStatus::Exited(index) => Some(index),
+        }
+    }
+}
+
 pub struct Validator {
     public_key: PublicKeyBytes,
-    index: ValidatorIndex,
+    status: ValidatorStatus,
     public_key_str: String,
     private_key: SecretKey,
 }
 
-pub struct Service<C: EthConfig> {
-    beacon_node: BasicBeaconNode,
-    validators: Vec<Validator>,
-    attestation_producer: AttestationProducer<C>,
+pub struct Service<C: EthConfig, B: BeaconNode<C>> {
+    beacon_node: Arc<B>,
+    validators: RwLock<Vec<Validator>>,
+    /// Serialized public keys of the registry entries seen so far. The registry only grows, so
+    /// entries are serialized once rather than with every state.
+    registry_pubkeys: RwLock<Vec<Vec<u8>>>,
+    attestation_producer: AttestationProducer<C, B>,
+    eth1_data_source: Box<dyn Eth1DataSource>,
+    graffiti: Option<[u8; 32]>,
+    run_duration: Option<Duration>,
+    metrics: Arc<Metrics>,
+    shutdown: watch::Receiver<bool>,
 }
 
-impl<C: EthConfig> Service<C> {
-    pub fn new(eth_config: C, validators_keys: Vec<KeysPair>) -> Service<C> {
-        let validators = parse_validators(validators_keys).unwrap();
+impl<C: EthConfig, B: BeaconNode<C>> Service<C, B> {
+    pub fn new(
+        eth_config: C,
+        beacon_node: B,
+        slashing_protection: SlashingProtection,
+        keypairs: Vec<Keypair>,
+    ) -> Service<C, B> {
+        let validators = RwLock::new(keypairs_to_validators(keypairs));
+        let beacon_node = Arc::new(beacon_node);
         let attestation_producer = AttestationProducer {
             config: eth_config,
-            beacon_node: BasicBeaconNode::new(),
+            spec: ChainSpec::from_config::<C>(),
+            beacon_node: Arc::clone(&beacon_node),
+            slashing_protection: Arc::new(slashing_protection),
         };
         Service {
-            beacon_node: BasicBeaconNode::new(),
+            beacon_node,
             validators,
+            registry_pubkeys: RwLock::new(Vec::new()),
             attestation_producer,
+            eth1_data_source: Box::new(InMemoryEth1DataSource::default()),
+            graffiti: None,
+            run_duration: None,
+            metrics: Arc::new(Metrics::default()),
+            shutdown: watch::channel(false).1,
         }
     }
 
-    pub fn start(&self) -> Result<(), String> {
-        let mut counter = 0u128;
+    pub fn with_eth1_data_source(mut self, eth1_data_source: Box<dyn Eth1DataSource>) -> Self {
+        self.eth1_data_source = eth1_data_source;
+        self
+    }
 
-        loop {
-            println!("Fetching current beacon state...");
-            let beacon_state = match &(self.beacon_node.get_state()) {
-                Some(state) => state,
-                None => return Err(String::from("can not get beacon state")),
-            };
-            let epoch: Epoch = beacon_state.fork.epoch;
-            let mut validator_pubkeys = Vec::new();
+    /// Replaces the constants of the `C` preset with those of the chain the validators are on.
+    pub fn with_chain_spec(mut self, spec: ChainSpec) -> Self {
+        self.attestation_producer.spec = spec;
+        self
+    }
 
-            for validator in &self.validators {
-                validator_pubkeys.push(validator.public_key.clone());
-            }
+    /// Graffiti of proposed blocks, instead of the one chosen by the beacon node.
+    pub fn with_graffiti(mut self, graffiti: [u8; 32]) -> Self {
+        self.graffiti = Some(graffiti);
+        self
+    }
 
-            let duties = self.beacon_node.get_duties(validator_pubkeys, epoch);
-            let mut current_slot = beacon_state.slot % SLOTS_PER_EPOCH;
-            loop {
-                println!(
-                    "Working at slot: {}...",
-                    current_slot
-                );
-                for duty in duties.iter() {
-                    if duty.attestation_slot == current_slot {
-                        let validator_index =
-                            self.get_validator_index(&duty.validator_pubkey).unwrap();
-                        let private_key = (&self).get_private_key(validator_index);
-                        let test = private_key.clone();
-                        //print_privates(test, &validator_index);
-                        println!("\tvalidator {} should attest block", validator_index);
-                        let attestation = self.attestation_producer.get_attestation(
-                            &beacon_state,
-                            duty.attestation_committee_index,
-                            duty.attestation_committee_position,
-                            private_key,
-                        );
+    /// Stops the service at the end of the epoch in which `run_duration` has passed, instead of
+    /// running until the process is stopped.
+    pub fn with_run_duration(mut self, run_duration: Duration) -> Self {
+        self.run_duration = Some(run_duration);
+        self
+    }
 
-                        let attestation_result = match attestation {
-                            Some(data) => self.beacon_node.publish_attestation(data),
-                            None => {
-                                println!(
-                                    "Failed to build attestation data, for validator: {}",
-                                    validator_index
-                                );
-                                Err(Error::AttestionPublishingError)
-                            }
-                        };
-                        match attestation_result {
-                            Err(e) => match e {
-                                Error::AttestionPublishingError => {
-                                    println!("Attestation publishing error in API")
-                                }
-                                _ => println!("Unknown error in API"),
-                            },
-                            _ => (),
-                        }
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Stops the service once `shutdown` turns true. Duties already being performed are finished
+    /// and the slashing protection database is flushed before `start` returns.
+    pub fn with_shutdown(mut self, shutdown: watch::Receiver<bool>) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Performs the duties of the validators until shut down, or until the run duration has
+    /// passed. Beacon node outages are waited out.
+    pub async fn start(&self) -> Result<(), String> {
+        info!("Fetching current beacon state...");
+        let retry_delay = self.attestation_producer.spec.slot_duration();
+        let genesis_time = loop {
+            match self.beacon_node.get_state().await {
+                Ok(state) => break state.genesis_time,
+                Err(e) => {
+                    warn!(
+                        "Can not get beacon state, retrying in {:?}: {:?}",
+                        retry_delay, e
+                    );
+                    if self
+                        .until_shutdown(tokio::time::sleep(retry_delay))
+                        .await
+                        .is_none()
+                    {
+                        return self.end();
                     }
-                    match duty.block_proposal_slot {
-                        Some(slot) => {
-                            if slot == current_slot {
-                                let validator_index =
-                                    self.get_validator_index(&duty.validator_pubkey).unwrap();
-                                let private_key = (&self).get_private_key(validator_index);
-                                //produce_block(&self.beacon_node, beacon_state, private_key, slot);
-                                println!("\tvalidator {} should propose block", validator_index);
-                            }
-                        }
-                        _ => (),
-                    };
                 }
-                let slot_duration = time::Duration::from_millis(12000);
-                thread::sleep(slot_duration);
-                current_slot = current_slot + 1;
-                if current_slot > SLOTS_PER_EPOCH {
+            }
+        };
+        let slot_clock = SystemTimeSlotClock::new(genesis_time, retry_delay);
+        self.run(&slot_clock).await
+    }
+
+    async fn run<T: SlotClock>(&self, slot_clock: &T) -> Result<(), String> {
+        let slots_per_epoch = C::SlotsPerEpoch::to_u64();
+        let stop_at = match (self.run_duration, slot_clock.now_duration()) {
+            (Some(run_duration), Some(now)) => Some(now + run_duration),
+            _ => None,
+        };
+
+        while !self.is_shutting_down() {
+            if let (Some(stop_at), Some(now)) = (stop_at, slot_clock.now_duration()) {
+                if now >= stop_at {
                     break;
                 }
             }
-            counter = counter + 1;
-            if counter > 65 {
-                break;
+            let current_slot = match slot_clock.now() {
+                Some(slot) => slot,
+                None => {
+                    info!("Waiting for genesis...");
+                    self.until_shutdown(slot_clock.wait_until_slot(0)).await;
+                    continue;
+                }
+            };
+            let epoch: Epoch = current_slot / slots_per_epoch;
+            // The beacon state and duties are fetched again at the start of every epoch, or at
+            // the next slot if fetching them fails.
+            let next_attempt_slot = match self.run_epoch(slot_clock, current_slot, epoch).await {
+                Ok(()) => (epoch + 1) * slots_per_epoch,
+                Err(e) if e.is_retryable() => {
+                    warn!(
+                        "Beacon node request failed at slot {}, retrying next slot: {:?}",
+                        current_slot, e
+                    );
+                    Metrics::increment(&self.metrics.beacon_node_errors);
+                    current_slot + 1
+                }
+                Err(e) => {
+                    error!(
+                        "Beacon node request failed, skipping epoch {}: {:?}",
+                        epoch, e
+                    );
+                    Metrics::increment(&self.metrics.beacon_node_errors);
+                    (epoch + 1) * slots_per_epoch
+                }
+            };
+            self.until_shutdown(slot_clock.wait_until_slot(next_attempt_slot))
+                .await;
+        }
+        self.end()
+    }
+
+    /// Performs the duties of `epoch` from `current_slot` on. Fails if the beacon state or the
+    /// duties can not be fetched, failures of individual duties are only logged. Duties of
+    /// different validators in the same slot are performed concurrently. Returns early on
+    /// shutdown, but never while duties are being performed.
+    async fn run_epoch<T: SlotClock>(
+        &self,
+        slot_clock: &T,
+        current_slot: Slot,
+        epoch: Epoch,
+    ) -> Result<(), Error> {
+        let slots_per_epoch = C::SlotsPerEpoch::to_u64();
+        let beacon_state = self.beacon_node.get_state().await?;
+        self.update_validator_statuses(&beacon_state, epoch);
+        let validator_pubkeys: Vec<PublicKeyBytes> = self
+            .validators()
+            .iter()
+            .filter(|validator| matches!(validator.status, ValidatorStatus::Active(_)))
+            .map(|validator| validator.public_key.clone())
+            .collect();
+
+        let duties = if validator_pubkeys.is_empty() {
+            info!("No active validators at epoch {}", epoch);
+            Vec::new()
+        } else {
+            self.beacon_node
+                .get_duties(validator_pubkeys.clone(), epoch)
+                .await?
+        };
+        if beacon_state.get_current_epoch() == epoch {
+            if let Ok(local_duties) = compute_duties(
+                &self.attestation_producer.spec,
+                &beacon_state,
+                &validator_pubkeys,
+                epoch,
+            ) {
+                for duty in duties.iter() {
+                    if !local_duties.contains(duty) {
+                        warn!(
+                            "Duties of validator {} differ from the beacon state",
+                            duty.validator_pubkey
+                        );
+                    }
+                }
             }
         }
-        &self.end();
+        let next_epoch_start_slot = (epoch + 1) * slots_per_epoch;
+        for slot in current_slot..next_epoch_start_slot {
+            if self
+                .until_shutdown(slot_clock.wait_until_slot(slot))
+                .await
+                .is_none()
+            {
+                return Ok(());
+            }
+            if slot_clock.now() != Some(slot) {
+                warn!("Missed slot: {}, clock is ahead", slot);
+                Metrics::increment(&self.metrics.missed_slots);
+                continue;
+            }
+            if self.beacon_node.circuit_state() == CircuitState::Open {
+                warn!(
+                    "Beacon node is unavailable, missed duties at slot: {}",
+                    slot
+                );
+                Metrics::increment(&self.metrics.missed_slots);
+                continue;
+            }
+            info!("Working at slot: {}...", slot);
+            let proposals = duties
+                .iter()
+                .filter(|duty| duty.block_proposal_slot == Some(slot))
+                .map(|duty| self.propose(duty, &beacon_state, slot));
+            future::join_all(proposals).await;
+
+            if self
+                .until_shutdown(slot_clock.wait_until_attestation_point(slot))
+                .await
+                .is_none()
+            {
+                return Ok(());
+            }
+            let attestations = duties
+                .iter()
+                .filter(|duty| duty.attestation_slot == slot)
+                .map(|duty| self.attest(duty, &beacon_state));
+            future::join_all(attestations).await;
+        }
         Ok(())
     }
 
-    fn end(&self) {
-        println!("End service work.");
+    /// Looks the validators up in the registry of `state`. Validators are only assigned an index
+    /// once their deposit is processed, so those not found are looked up again with every new
+    /// state, as are the statuses of those pending activation.
+    fn update_validator_statuses(&self, state: &BeaconState<C>, epoch: Epoch) {
+        let mut validators = self
+            .validators
+            .write()
+            .expect("validators lock is not poisoned");
+        let mut unknown: HashMap<Vec<u8>, &mut Validator> = HashMap::new();
+        for validator in validators.iter_mut() {
+            let status = match validator.status.index() {
+                Some(index) => ValidatorStatus::at_index(state, index, epoch),
+                None => ValidatorStatus::Unknown,
+            };
+            if status != validator.status {
+                info!("Validator {} is now {:?}", validator.public_key_str, status);
+                validator.status = status;
+            }
+            if status == ValidatorStatus::Unknown {
+                unknown.insert(validator.public_key.as_bytes(), validator);
+            }
+        }
+        if unknown.is_empty() {
+            return;
+        }
+
+        let mut registry_pubkeys = self
+            .registry_pubkeys
+            .write()
+            .expect("registry public keys lock is not poisoned");
+        // The registry of a chain only grows, so the cache is checked against `state` where they
+        // overlap last and where a validator is found. A mismatch means the beacon node is on
+        // another chain now.
+        let overlap = registry_pubkeys.len().min(state.validators.len());
+        let mut stale = overlap > 0
+            && registry_pubkeys[overlap - 1] != state.validators[overlap - 1].pubkey.as_bytes();
+        let mut found = Vec::new();
+        if !stale {
+            found = find_in_registry(&mut registry_pubkeys, state, &unknown);
+            stale = found
+                .iter()
+                .any(|&index| registry_pubkeys[index] != state.validators[index].pubkey.as_bytes());
+        }
+        if stale {
+            warn!("Registry of the beacon state does not match the cached one, rebuilding it");
+            registry_pubkeys.clear();
+            found = find_in_registry(&mut registry_pubkeys, state, &unknown);
+        }
+        for index in found {
+            if let Some(validator) = unknown.remove(&registry_pubkeys[index]) {
+                let status = ValidatorStatus::at_index(state, index as ValidatorIndex, epoch);
+                info!("Validator {} is now {:?}", validator.public_key_str, status);
+                validator.status = status;
+            }
+        }
+        for validator in unknown.values() {
+            info!(
+                "Validator {} is not in the registry yet, waiting for its deposit",
+                validator.public_key_str
+            );
+        }
     }
 
-    fn get_validator_index(&self, pubkey: &String) -> Option<ValidatorIndex> {
-        for validator in &self.validators {
-            if validator.public_key_str == *pubkey {
-                return Some(validator.index.clone());
+    fn validators(&self) -> RwLockReadGuard<'_, Vec<Validator>> {
+        self.validators
+            .read()
+            .expect("validators lock is not poisoned")
+    }
+
+    fn is_shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Output of `future`, or `None` if the service is shut down before it completes.
+    async fn until_shutdown<F: Future>(&self, future: F) -> Option<F::Output> {
+        let mut shutdown = self.shutdown.clone();
+        let shutdown_requested = async move {
+            while !*shutdown.borrow() {
+                // Without a sender the service can not be shut down.
+                if shutdown.changed().await.is_err() {
+                    future::pending::<()>().await;
+                }
             }
+        };
+        tokio::select! {
+            output = future => Some(output).filter(|_| !self.is_shutting_down()),
+            () = shutdown_requested => None,
         }
-        None
     }
 
-    fn get_private_key(&self, validator_index: ValidatorIndex) -> SecretKey {
-        let mut privkey: SecretKey = SecretKey::random();
-        for v in &self.validators {
-            if v.index == validator_index {
-                privkey = v.private_key.clone();
-                break;
+    async fn propose(&self, duty: &DutyInfo, beacon_state: &BeaconState<C>, slot: Slot) {
+        let (validator_index, private_key) = match self.get_signer(&duty.validator_pubkey) {
+            Some(signer) => signer,
+            None => return,
+        };
+        info!("validator {} should propose block", validator_index);
+        if produce_block(
+            &*self.beacon_node,
+            &self.attestation_producer.spec,
+            &self.attestation_producer.slashing_protection,
+            &*self.eth1_data_source,
+            self.graffiti,
+            &duty.validator_pubkey,
+            beacon_state,
+            private_key,
+            slot,
+        )
+        .await
+        .is_some()
+        {
+            Metrics::increment(&self.metrics.blocks_proposed);
+        } else {
+            error!(
+                "Failed to propose block, for validator: {}",
+                validator_index
+            );
+            Metrics::increment(&self.metrics.block_proposals_failed);
+        }
+    }
+
+    async fn attest(&self, duty: &DutyInfo, beacon_state: &BeaconState<C>) {
+        let (validator_index, private_key) = match self.get_signer(&duty.validator_pubkey) {
+            Some(signer) => signer,
+            None => return,
+        };
+        info!("validator {} should attest block", validator_index);
+        let attestation = self.attestation_producer.get_attestation(
+            beacon_state,
+            duty.attestation_committee_index,
+            duty.attestation_committee_position,
+            &duty.validator_pubkey,
+            private_key,
+        );
+
+        match attestation {
+            Some(attestation) => {
+                if let Err(e) = self.beacon_node.publish_attestation(attestation).await {
+                    error!(
+                        "Failed to publish attestation, for validator {}: {:?}",
+                        validator_index, e
+                    );
+                    Metrics::increment(&self.metrics.attestations_failed);
+                } else {
+                    Metrics::increment(&self.metrics.attestations_published);
+                }
+            }
+            None => {
+                error!(
+                    "Failed to build attestation data, for validator: {}",
+                    validator_index
+                );
+                Metrics::increment(&self.metrics.attestations_failed);
             }
         }
-        privkey
+    }
+
+    /// Stores everything signed, so the validators can be moved to another client safely.
+    fn end(&self) -> Result<(), String> {
+        info!("End service work.");
+        self.attestation_producer
+            .slashing_protection
+            .flush()
+            .map_err(|e| format!("can not flush slashing protection database: {:?}", e))
+    }
+
+    fn get_validator_index(&self, pubkey: &str) -> Option<ValidatorIndex> {
+        self.validators()
+            .iter()
+            .find(|validator| validator.public_key_str == pubkey)
+            .and_then(|validator| validator.status.index())
+    }
+
+    /// Index and secret key of the validator with `pubkey`, `None` if its index is not known.
+    fn get_signer(&self, pubkey: &str) -> Option<(ValidatorIndex, SecretKey)> {
+        let signer = self.get_validator_index(pubkey).and_then(|index| {
+            self.validators()
+                .iter()
+                .find(|validator| validator.public_key_str == pubkey)
+                .map(|validator| (index, validator.private_key.clone()))
+        });
+        if signer.is_none() {
+            error!("Got a duty of validator {} with unknown index", pubkey);
+        }
+        signer
     }
 }
 
-fn parse_validators(keys: Vec<KeysPair>) -> Result<Vec<Validator>, String> {
+/// Caches the public keys of the registry entries of `state` not cached yet, and returns the
+/// registry indices of the ones in `wanted`.
+fn find_in_registry<C: EthConfig, V>(
+    registry_pubkeys: &mut Vec<Vec<u8>>,
+    state: &BeaconState<C>,
+    wanted: &HashMap<Vec<u8>, V>,
+) -> Vec<usize> {
+    let cached = registry_pubkeys.len();
+    registry_pubkeys.extend(
+        state
+            .validators
+            .iter()
+            .skip(cached)
+            .map(|registry_entry| registry_entry.pubkey.as_bytes()),
+    );
+    registry_pubkeys
+        .iter()
+        .take(state.validators.len())
+        .enumerate()
+        .filter(|(_, pubkey)| wanted.contains_key(*pubkey))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// Parses plaintext hex keys. Only meant for mock data, real keys are loaded from keystores.
+pub fn parse_keypairs(keys: Vec<KeysPair>) -> Result<Vec<Keypair>, String> {
     const PREFIX: &str = "0x";
+    const SECRET_KEY_LENGTH: usize = 48;
     let mut result = Vec::new();
-    for index in 0..keys.len() {
-        let public = &keys[index].public;
-        let private = &keys[index].private;
-        if public.starts_with(PREFIX) && private.starts_with(PREFIX) {
-            let pubkey_bytes = hex::decode(public.trim_start_matches(PREFIX)).unwrap();
-            let public_key = PublicKeyBytes::from_bytes(pubkey_bytes.as_slice()).unwrap();
-            let private_key_bytes = hex::decode(private.trim_start_matches(PREFIX)).unwrap();
-            let mut bytes = vec![0; 48 - private_key_bytes.len()];
+    for key in keys.iter() {
+        if key.public.starts_with(PREFIX) && key.private.starts_with(PREFIX) {
+            let pubkey_bytes = hex::decode(key.public.trim_start_matches(PREFIX))
+                .map_err(|e| format!("Failed to decode public key hex: {:?}", e))?;
+            let pk = PublicKey::from_bytes(pubkey_bytes.as_slice())
+                .map_err(|e| format!("Failed to decode bytes into public key: {:?}", e))?;
+            let private_key_bytes = hex::decode(key.private.trim_start_matches(PREFIX))
+                .map_err(|e| format!("Failed to decode secret key hex: {:?}", e))?;
+            if private_key_bytes.len() > SECRET_KEY_LENGTH {
+                return Err(format!(
+                    "Secret key must be at most {} bytes, got {}",
+                    SECRET_KEY_LENGTH,
+                    private_key_bytes.len()
+                ));
+            }
+            let mut bytes = vec![0; SECRET_KEY_LENGTH - private_key_bytes.len()];
             bytes.extend_from_slice(&private_key_bytes[..]);
-            let private_key = SecretKey::from_bytes(&bytes)
+            let sk = SecretKey::from_bytes(&bytes)
                 .map_err(|e| format!("Failed to decode bytes into secret key: {:?}", e))?;
-            result.push(Validator {
-                private_key,
-                public_key,
-                index: index as ValidatorIndex,
-                public_key_str: public.to_owned(),
-            });
+            result.push(Keypair { sk, pk });
         } else {
             return Err(String::from("Public key must have a 0x prefix"));
         }
@@ -182,42 +560,41 @@ fn parse_validators(keys: Vec<KeysPair>) -> Result<Vec<Validator>, String> {
     Ok(result)
 }
 
-fn print_privates(pk: SecretKey, vi: &ValidatorIndex) {
-    if *vi != 9 {
-        return ();
-    }
-    let test_val: &str = r#"
-        [{
-            "private":"0x2b3b88a041168a1c4cd04bdd8de7964fd35238f95442dc678514f9dadb81ec34",
-            "public":"0x9893413c00283a3f9ed9fd9845dda1cea38228d22567f9541dccc357e54a2d6a6e204103c92564cbc05f4905ac7c493a"
-          }
-        ]"#;
-    let keys: Vec<KeysPair> = serde_json::from_str(test_val).unwrap();
-    let v2 = &parse_validators(keys).unwrap()[0];
-    let pk2 = v2.private_key.clone().as_raw().x.w;
-
-    let pk = pk.as_raw().x.w;
-    let mut pk_bytes: Vec<u8> = Vec::new();
-    assert!(pk.len() == pk2.len());
-    for i in 0..pk.len() {
-        let pkb = pk[i].to_be_bytes();
-        let pkb2 = pk2[i].to_be_bytes();
-        for idx in 0..pkb.len() {
-            pk_bytes.push(pkb[idx]);
-            pk_bytes.push(pkb2[idx]);
-        }
-    }
-    for i in 0..pk_bytes.len() {
-        print!("{} ", pk_bytes[i]);
-    }
+fn keypairs_to_validators(keypairs: Vec<Keypair>) -> Vec<Validator> {
+    keypairs
+        .into_iter()
+        .map(|keypair| {
+            let pubkey_bytes = keypair.pk.as_bytes();
+            Validator {
+                public_key: PublicKeyBytes::from_bytes(&pubkey_bytes).unwrap(),
+                status: ValidatorStatus::Unknown,
+                public_key_str: format!("0x{}", hex::encode(&pubkey_bytes)),
+                private_key: keypair.sk,
+            }
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::beacon_node::BasicBeaconNode;
+    use crate::beacon_node_simulator::BeaconNodeSimulator;
+    use crate::mock_beacon_node::MockBeaconNode;
+    use crate::slot_clock::ManualSlotClock;
     use bls::{PublicKey, Signature};
-    use types::config::MinimalConfig;
-    use types::primitives::Domain;
+    use ssz_types::VariableList;
+    use std::env;
+    use std::fs;
+    use std::sync::atomic::Ordering;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use types::config::{MinimalConfig, QuickConfig};
+    use types::consts::FAR_FUTURE_EPOCH;
+    use types::genesis::{interop_genesis_state, interop_keypair};
+    use types::primitives::{Domain, H256};
+    use types::types::{BeaconBlock, BeaconBlockBody, Validator as BeaconValidator};
+
+    const SLOT_DURATION: Duration = Duration::from_millis(12000);
 
     const VALIDATORS: &str = r#"
         [{
@@ -239,31 +616,339 @@ mod tests {
             "public":"0xb89bebc699769726a318c8e9971bd3171297c61aea4a6578a7a4f94b547dcba5bac16a89108b6b6a1fe3695d1a874a0b"
         }]"#;
 
+    const PUBLIC_KEY: &str = "0xa99a76ed7796f7be22d5b7e85deeb7c5677e88e511e0b337618f8c4eb61349b4bf2d153f649f7b53359fe8b94a38e44c";
+    const OTHER_PUBLIC_KEY: &str = "0xb89bebc699769726a318c8e9971bd3171297c61aea4a6578a7a4f94b547dcba5bac16a89108b6b6a1fe3695d1a874a0b";
+
+    /// State whose registry holds `validators`, given as public keys and their activation epochs.
+    fn registry_state<C: EthConfig>(validators: &[(&str, Epoch)]) -> BeaconState<C> {
+        let validators: Vec<BeaconValidator> = validators
+            .iter()
+            .map(|(public_key, activation_epoch)| {
+                let pubkey_bytes = hex::decode(public_key.trim_start_matches("0x")).unwrap();
+                BeaconValidator {
+                    pubkey: PublicKey::from_bytes(&pubkey_bytes).unwrap(),
+                    activation_epoch: *activation_epoch,
+                    exit_epoch: FAR_FUTURE_EPOCH,
+                    withdrawable_epoch: FAR_FUTURE_EPOCH,
+                    ..BeaconValidator::default()
+                }
+            })
+            .collect();
+        BeaconState {
+            validators: VariableList::from(validators),
+            ..BeaconState::default()
+        }
+    }
+
+    /// Both validators of `VALIDATORS`, active since genesis.
+    fn active_state<C: EthConfig>() -> BeaconState<C> {
+        registry_state(&[(PUBLIC_KEY, 0), (OTHER_PUBLIC_KEY, 0)])
+    }
+
+    /// Service of the validators of `VALIDATORS`, connected to `beacon_node`.
+    fn mock_service<C: EthConfig>(
+        eth_config: C,
+        beacon_node: MockBeaconNode<C>,
+    ) -> Service<C, MockBeaconNode<C>> {
+        let keys: Vec<KeysPair> = serde_json::from_str(VALIDATORS).unwrap();
+        Service::new(
+            eth_config,
+            beacon_node,
+            SlashingProtection::in_memory(),
+            parse_keypairs(keys).unwrap(),
+        )
+    }
+
+    fn duty(
+        validator_pubkey: &str,
+        attestation_slot: Slot,
+        block_proposal_slot: Option<Slot>,
+    ) -> DutyInfo {
+        DutyInfo {
+            validator_pubkey: String::from(validator_pubkey),
+            attestation_slot,
+            attestation_committee_index: 0,
+            attestation_committee_position: 0,
+            block_proposal_slot,
+        }
+    }
+
+    /// Runs `service` from genesis until the end of its first `epochs` epochs. The manual clock
+    /// jumps to every point in time the service waits for, so the run takes no time and always
+    /// covers the same slots.
+    async fn run_epochs<C: EthConfig, B: BeaconNode<C>>(
+        service: Service<C, B>,
+        epochs: u64,
+    ) -> Service<C, B> {
+        let slots = epochs * C::SlotsPerEpoch::to_u64();
+        let service = service.with_run_duration(SLOT_DURATION * slots as u32);
+        let slot_clock = ManualSlotClock::new(0, SLOT_DURATION);
+
+        service.run(&slot_clock).await.unwrap();
+
+        assert_eq!(slot_clock.now(), Some(slots));
+        service
+    }
+
     #[test]
     fn should_init_service() {
-        let keys: Vec<KeysPair> = serde_json::from_str(VALIDATORS).unwrap();
-        let service = Service::new(MinimalConfig::default(), keys);
-        assert_eq!(service.validators[0].public_key_str, "0xa99a76ed7796f7be22d5b7e85deeb7c5677e88e511e0b337618f8c4eb61349b4bf2d153f649f7b53359fe8b94a38e44c");
-        assert_eq!(service.validators.len(), 2);
+        let service = mock_service(MinimalConfig, MockBeaconNode::default());
+        let validators = service.validators();
+        assert_eq!(validators[0].public_key_str, PUBLIC_KEY);
+        assert_eq!(validators[0].status, ValidatorStatus::Unknown);
+        assert_eq!(validators.len(), 2);
     }
 
     #[test]
     fn should_get_validator_index() {
-        let keys: Vec<KeysPair> = serde_json::from_str(VALIDATORS).unwrap();
-        let service = Service::new(MinimalConfig::default(), keys);
-        let index = service.get_validator_index(&String::from("0xb89bebc699769726a318c8e9971bd3171297c61aea4a6578a7a4f94b547dcba5bac16a89108b6b6a1fe3695d1a874a0b"));
-        assert_eq!(index, Some(1));
-        let index = service.get_validator_index(&String::from("random"));
-        assert_eq!(index, None);
+        let service = mock_service(MinimalConfig, MockBeaconNode::default());
+        let unrelated_key = hex::encode(interop_keypair(0).pk.as_bytes());
+        let state = registry_state(&[(&unrelated_key, 0), (OTHER_PUBLIC_KEY, 0)]);
+
+        service.update_validator_statuses(&state, 0);
+
+        assert_eq!(service.get_validator_index(OTHER_PUBLIC_KEY), Some(1));
+        assert_eq!(service.get_validator_index(PUBLIC_KEY), None);
+        assert_eq!(service.get_validator_index("random"), None);
+    }
+
+    #[test]
+    fn should_track_validators_until_active() {
+        let service = mock_service(MinimalConfig, MockBeaconNode::default());
+        let status = |public_key: &str| {
+            service
+                .validators()
+                .iter()
+                .find(|validator| validator.public_key_str == public_key)
+                .unwrap()
+                .status
+        };
+
+        let registry_size = || service.registry_pubkeys.read().unwrap().len();
+
+        service.update_validator_statuses(&registry_state(&[(OTHER_PUBLIC_KEY, 2)]), 0);
+        assert_eq!(registry_size(), 1);
+        assert_eq!(status(PUBLIC_KEY), ValidatorStatus::Unknown);
+        assert_eq!(
+            status(OTHER_PUBLIC_KEY),
+            ValidatorStatus::PendingActivation(0)
+        );
+
+        let state = registry_state(&[(OTHER_PUBLIC_KEY, 2), (PUBLIC_KEY, 3)]);
+        service.update_validator_statuses(&state, 2);
+        assert_eq!(registry_size(), 2);
+        assert_eq!(status(PUBLIC_KEY), ValidatorStatus::PendingActivation(1));
+        assert_eq!(status(OTHER_PUBLIC_KEY), ValidatorStatus::Active(0));
+
+        service.update_validator_statuses(&state, 3);
+        assert_eq!(status(PUBLIC_KEY), ValidatorStatus::Active(1));
+    }
+
+    #[test]
+    fn should_rebuild_registry_cache_of_other_chain() {
+        let service = mock_service(MinimalConfig, MockBeaconNode::default());
+        let unrelated_keys: Vec<String> = (0..3)
+            .map(|index| hex::encode(interop_keypair(index).pk.as_bytes()))
+            .collect();
+
+        service.update_validator_statuses(
+            &registry_state(&[(&unrelated_keys[0], 0), (&unrelated_keys[1], 0)]),
+            0,
+        );
+        assert_eq!(service.get_validator_index(PUBLIC_KEY), None);
+
+        let other_chain_state = registry_state(&[
+            (&unrelated_keys[2], 0),
+            (PUBLIC_KEY, 0),
+            (OTHER_PUBLIC_KEY, 0),
+        ]);
+        service.update_validator_statuses(&other_chain_state, 0);
+
+        assert_eq!(service.registry_pubkeys.read().unwrap().len(), 3);
+        assert_eq!(service.get_validator_index(PUBLIC_KEY), Some(1));
+        assert_eq!(service.get_validator_index(OTHER_PUBLIC_KEY), Some(2));
+    }
+
+    #[tokio::test]
+    async fn should_only_request_duties_of_active_validators() {
+        let beacon_node = MockBeaconNode::new(registry_state(&[(PUBLIC_KEY, FAR_FUTURE_EPOCH)]))
+            .with_duties(vec![duty(PUBLIC_KEY, 2, None)]);
+
+        let service = run_epochs(mock_service(MinimalConfig, beacon_node), 1).await;
+
+        let published = service.beacon_node.published_attestations.lock().unwrap();
+        assert!(published.is_empty());
+        assert_eq!(
+            service.get_validator_index(PUBLIC_KEY),
+            Some(0),
+            "pending validators are still indexed"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_attest_at_assigned_slot() {
+        let beacon_node =
+            MockBeaconNode::new(active_state()).with_duties(vec![duty(PUBLIC_KEY, 2, None)]);
+
+        let service = run_epochs(mock_service(MinimalConfig, beacon_node), 1).await;
+
+        let published = service.beacon_node.published_attestations.lock().unwrap();
+        assert_eq!(published.len(), 1);
+        assert_eq!(
+            service
+                .metrics
+                .attestations_published
+                .load(Ordering::Relaxed),
+            1
+        );
+    }
+
+    /// Runs a service with `eth_config` for validators activated at epoch 2, with an attestation
+    /// duty at slot 2, and returns the number of attestations published.
+    async fn attestations_of_validators_activated_at_epoch_2<C: EthConfig>(eth_config: C) -> usize {
+        let state = registry_state(&[(PUBLIC_KEY, 2), (OTHER_PUBLIC_KEY, 2)]);
+        let beacon_node = MockBeaconNode::new(state).with_duties(vec![duty(PUBLIC_KEY, 2, None)]);
+
+        let service = run_epochs(mock_service(eth_config, beacon_node), 3).await;
+
+        let published = service.beacon_node.published_attestations.lock().unwrap();
+        published.len()
+    }
+
+    #[tokio::test]
+    async fn should_attest_with_selected_preset() {
+        // Slot 2 is in epoch 2 with one slot per epoch, so the validator is active by then.
+        assert_eq!(
+            attestations_of_validators_activated_at_epoch_2(QuickConfig).await,
+            1
+        );
+        // Slot 2 is in epoch 0 with eight slots per epoch, before the validator is activated.
+        assert_eq!(
+            attestations_of_validators_activated_at_epoch_2(MinimalConfig).await,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn should_propose_at_assigned_slot() {
+        let block = BeaconBlock {
+            slot: 3,
+            parent_root: H256::zero(),
+            state_root: H256::zero(),
+            body: BeaconBlockBody {
+                randao_reveal: Signature::empty_signature(),
+                eth1_data: Default::default(),
+                graffiti: [0; 32],
+                proposer_slashings: Default::default(),
+                attester_slashings: Default::default(),
+                attestations: Default::default(),
+                deposits: Default::default(),
+                voluntary_exits: Default::default(),
+                transfers: Default::default(),
+            },
+            signature: Signature::empty_signature(),
+        };
+        let beacon_node = MockBeaconNode::new(active_state())
+            .with_duties(vec![duty(OTHER_PUBLIC_KEY, 9, Some(3))])
+            .with_block(block);
+
+        let service = run_epochs(mock_service(MinimalConfig, beacon_node), 1).await;
+
+        let published = service.beacon_node.published_blocks.lock().unwrap();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].message.slot, 3);
+        assert_eq!(service.metrics.blocks_proposed.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn should_wait_for_beacon_node_to_recover() {
+        let beacon_node = MockBeaconNode::new(active_state())
+            .with_duties(vec![duty(PUBLIC_KEY, 2, None)])
+            .with_state_failures(2);
+
+        let service = run_epochs(mock_service(MinimalConfig, beacon_node), 1).await;
+
+        let published = service.beacon_node.published_attestations.lock().unwrap();
+        assert_eq!(published.len(), 1);
+        assert_eq!(
+            service.metrics.beacon_node_errors.load(Ordering::Relaxed),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn should_flush_slashing_protection_on_shutdown() {
+        let path = env::temp_dir().join("should_flush_slashing_protection_on_shutdown.json");
+        let _ = fs::remove_file(&path);
+        let (shutdown, shutdown_signal) = watch::channel(false);
+        let service = Service::new(
+            MinimalConfig,
+            MockBeaconNode::new(BeaconState::default()),
+            SlashingProtection::open(&path).unwrap(),
+            vec![],
+        )
+        .with_shutdown(shutdown_signal);
+        let slot_clock = ManualSlotClock::new(0, SLOT_DURATION);
+        shutdown.send(true).unwrap();
+
+        service.run(&slot_clock).await.unwrap();
+
+        assert!(path.exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_stop_waiting_on_shutdown() {
+        let (shutdown, shutdown_signal) = watch::channel(false);
+        let service = Service::new(
+            MinimalConfig,
+            MockBeaconNode::new(BeaconState::default()),
+            SlashingProtection::in_memory(),
+            vec![],
+        )
+        .with_shutdown(shutdown_signal);
+        let genesis_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 3600;
+        let slot_clock = SystemTimeSlotClock::new(genesis_time, SLOT_DURATION);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            shutdown.send(true).unwrap();
+        });
+
+        let result = tokio::time::timeout(Duration::from_secs(5), service.run(&slot_clock)).await;
+
+        assert_eq!(result, Ok(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn should_extend_simulated_chain() {
+        let genesis_state = interop_genesis_state::<MinimalConfig>(64, 0).unwrap();
+        let simulator =
+            BeaconNodeSimulator::start(genesis_state, &([127, 0, 0, 1], 0).into()).unwrap();
+        let service = Service::new(
+            MinimalConfig,
+            BasicBeaconNode::new(simulator.url()),
+            SlashingProtection::in_memory(),
+            (0..8).map(interop_keypair).collect(),
+        );
+
+        run_epochs(service, 2).await;
+
+        let chain = simulator.chain.lock().unwrap();
+        assert!(!chain.blocks().is_empty());
+        assert_eq!(chain.head_state().slot, chain.blocks().last().unwrap().slot);
     }
 
     #[test]
     fn should_init_validators() {
         let keys: Vec<KeysPair> = serde_json::from_str(VALIDATORS).unwrap();
-        let parsed = parse_validators(keys).unwrap();
+        let parsed = keypairs_to_validators(parse_keypairs(keys).unwrap());
         assert_eq!(parsed.len(), 2);
-        assert_eq!(parsed[0].index, 0);
-        assert_eq!(parsed[1].index, 1);
+        assert_eq!(parsed[0].status, ValidatorStatus::Unknown);
+        assert_eq!(parsed[1].status, ValidatorStatus::Unknown);
         let mut bytes = vec![0u8; 48];
         let private_key_other_bytes =
             hex::decode("51d0b65185db6989ab0b560d6deed19c7ead0e24b9b6372cbecb1f26bdfad000")
@@ -283,14 +968,26 @@ mod tests {
     fn should_validate_validators() {
         let keys: Vec<KeysPair> = serde_json::from_str(INVALID_VALIDATORS).unwrap();
         let optb = Vec::new();
-        let parsed = parse_validators(keys).unwrap_or(optb);
+        let parsed = parse_keypairs(keys).unwrap_or(optb);
         assert_eq!(parsed.len(), 0);
     }
 
+    #[test]
+    fn should_reject_malformed_keys() {
+        let keys = |private: &str| {
+            vec![KeysPair {
+                private: String::from(private),
+                public: String::from(PUBLIC_KEY),
+            }]
+        };
+        assert!(parse_keypairs(keys("0xnothex")).is_err());
+        assert!(parse_keypairs(keys(&format!("0x{}", "11".repeat(49)))).is_err());
+    }
+
     #[test]
     fn should_accept_signature_for_attestation() {
         let keys: Vec<KeysPair> = serde_json::from_str(VALIDATORS).unwrap();
-        let parsed = parse_validators(keys).unwrap();
+        let parsed = keypairs_to_validators(parse_keypairs(keys).unwrap());
         let domain: Domain = 2; // attestation domain;
         let test_msg = [1u8, 2u8];
         let signature = Signature::new(&test_msg, domain, &(parsed[0].private_key));
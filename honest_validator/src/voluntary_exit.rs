@@ -0,0 +1,174 @@
+use bls::{Keypair, PublicKey, Signature};
+use tree_hash::SignedRoot;
+use types::beacon_state::BeaconState;
+use types::chain_spec::ChainSpec;
+use types::config::Config as EthConfig;
+use types::consts::FAR_FUTURE_EPOCH;
+use types::primitives::{Epoch, ValidatorIndex};
+use types::types::VoluntaryExit;
+
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    UnknownValidator,
+    NotActive,
+    AlreadyExiting(Epoch),
+    TooEarly { eligible_epoch: Epoch },
+}
+
+pub fn find_validator_index<C: EthConfig>(
+    state: &BeaconState<C>,
+    pubkey: &PublicKey,
+) -> Option<ValidatorIndex> {
+    state
+        .validators
+        .iter()
+        .position(|validator| validator.pubkey == *pubkey)
+        .map(|index| index as ValidatorIndex)
+}
+
+/// Checks the conditions `process_voluntary_exit` puts on a validator exiting at `epoch`.
+pub fn check_exit_eligibility<C: EthConfig>(
+    spec: &ChainSpec,
+    state: &BeaconState<C>,
+    validator_index: ValidatorIndex,
+    epoch: Epoch,
+) -> Result<(), Error> {
+    let validator = state
+        .validators
+        .get(validator_index as usize)
+        .ok_or(Error::UnknownValidator)?;
+    if validator.exit_epoch != FAR_FUTURE_EPOCH {
+        return Err(Error::AlreadyExiting(validator.exit_epoch));
+    }
+    if validator.activation_epoch > epoch {
+        return Err(Error::NotActive);
+    }
+    let eligible_epoch = validator.activation_epoch + spec.persistent_committee_period;
+    if epoch < eligible_epoch {
+        return Err(Error::TooEarly { eligible_epoch });
+    }
+    Ok(())
+}
+
+/// Signed exit of the validator owning `keypair`, effective from the current epoch of `state`.
+pub fn prepare_voluntary_exit<C: EthConfig>(
+    spec: &ChainSpec,
+    state: &BeaconState<C>,
+    keypair: &Keypair,
+) -> Result<VoluntaryExit, Error> {
+    let epoch = state.get_current_epoch();
+    let validator_index =
+        find_validator_index(state, &keypair.pk).ok_or(Error::UnknownValidator)?;
+    check_exit_eligibility(spec, state, validator_index, epoch)?;
+
+    let mut exit = VoluntaryExit {
+        epoch,
+        validator_index,
+        signature: Signature::empty_signature(),
+    };
+    let domain = state.get_domain(spec.domain_voluntary_exit, Some(epoch));
+    exit.signature = Signature::new(&exit.signed_root(), domain, &keypair.sk);
+    Ok(exit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bls::SecretKey;
+    use ssz_types::VariableList;
+    use types::config::MinimalConfig;
+    use types::types::Validator;
+
+    fn new_keypair(byte: u8) -> Keypair {
+        let sk = SecretKey::from_bytes(&[byte; 48]).unwrap();
+        Keypair {
+            pk: PublicKey::from_secret_key(&sk),
+            sk,
+        }
+    }
+
+    fn spec() -> ChainSpec {
+        ChainSpec::from_config::<MinimalConfig>()
+    }
+
+    fn state(slot: u64, validators: Vec<Validator>) -> BeaconState<MinimalConfig> {
+        BeaconState {
+            slot,
+            validators: VariableList::new(validators).unwrap(),
+            ..BeaconState::default()
+        }
+    }
+
+    fn validator(pubkey: PublicKey, activation_epoch: Epoch) -> Validator {
+        Validator {
+            pubkey,
+            activation_epoch,
+            exit_epoch: FAR_FUTURE_EPOCH,
+            withdrawable_epoch: FAR_FUTURE_EPOCH,
+            ..Validator::default()
+        }
+    }
+
+    #[test]
+    fn should_sign_exit_for_eligible_validator() {
+        let keypair = new_keypair(1);
+        let state = state(
+            2048 * 8,
+            vec![
+                validator(new_keypair(2).pk, 0),
+                validator(keypair.pk.clone(), 0),
+            ],
+        );
+
+        let exit = prepare_voluntary_exit(&spec(), &state, &keypair).unwrap();
+        assert_eq!(exit.validator_index, 1);
+        assert_eq!(exit.epoch, 2048);
+        let domain = state.get_domain(MinimalConfig::domain_voluntary_exit(), Some(2048));
+        assert!(exit
+            .signature
+            .verify(&exit.signed_root(), domain, &keypair.pk));
+    }
+
+    #[test]
+    fn should_refuse_ineligible_exits() {
+        let keypair = new_keypair(1);
+        let mut exited = validator(keypair.pk.clone(), 0);
+        exited.exit_epoch = 10;
+        let state = state(
+            100 * 8,
+            vec![
+                validator(keypair.pk.clone(), 10),
+                validator(keypair.pk.clone(), 200),
+                exited,
+            ],
+        );
+
+        assert_eq!(
+            check_exit_eligibility(&spec(), &state, 0, 100),
+            Err(Error::TooEarly {
+                eligible_epoch: 10 + 2048
+            })
+        );
+        assert_eq!(
+            check_exit_eligibility(&spec(), &state, 1, 100),
+            Err(Error::NotActive)
+        );
+        assert_eq!(
+            check_exit_eligibility(&spec(), &state, 2, 100),
+            Err(Error::AlreadyExiting(10))
+        );
+        assert_eq!(
+            check_exit_eligibility(&spec(), &state, 3, 100),
+            Err(Error::UnknownValidator)
+        );
+    }
+
+    #[test]
+    fn should_refuse_unknown_validator() {
+        let state = state(0, vec![]);
+        assert_eq!(
+            prepare_voluntary_exit(&spec(), &state, &new_keypair(1)),
+            Err(Error::UnknownValidator)
+        );
+    }
+}
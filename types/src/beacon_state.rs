@@ -1,9 +1,13 @@
+use eth2_hashing::hash;
 use serde::{Deserialize, Serialize};
 use ssz_derive::{Decode, Encode};
-use ssz_types::{BitVector, FixedVector, VariableList};
+use ssz_types::{BitList, BitVector, FixedVector, VariableList};
 use tree_hash_derive::TreeHash;
+use typenum::Unsigned;
 
-use crate::{config::*, consts, primitives::*, types::*};
+use crate::{
+    chain_spec::ChainSpec, config::*, consts, helper_functions::*, primitives::*, types::*,
+};
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Encode, Decode, TreeHash, Default)]
 pub struct BeaconState<C: Config> {
@@ -26,6 +30,9 @@ pub struct BeaconState<C: Config> {
     pub validators: VariableList<Validator, C::ValidatorRegistryLimit>,
     pub balances: VariableList<u64, C::ValidatorRegistryLimit>,
 
+    // Randomness
+    pub randao_mixes: FixedVector<H256, C::EpochsPerHistoricalVector>,
+
     // Slashings
     pub slashings: FixedVector<u64, C::EpochsPerSlashingsVector>,
 
@@ -40,3 +47,514 @@ pub struct BeaconState<C: Config> {
     pub current_justified_checkpoint: Checkpoint,
     pub finalized_checkpoint: Checkpoint,
 }
+
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    SlotOutOfRange,
+    EpochOutOfRange,
+    IndexOutOfRange,
+    NoActiveValidators,
+}
+
+impl<C: Config> BeaconState<C> {
+    pub fn get_current_epoch(&self) -> Epoch {
+        compute_epoch_at_slot::<C>(self.slot)
+    }
+
+    /// Epoch before the current one, the genesis epoch during the genesis epoch.
+    pub fn get_previous_epoch(&self) -> Epoch {
+        let current_epoch = self.get_current_epoch();
+        if current_epoch > C::genesis_epoch() {
+            current_epoch - 1
+        } else {
+            C::genesis_epoch()
+        }
+    }
+
+    /// Root of the block at the start of `epoch`.
+    pub fn get_block_root(&self, epoch: Epoch) -> Result<H256, Error> {
+        self.get_block_root_at_slot(compute_start_slot_at_epoch::<C>(epoch))
+    }
+
+    /// Root of the block at `slot`, which has to be one of the last `SlotsPerHistoricalRoot`
+    /// slots before the state slot.
+    pub fn get_block_root_at_slot(&self, slot: Slot) -> Result<H256, Error> {
+        let slots_per_historical_root = C::SlotsPerHistoricalRoot::to_u64();
+        if slot >= self.slot || self.slot > slot + slots_per_historical_root {
+            return Err(Error::SlotOutOfRange);
+        }
+        self.block_roots
+            .get((slot % slots_per_historical_root) as usize)
+            .copied()
+            .ok_or(Error::IndexOutOfRange)
+    }
+
+    pub fn get_randao_mix(&self, epoch: Epoch) -> Result<H256, Error> {
+        let index = epoch % C::EpochsPerHistoricalVector::to_u64();
+        self.randao_mixes
+            .get(index as usize)
+            .copied()
+            .ok_or(Error::IndexOutOfRange)
+    }
+
+    pub fn get_active_validator_indices(&self, epoch: Epoch) -> Vec<ValidatorIndex> {
+        self.validators
+            .iter()
+            .enumerate()
+            .filter(|(_, validator)| is_active_validator(validator, epoch))
+            .map(|(index, _)| index as ValidatorIndex)
+            .collect()
+    }
+
+    /// Sum of the effective balances of `indices`, at least `effective_balance_increment` to
+    /// avoid divisions by zero.
+    pub fn get_total_balance(&self, indices: &[ValidatorIndex], spec: &ChainSpec) -> Gwei {
+        let total: Gwei = indices
+            .iter()
+            .filter_map(|index| self.validators.get(*index as usize))
+            .map(|validator| validator.effective_balance)
+            .sum();
+        total.max(spec.effective_balance_increment)
+    }
+
+    pub fn get_total_active_balance(&self, spec: &ChainSpec) -> Gwei {
+        self.get_total_balance(
+            &self.get_active_validator_indices(self.get_current_epoch()),
+            spec,
+        )
+    }
+
+    /// Number of validators allowed to enter or leave the registry per epoch.
+    pub fn get_validator_churn_limit(&self, spec: &ChainSpec) -> u64 {
+        let active_validators = self
+            .get_active_validator_indices(self.get_current_epoch())
+            .len() as u64;
+        (active_validators / spec.churn_limit_quotient).max(spec.min_per_epoch_churn_limit)
+    }
+
+    pub fn get_seed(
+        &self,
+        epoch: Epoch,
+        domain_type: DomainType,
+        spec: &ChainSpec,
+    ) -> Result<H256, Error> {
+        let mix = self.get_randao_mix(
+            epoch + C::EpochsPerHistoricalVector::to_u64() - spec.min_seed_lookahead - 1,
+        )?;
+        let mut input = int_to_bytes4(domain_type).to_vec();
+        input.extend_from_slice(&int_to_bytes8(epoch));
+        input.extend_from_slice(mix.as_bytes());
+        Ok(H256::from_slice(&hash(&input)))
+    }
+
+    pub fn get_committee_count_at_slot(&self, slot: Slot, spec: &ChainSpec) -> u64 {
+        let epoch = compute_epoch_at_slot::<C>(slot);
+        let active_validators = self.get_active_validator_indices(epoch).len() as u64;
+        let committees =
+            active_validators / C::SlotsPerEpoch::to_u64() / spec.target_committee_size;
+        committees.min(spec.max_committees_per_slot).max(1)
+    }
+
+    pub fn get_beacon_committee(
+        &self,
+        slot: Slot,
+        index: CommitteeIndex,
+        spec: &ChainSpec,
+    ) -> Result<Vec<ValidatorIndex>, Error> {
+        let epoch = compute_epoch_at_slot::<C>(slot);
+        let committees_per_slot = self.get_committee_count_at_slot(slot, spec);
+        if index >= committees_per_slot {
+            return Err(Error::IndexOutOfRange);
+        }
+        let seed = self.get_seed(epoch, spec.domain_beacon_attester, spec)?;
+        let slots_per_epoch = C::SlotsPerEpoch::to_u64();
+        compute_committee(
+            &self.get_active_validator_indices(epoch),
+            seed.as_bytes(),
+            (slot % slots_per_epoch) * committees_per_slot + index,
+            committees_per_slot * slots_per_epoch,
+            spec,
+        )
+        .ok_or(Error::IndexOutOfRange)
+    }
+
+    /// Domain of `domain_type` signatures at `message_epoch`, the current epoch by default.
+    pub fn get_domain(&self, domain_type: DomainType, message_epoch: Option<Epoch>) -> Domain {
+        let epoch = message_epoch.unwrap_or_else(|| self.get_current_epoch());
+        let fork_version = if epoch < self.fork.epoch {
+            self.fork.previous_version
+        } else {
+            self.fork.current_version
+        };
+        compute_domain(domain_type, fork_version)
+    }
+
+    /// Sorted indices of the members of the committee of `data` whose bit is set in `bits`.
+    pub fn get_attesting_indices(
+        &self,
+        data: &AttestationData,
+        bits: &BitList<C::MaxValidatorsPerCommittee>,
+        spec: &ChainSpec,
+    ) -> Result<Vec<ValidatorIndex>, Error> {
+        let committee = self.get_beacon_committee(data.slot, data.index, spec)?;
+        let mut indices: Vec<ValidatorIndex> = committee
+            .into_iter()
+            .enumerate()
+            .filter(|(position, _)| bits.get(*position).unwrap_or(false))
+            .map(|(_, index)| index)
+            .collect();
+        indices.sort();
+        Ok(indices)
+    }
+
+    pub fn get_beacon_proposer_index(&self, spec: &ChainSpec) -> Result<ValidatorIndex, Error> {
+        self.get_beacon_proposer_index_at_slot(self.slot, spec)
+    }
+
+    /// Proposer of `slot`, which has to be in the current epoch as effective balances may change
+    /// at epoch boundaries.
+    pub fn get_beacon_proposer_index_at_slot(
+        &self,
+        slot: Slot,
+        spec: &ChainSpec,
+    ) -> Result<ValidatorIndex, Error> {
+        let epoch = self.get_current_epoch();
+        if compute_epoch_at_slot::<C>(slot) != epoch {
+            return Err(Error::SlotOutOfRange);
+        }
+        let mut input = self
+            .get_seed(epoch, spec.domain_beacon_proposer, spec)?
+            .as_bytes()
+            .to_vec();
+        input.extend_from_slice(&int_to_bytes8(slot));
+        let seed = hash(&input);
+        self.compute_proposer_index(&self.get_active_validator_indices(epoch), &seed, spec)
+    }
+
+    pub fn increase_balance(&mut self, index: ValidatorIndex, delta: Gwei) -> Result<(), Error> {
+        let balance = self
+            .balances
+            .get_mut(index as usize)
+            .ok_or(Error::IndexOutOfRange)?;
+        *balance = balance.saturating_add(delta);
+        Ok(())
+    }
+
+    pub fn decrease_balance(&mut self, index: ValidatorIndex, delta: Gwei) -> Result<(), Error> {
+        let balance = self
+            .balances
+            .get_mut(index as usize)
+            .ok_or(Error::IndexOutOfRange)?;
+        *balance = balance.saturating_sub(delta);
+        Ok(())
+    }
+
+    /// Queues the exit of validator `index` behind the exits already scheduled, respecting the
+    /// churn limit.
+    pub fn initiate_validator_exit(
+        &mut self,
+        index: ValidatorIndex,
+        spec: &ChainSpec,
+    ) -> Result<(), Error> {
+        let exit_epoch = self
+            .validators
+            .get(index as usize)
+            .ok_or(Error::IndexOutOfRange)?
+            .exit_epoch;
+        if exit_epoch != consts::FAR_FUTURE_EPOCH {
+            return Ok(());
+        }
+
+        let mut exit_queue_epoch = self
+            .validators
+            .iter()
+            .map(|validator| validator.exit_epoch)
+            .filter(|epoch| *epoch != consts::FAR_FUTURE_EPOCH)
+            .fold(
+                compute_activation_exit_epoch(self.get_current_epoch(), spec),
+                Epoch::max,
+            );
+        let exit_queue_churn = self
+            .validators
+            .iter()
+            .filter(|validator| validator.exit_epoch == exit_queue_epoch)
+            .count() as u64;
+        if exit_queue_churn >= self.get_validator_churn_limit(spec) {
+            exit_queue_epoch += 1;
+        }
+
+        let validator = &mut self.validators[index as usize];
+        validator.exit_epoch = exit_queue_epoch;
+        validator.withdrawable_epoch = exit_queue_epoch + spec.min_validator_withdrawability_delay;
+        Ok(())
+    }
+
+    /// Slashes validator `index`, rewarding the whistleblower, by default the current proposer.
+    pub fn slash_validator(
+        &mut self,
+        index: ValidatorIndex,
+        whistleblower_index: Option<ValidatorIndex>,
+        spec: &ChainSpec,
+    ) -> Result<(), Error> {
+        let epoch = self.get_current_epoch();
+        // Everything that can fail is looked up first, so an error leaves the state unchanged.
+        let effective_balance = self
+            .validators
+            .get(index as usize)
+            .ok_or(Error::IndexOutOfRange)?
+            .effective_balance;
+        let slashings_index = (epoch % C::EpochsPerSlashingsVector::to_u64()) as usize;
+        let slashings = *self
+            .slashings
+            .get(slashings_index)
+            .ok_or(Error::IndexOutOfRange)?;
+        let proposer_index = self.get_beacon_proposer_index(spec)?;
+        let whistleblower_index = whistleblower_index.unwrap_or(proposer_index);
+        for balance_index in &[index, proposer_index, whistleblower_index] {
+            if self.balances.get(*balance_index as usize).is_none() {
+                return Err(Error::IndexOutOfRange);
+            }
+        }
+
+        self.initiate_validator_exit(index, spec)?;
+        let validator = &mut self.validators[index as usize];
+        validator.slashed = true;
+        validator.withdrawable_epoch = validator
+            .withdrawable_epoch
+            .max(epoch + C::EpochsPerSlashingsVector::to_u64());
+        self.slashings[slashings_index] = slashings + effective_balance;
+        self.decrease_balance(
+            index,
+            effective_balance / spec.min_slashing_penalty_quotient,
+        )?;
+
+        // Apply proposer and whistleblower rewards
+        let whistleblower_reward = effective_balance / spec.whistleblower_reward_quotient;
+        let proposer_reward = whistleblower_reward / spec.proposer_reward_quotient;
+        self.increase_balance(proposer_index, proposer_reward)?;
+        self.increase_balance(whistleblower_index, whistleblower_reward - proposer_reward)
+    }
+
+    fn compute_proposer_index(
+        &self,
+        indices: &[ValidatorIndex],
+        seed: &[u8],
+        spec: &ChainSpec,
+    ) -> Result<ValidatorIndex, Error> {
+        let effective_balance = |index: ValidatorIndex| {
+            self.validators
+                .get(index as usize)
+                .map_or(0, |validator| validator.effective_balance)
+        };
+        // Sampling would never terminate without a candidate that has some balance.
+        if indices.iter().all(|index| effective_balance(*index) == 0) {
+            return Err(Error::NoActiveValidators);
+        }
+
+        let max_random_byte = u64::from(u8::MAX);
+        let count = indices.len() as u64;
+        let mut i = 0;
+        loop {
+            let shuffled = compute_shuffled_index(i % count, count, seed, spec)
+                .ok_or(Error::IndexOutOfRange)?;
+            let candidate = indices[shuffled as usize];
+            let mut input = seed.to_vec();
+            input.extend_from_slice(&int_to_bytes8(i / 32));
+            let random_byte = u64::from(hash(&input)[(i % 32) as usize]);
+            if effective_balance(candidate) * max_random_byte
+                >= spec.max_effective_balance * random_byte
+            {
+                return Ok(candidate);
+            }
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genesis::interop_genesis_state;
+
+    fn spec() -> ChainSpec {
+        ChainSpec::from_config::<MinimalConfig>()
+    }
+
+    fn validator(effective_balance: Gwei, activation_epoch: Epoch, exit_epoch: Epoch) -> Validator {
+        Validator {
+            effective_balance,
+            activation_epoch,
+            exit_epoch,
+            withdrawable_epoch: consts::FAR_FUTURE_EPOCH,
+            ..Validator::default()
+        }
+    }
+
+    fn state<C: Config>(slot: Slot, validators: Vec<Validator>) -> BeaconState<C> {
+        BeaconState {
+            slot,
+            validators: VariableList::from(validators),
+            ..BeaconState::default()
+        }
+    }
+
+    fn active_validators(count: usize) -> Vec<Validator> {
+        vec![validator(32_000_000_000, 0, consts::FAR_FUTURE_EPOCH); count]
+    }
+
+    #[test]
+    fn should_slash_and_reward_whistleblower() {
+        let spec = spec();
+        let mut state = interop_genesis_state::<MinimalConfig>(16, 0).unwrap();
+        let proposer_index = state.get_beacon_proposer_index(&spec).unwrap();
+        let mut others = (0..16).filter(|index| *index != proposer_index);
+        let index = others.next().unwrap();
+        let whistleblower_index = others.next().unwrap();
+
+        assert_eq!(
+            state.slash_validator(index, Some(whistleblower_index), &spec),
+            Ok(())
+        );
+
+        let validator = &state.validators[index as usize];
+        assert!(validator.slashed);
+        assert_eq!(
+            validator.exit_epoch,
+            compute_activation_exit_epoch(0, &spec)
+        );
+        assert_eq!(state.slashings[0], 32_000_000_000);
+        assert_eq!(state.balances[index as usize], 31_000_000_000);
+        assert_eq!(state.balances[proposer_index as usize], 32_007_812_500);
+        assert_eq!(state.balances[whistleblower_index as usize], 32_054_687_500);
+    }
+
+    #[test]
+    fn should_leave_state_unchanged_if_slashing_fails() {
+        let spec = spec();
+        let state = interop_genesis_state::<MinimalConfig>(16, 0).unwrap();
+
+        let mut unknown_whistleblower = state.clone();
+        assert_eq!(
+            unknown_whistleblower.slash_validator(1, Some(16), &spec),
+            Err(Error::IndexOutOfRange)
+        );
+        assert_eq!(unknown_whistleblower, state);
+
+        let mut unknown_validator = state.clone();
+        assert_eq!(
+            unknown_validator.slash_validator(16, None, &spec),
+            Err(Error::IndexOutOfRange)
+        );
+        assert_eq!(unknown_validator, state);
+
+        let mut without_proposer = state.clone();
+        for validator in without_proposer.validators.iter_mut() {
+            validator.effective_balance = 0;
+        }
+        let before = without_proposer.clone();
+        assert_eq!(
+            without_proposer.slash_validator(1, Some(2), &spec),
+            Err(Error::NoActiveValidators)
+        );
+        assert_eq!(without_proposer, before);
+    }
+
+    #[test]
+    fn should_get_previous_epoch() {
+        assert_eq!(state::<MinimalConfig>(0, vec![]).get_previous_epoch(), 0);
+        assert_eq!(state::<MinimalConfig>(7, vec![]).get_previous_epoch(), 0);
+        assert_eq!(state::<MinimalConfig>(8, vec![]).get_previous_epoch(), 0);
+        assert_eq!(
+            state::<MinimalConfig>(64 + 19, vec![]).get_previous_epoch(),
+            9
+        );
+    }
+
+    #[test]
+    fn should_get_block_roots_of_recent_slots() {
+        let block_roots: Vec<H256> = (0..64).map(H256::from_low_u64_le).collect();
+        let state: BeaconState<MinimalConfig> = BeaconState {
+            slot: 64 + 19,
+            block_roots: block_roots.into(),
+            ..BeaconState::default()
+        };
+
+        assert_eq!(
+            state.get_block_root_at_slot(64 + 18),
+            Ok(H256::from_low_u64_le(18))
+        );
+        assert_eq!(
+            state.get_block_root_at_slot(19),
+            Ok(H256::from_low_u64_le(19))
+        );
+        assert_eq!(state.get_block_root(10), Ok(H256::from_low_u64_le(16)));
+        assert_eq!(state.get_block_root_at_slot(18), Err(Error::SlotOutOfRange));
+        assert_eq!(
+            state.get_block_root_at_slot(64 + 19),
+            Err(Error::SlotOutOfRange)
+        );
+    }
+
+    #[test]
+    fn should_sum_balances_of_active_validators() {
+        let validators = vec![
+            validator(32_000_000_000, 0, consts::FAR_FUTURE_EPOCH),
+            validator(31_000_000_000, 1, consts::FAR_FUTURE_EPOCH),
+            validator(32_000_000_000, 3, consts::FAR_FUTURE_EPOCH),
+            validator(32_000_000_000, 0, 2),
+        ];
+        let state = state::<MinimalConfig>(16, validators);
+
+        assert_eq!(state.get_active_validator_indices(2), vec![0, 1]);
+        assert_eq!(state.get_total_active_balance(&spec()), 63_000_000_000);
+        assert_eq!(
+            state.get_total_balance(&[1, 2, 100], &spec()),
+            63_000_000_000
+        );
+    }
+
+    #[test]
+    fn should_floor_total_balance_at_increment() {
+        let spec = spec();
+        let state = state::<MinimalConfig>(0, vec![validator(0, 0, consts::FAR_FUTURE_EPOCH)]);
+        assert_eq!(
+            state.get_total_active_balance(&spec),
+            spec.effective_balance_increment
+        );
+        assert_eq!(
+            state.get_total_balance(&[], &spec),
+            spec.effective_balance_increment
+        );
+    }
+
+    #[test]
+    fn should_limit_churn_by_active_validators() {
+        let spec = spec();
+        assert_eq!(
+            state::<MinimalConfig>(0, active_validators(10)).get_validator_churn_limit(&spec),
+            spec.min_per_epoch_churn_limit
+        );
+        // Churns faster than the minimal preset, so the churn limit grows with a few validators.
+        let fast_churn_spec = ChainSpec {
+            churn_limit_quotient: 2,
+            ..spec
+        };
+        assert_eq!(
+            state::<MinimalConfig>(0, active_validators(6))
+                .get_validator_churn_limit(&fast_churn_spec),
+            4
+        );
+        assert_eq!(
+            state::<MinimalConfig>(0, active_validators(11))
+                .get_validator_churn_limit(&fast_churn_spec),
+            5
+        );
+        let mut validators = active_validators(11);
+        validators[0].exit_epoch = 0;
+        validators[1].exit_epoch = 0;
+        assert_eq!(
+            state::<MinimalConfig>(0, validators).get_validator_churn_limit(&fast_churn_spec),
+            4
+        );
+    }
+}
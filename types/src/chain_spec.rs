@@ -0,0 +1,384 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_yaml::{Mapping, Value};
+use typenum::Unsigned;
+
+use crate::{config::*, primitives::*};
+
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    IOError(String),
+    InvalidYaml(String),
+    /// A type-level constant of the config file differs from the one compiled into the preset.
+    PresetMismatch {
+        name: String,
+        preset: u64,
+        config: u64,
+    },
+    InvalidValue(String),
+}
+
+/// Constants of a chain which are not part of the type-level `Config` preset, so they can be read
+/// from the config file a testnet publishes. Serialized with the names used by the eth2 YAML config
+/// format, with domain types and fork versions written as `0x` prefixed 4 byte strings.
+#[derive(Clone, PartialEq, Eq, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct ChainSpec {
+    pub seconds_per_slot: u64,
+    #[serde(with = "bytes4")]
+    pub genesis_fork_version: [u8; 4],
+    pub min_genesis_time: u64,
+    pub min_genesis_active_validator_count: u64,
+
+    // Domains
+    #[serde(with = "domain_type")]
+    pub domain_beacon_proposer: DomainType,
+    #[serde(with = "domain_type")]
+    pub domain_beacon_attester: DomainType,
+    #[serde(with = "domain_type")]
+    pub domain_randao: DomainType,
+    #[serde(with = "domain_type")]
+    pub domain_deposit: DomainType,
+    #[serde(with = "domain_type")]
+    pub domain_voluntary_exit: DomainType,
+    #[serde(with = "domain_type")]
+    pub domain_transfer: DomainType,
+
+    // Validator cycle
+    pub churn_limit_quotient: u64,
+    pub min_per_epoch_churn_limit: u64,
+    pub activation_exit_delay: u64,
+    pub persistent_committee_period: u64,
+    pub min_validator_withdrawability_delay: u64,
+    pub eth1_follow_distance: u64,
+
+    // Committees
+    pub max_committees_per_slot: u64,
+    pub target_committee_size: u64,
+    pub shuffle_round_count: u64,
+    pub min_seed_lookahead: u64,
+    pub min_attestation_inclusion_delay: u64,
+    pub max_epochs_per_crosslink: u64,
+
+    // Gwei values
+    pub min_deposit_amount: u64,
+    pub max_effective_balance: u64,
+    pub ejection_balance: u64,
+    pub effective_balance_increment: u64,
+    pub bls_withdrawal_prefix: u8,
+
+    // Rewards and penalties
+    pub base_reward_factor: u64,
+    pub whistleblower_reward_quotient: u64,
+    pub proposer_reward_quotient: u64,
+    pub inactivity_penalty_quotient: u64,
+    pub min_slashing_penalty_quotient: u64,
+    pub min_epochs_to_inactivity_penalty: u64,
+}
+
+impl ChainSpec {
+    /// Constants hard-coded into the preset `C`.
+    pub fn from_config<C: Config>() -> ChainSpec {
+        ChainSpec {
+            seconds_per_slot: C::millisecons_per_slot() / 1000,
+            genesis_fork_version: C::genesis_fork_version(),
+            min_genesis_time: C::min_genesis_time(),
+            min_genesis_active_validator_count: C::min_genesis_active_validator_count(),
+            domain_beacon_proposer: C::domain_beacon_proposer(),
+            domain_beacon_attester: C::domain_attestation(),
+            domain_randao: C::domain_randao(),
+            domain_deposit: C::domain_deposit(),
+            domain_voluntary_exit: C::domain_voluntary_exit(),
+            domain_transfer: C::domain_transfer(),
+            churn_limit_quotient: C::churn_limit_quotient(),
+            min_per_epoch_churn_limit: C::min_per_epoch_churn_limit(),
+            activation_exit_delay: C::activation_exit_delay(),
+            persistent_committee_period: C::persistent_committee_period(),
+            min_validator_withdrawability_delay: C::min_validator_withdrawability_delay(),
+            eth1_follow_distance: C::eth1_follow_distance(),
+            max_committees_per_slot: C::max_committees_per_slot(),
+            target_committee_size: C::target_committee_size(),
+            shuffle_round_count: C::shuffle_round_count(),
+            min_seed_lookahead: C::min_seed_lookahead(),
+            min_attestation_inclusion_delay: C::min_attestation_inclusion_delay(),
+            max_epochs_per_crosslink: C::max_epochs_per_crosslink(),
+            min_deposit_amount: C::min_deposit_amount(),
+            max_effective_balance: C::max_effective_balance(),
+            ejection_balance: C::ejection_balance(),
+            effective_balance_increment: C::effective_balance_increment(),
+            bls_withdrawal_prefix: C::bls_withdrawal_prefix_byte(),
+            base_reward_factor: C::base_reward_factor(),
+            whistleblower_reward_quotient: C::whistleblower_reward_quotient(),
+            proposer_reward_quotient: C::proposer_reward_quotient(),
+            inactivity_penalty_quotient: C::inactivity_penalty_quotient(),
+            min_slashing_penalty_quotient: C::min_slashing_penalty_quotient(),
+            min_epochs_to_inactivity_penalty: C::min_epochs_to_inactivity_penalty(),
+        }
+    }
+
+    /// Reads an eth2 YAML config file, see `from_yaml_str`. JSON files are accepted as well.
+    pub fn from_file<C: Config>(path: &Path) -> Result<ChainSpec, Error> {
+        let yaml = fs::read_to_string(path).map_err(|e| Error::IOError(e.to_string()))?;
+        ChainSpec::from_yaml_str::<C>(&yaml)
+    }
+
+    /// Parses an eth2 YAML config. Constants missing from it are taken from the preset `C`, unknown
+    /// ones are ignored, and the type-level constants it contains must match those of `C`.
+    pub fn from_yaml_str<C: Config>(yaml: &str) -> Result<ChainSpec, Error> {
+        let config: Mapping =
+            serde_yaml::from_str(yaml).map_err(|e| Error::InvalidYaml(e.to_string()))?;
+        check_preset::<C>(&config)?;
+
+        let mut chain_spec = ChainSpec::from_config::<C>();
+        let mut spec = match serde_yaml::to_value(&chain_spec) {
+            Ok(Value::Mapping(spec)) => spec,
+            _ => unreachable!("ChainSpec serializes to a mapping"),
+        };
+        for (name, value) in config {
+            if !spec.contains_key(&name) {
+                continue;
+            }
+            // Applying one constant at a time lets the error name the invalid one.
+            spec.insert(name.clone(), value);
+            chain_spec = serde_yaml::from_value(Value::Mapping(spec.clone())).map_err(|e| {
+                Error::InvalidValue(format!("{}: {}", name.as_str().unwrap_or_default(), e))
+            })?;
+        }
+        if chain_spec.seconds_per_slot == 0 {
+            return Err(Error::InvalidValue(String::from(
+                "SECONDS_PER_SLOT: must be positive",
+            )));
+        }
+        Ok(chain_spec)
+    }
+
+    pub fn slot_duration(&self) -> Duration {
+        Duration::from_secs(self.seconds_per_slot)
+    }
+}
+
+/// Type-level constants of the preset `C`, by their name in the eth2 YAML config format.
+fn preset_constants<C: Config>() -> Vec<(&'static str, u64)> {
+    vec![
+        ("SHARD_COUNT", C::ShardCount::to_u64()),
+        ("SLOTS_PER_EPOCH", C::SlotsPerEpoch::to_u64()),
+        (
+            "SLOTS_PER_ETH1_VOTING_PERIOD",
+            C::SlotsPerEth1VotingPeriod::to_u64(),
+        ),
+        (
+            "SLOTS_PER_HISTORICAL_ROOT",
+            C::SlotsPerHistoricalRoot::to_u64(),
+        ),
+        (
+            "EPOCHS_PER_HISTORICAL_VECTOR",
+            C::EpochsPerHistoricalVector::to_u64(),
+        ),
+        (
+            "EPOCHS_PER_SLASHINGS_VECTOR",
+            C::EpochsPerSlashingsVector::to_u64(),
+        ),
+        ("HISTORICAL_ROOTS_LIMIT", C::HistoricalRootsLimit::to_u64()),
+        (
+            "VALIDATOR_REGISTRY_LIMIT",
+            C::ValidatorRegistryLimit::to_u64(),
+        ),
+        (
+            "MAX_VALIDATORS_PER_COMMITTEE",
+            C::MaxValidatorsPerCommittee::to_u64(),
+        ),
+        ("MAX_PROPOSER_SLASHINGS", C::MaxProposerSlashings::to_u64()),
+        ("MAX_ATTESTER_SLASHINGS", C::MaxAttesterSlashings::to_u64()),
+        ("MAX_ATTESTATIONS", C::MaxAttestations::to_u64()),
+        ("MAX_DEPOSITS", C::MaxDeposits::to_u64()),
+        ("MAX_VOLUNTARY_EXITS", C::MaxVoluntaryExits::to_u64()),
+        ("MAX_TRANSFERS", C::MaxTransfers::to_u64()),
+    ]
+}
+
+/// The sizes of the SSZ types are fixed at compile time, a config disagreeing with them can not be
+/// followed by this preset.
+fn check_preset<C: Config>(config: &Mapping) -> Result<(), Error> {
+    for (name, preset) in preset_constants::<C>() {
+        let value = match config.get(&Value::String(name.to_owned())) {
+            Some(value) => value,
+            None => continue,
+        };
+        match value.as_u64() {
+            Some(value) if value == preset => {}
+            Some(value) => {
+                return Err(Error::PresetMismatch {
+                    name: name.to_owned(),
+                    preset,
+                    config: value,
+                })
+            }
+            None => {
+                return Err(Error::InvalidValue(format!(
+                    "{}: expected an integer",
+                    name
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `0x` prefixed 4 byte values. YAML parsers read an unquoted `0x00000001` as an integer, whose big
+/// endian bytes are the ones written.
+mod bytes4 {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8; 4], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("0x{}", hex::encode(bytes)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 4], D::Error> {
+        deserializer.deserialize_any(Bytes4Visitor)
+    }
+
+    struct Bytes4Visitor;
+
+    impl<'de> Visitor<'de> for Bytes4Visitor {
+        type Value = [u8; 4];
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("4 bytes written as 0x followed by 8 hex digits")
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<[u8; 4], E> {
+            if value > u64::from(u32::MAX) {
+                return Err(E::custom(format!("{:#x} is longer than 4 bytes", value)));
+            }
+            Ok((value as u32).to_be_bytes())
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<[u8; 4], E> {
+            let digits = value
+                .strip_prefix("0x")
+                .ok_or_else(|| E::custom(format!("{} is not 0x prefixed", value)))?;
+            let decoded = hex::decode(digits).map_err(E::custom)?;
+            if decoded.len() != 4 {
+                return Err(E::custom(format!("{} is not 4 bytes long", value)));
+            }
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&decoded);
+            Ok(bytes)
+        }
+    }
+}
+
+/// Domain types are 4 byte values, stored as the little endian integer `compute_domain` expects.
+mod domain_type {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        domain_type: &DomainType,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        bytes4::serialize(&domain_type.to_le_bytes(), serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DomainType, D::Error> {
+        bytes4::deserialize(deserializer).map(DomainType::from_le_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_default_to_preset_constants() {
+        let spec = ChainSpec::from_yaml_str::<MinimalConfig>("PRESET_BASE: minimal").unwrap();
+        assert_eq!(spec, ChainSpec::from_config::<MinimalConfig>());
+    }
+
+    #[test]
+    fn should_round_trip_yaml() {
+        let spec = ChainSpec {
+            seconds_per_slot: 3,
+            genesis_fork_version: [0, 0, 0x20, 0x09],
+            domain_randao: 7,
+            ..ChainSpec::from_config::<MinimalConfig>()
+        };
+        let yaml = serde_yaml::to_string(&spec).unwrap();
+        assert_eq!(ChainSpec::from_yaml_str::<MinimalConfig>(&yaml), Ok(spec));
+    }
+
+    #[test]
+    fn should_parse_config_constants() {
+        let yaml = "
+            SECONDS_PER_SLOT: 14
+            MIN_GENESIS_TIME: 1606824000
+            SLOTS_PER_EPOCH: 8
+            DOMAIN_RANDAO: '0x02000000'
+            DEPOSIT_CONTRACT_ADDRESS: 0x00000000219ab540356cBB839Cbe05303d7705Fa
+        ";
+        let spec = ChainSpec::from_yaml_str::<MinimalConfig>(yaml).unwrap();
+
+        assert_eq!(spec.slot_duration(), Duration::from_secs(14));
+        assert_eq!(spec.min_genesis_time, 1_606_824_000);
+        assert_eq!(spec.domain_randao, 2);
+        assert_eq!(
+            spec.persistent_committee_period,
+            MinimalConfig::persistent_committee_period()
+        );
+    }
+
+    #[test]
+    fn should_read_fork_version_with_or_without_quotes() {
+        for yaml in &[
+            "GENESIS_FORK_VERSION: 0x00000001",
+            "GENESIS_FORK_VERSION: '0x00000001'",
+        ] {
+            let spec = ChainSpec::from_yaml_str::<MinimalConfig>(yaml).unwrap();
+            assert_eq!(spec.genesis_fork_version, [0, 0, 0, 1]);
+        }
+    }
+
+    #[test]
+    fn should_refuse_config_of_other_preset() {
+        assert_eq!(
+            ChainSpec::from_yaml_str::<MinimalConfig>("SLOTS_PER_EPOCH: 32"),
+            Err(Error::PresetMismatch {
+                name: String::from("SLOTS_PER_EPOCH"),
+                preset: 8,
+                config: 32,
+            })
+        );
+    }
+
+    #[test]
+    fn should_name_invalid_constant() {
+        let invalid_value = |yaml| match ChainSpec::from_yaml_str::<MinimalConfig>(yaml) {
+            Err(Error::InvalidValue(message)) => message,
+            result => panic!("{:?} is not an invalid value", result),
+        };
+
+        assert!(invalid_value("MIN_GENESIS_TIME: soon").starts_with("MIN_GENESIS_TIME: "));
+        assert!(invalid_value("GENESIS_FORK_VERSION: 0x0100000000")
+            .starts_with("GENESIS_FORK_VERSION: "));
+        assert!(invalid_value("DOMAIN_DEPOSIT: '0x0300'").starts_with("DOMAIN_DEPOSIT: "));
+        assert_eq!(
+            invalid_value("SLOTS_PER_EPOCH: eight"),
+            "SLOTS_PER_EPOCH: expected an integer"
+        );
+        assert_eq!(
+            invalid_value("SECONDS_PER_SLOT: 0"),
+            "SECONDS_PER_SLOT: must be positive"
+        );
+    }
+
+    #[test]
+    fn should_refuse_invalid_yaml() {
+        assert!(matches!(
+            ChainSpec::from_yaml_str::<MinimalConfig>("- SECONDS_PER_SLOT"),
+            Err(Error::InvalidYaml(_))
+        ));
+    }
+}
@@ -10,7 +10,18 @@ use crate::primitives::{DomainType, ValidatorIndex};
 
 pub trait Config
 where
-    Self: Clone + Copy + PartialEq + Eq + Hash + PartialOrd + Ord + Default + Debug,
+    Self: Clone
+        + Copy
+        + PartialEq
+        + Eq
+        + Hash
+        + PartialOrd
+        + Ord
+        + Default
+        + Debug
+        + Send
+        + Sync
+        + 'static,
 {
     type EpochsPerSlashingsVector: Unsigned
         + Clone
@@ -21,7 +32,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
     type EpochsPerHistoricalVector: Unsigned
         + Clone
         + Copy
@@ -31,7 +44,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
     type HistoricalRootsLimit: Unsigned
         + Clone
         + Copy
@@ -41,7 +56,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
     type MaxAttesterSlashings: Unsigned
         + Clone
         + Copy
@@ -51,7 +68,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
     type MaxAttestations: Unsigned
         + Clone
         + Copy
@@ -61,7 +80,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
     type MaxAttestationsPerEpoch: Unsigned
         + Clone
         + Copy
@@ -71,7 +92,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
     type MaxDeposits: Unsigned
         + Clone
         + Copy
@@ -81,7 +104,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
     type MaxProposerSlashings: Unsigned
         + Clone
         + Copy
@@ -91,7 +116,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
     type MaxTransfers: Unsigned
         + Clone
         + Copy
@@ -101,7 +128,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
     type MaxValidatorsPerCommittee: Unsigned
         + Clone
         + Copy
@@ -111,7 +140,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
     type MaxVoluntaryExits: Unsigned
         + Clone
         + Copy
@@ -121,7 +152,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
     type ShardCount: Unsigned
         + Clone
         + Copy
@@ -131,7 +164,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
     type SlotsPerEpoch: Unsigned
         + Clone
         + Copy
@@ -141,7 +176,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
     type SlotsPerEth1VotingPeriod: Unsigned
         + Clone
         + Copy
@@ -151,7 +188,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
     type SlotsPerHistoricalRoot: Unsigned
         + Clone
         + Copy
@@ -161,7 +200,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
     type ValidatorRegistryLimit: Unsigned
         + Clone
         + Copy
@@ -171,7 +212,9 @@ where
         + PartialOrd
         + Ord
         + Default
-        + Debug;
+        + Debug
+        + Send
+        + Sync;
 
     fn activation_exit_delay() -> u64 {
         4
@@ -209,15 +252,24 @@ where
     fn ejection_balance() -> u64 {
         16_000_000_000
     }
+    fn eth1_follow_distance() -> u64 {
+        1024
+    }
     fn genesis_epoch() -> u64 {
         0
     }
+    fn genesis_fork_version() -> [u8; 4] {
+        [0; 4]
+    }
     fn genesis_slot() -> u64 {
         0
     }
     fn inactivity_penalty_quotient() -> u64 {
         2_u64.pow(25)
     }
+    fn max_committees_per_slot() -> u64 {
+        64
+    }
     fn max_effective_balance() -> u64 {
         32_000_000_000
     }
@@ -320,6 +372,13 @@ impl Config for MinimalConfig {
     type SlotsPerEth1VotingPeriod = typenum::U16;
     type SlotsPerHistoricalRoot = typenum::U64;
     type ValidatorRegistryLimit = typenum::U1099511627776;
+
+    fn eth1_follow_distance() -> u64 {
+        16
+    }
+    fn max_committees_per_slot() -> u64 {
+        4
+    }
 }
 
 #[derive(
@@ -345,6 +404,12 @@ impl Config for QuickConfig {
     type SlotsPerHistoricalRoot = typenum::U64;
     type ValidatorRegistryLimit = typenum::U1099511627776;
 
+    fn eth1_follow_distance() -> u64 {
+        16
+    }
+    fn max_committees_per_slot() -> u64 {
+        4
+    }
     fn min_genesis_active_validator_count() -> ValidatorIndex {
         1
     }
@@ -1,5 +1,6 @@
 pub const JUSTIFICATION_BITS_LENGTH: usize = 4;
 pub const SECONDS_PER_DAY: u64 = 86400;
 pub const DEPOSIT_CONTRACT_TREE_DEPTH: u64 = 32;
+pub const FAR_FUTURE_EPOCH: u64 = u64::MAX;
 pub type DepositContractTreeDepth = typenum::U32;
 pub type JustificationBitsLength = typenum::U4;
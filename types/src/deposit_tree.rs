@@ -0,0 +1,166 @@
+use eth2_hashing::hash;
+
+use crate::{consts, helper_functions::int_to_bytes8, primitives::*};
+
+/// Incremental Merkle tree of deposit data roots, mirroring the eth1 deposit contract.
+pub struct DepositTree {
+    layers: Vec<Vec<H256>>,
+    zero_hashes: Vec<H256>,
+}
+
+impl Default for DepositTree {
+    fn default() -> DepositTree {
+        DepositTree::new()
+    }
+}
+
+impl DepositTree {
+    pub fn new() -> DepositTree {
+        let depth = consts::DEPOSIT_CONTRACT_TREE_DEPTH as usize;
+        let mut zero_hashes = vec![H256::zero()];
+        for level in 0..depth {
+            zero_hashes.push(hash_pair(zero_hashes[level], zero_hashes[level]));
+        }
+        DepositTree {
+            layers: vec![Vec::new(); depth + 1],
+            zero_hashes,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.layers[0].len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers[0].is_empty()
+    }
+
+    pub fn push(&mut self, leaf: H256) {
+        let mut index = self.layers[0].len();
+        self.layers[0].push(leaf);
+        for level in 0..consts::DEPOSIT_CONTRACT_TREE_DEPTH as usize {
+            let parent = hash_pair(self.node(level, index & !1), self.node(level, index | 1));
+            index /= 2;
+            if index < self.layers[level + 1].len() {
+                self.layers[level + 1][index] = parent;
+            } else {
+                self.layers[level + 1].push(parent);
+            }
+        }
+    }
+
+    /// Root of the tree with the number of deposits mixed in, as in `Eth1Data::deposit_root`.
+    pub fn root(&self) -> H256 {
+        let depth = consts::DEPOSIT_CONTRACT_TREE_DEPTH as usize;
+        hash_pair(self.node(depth, 0), self.length_node())
+    }
+
+    /// Merkle branch of deposit `index` against the current `root`, including the length node.
+    pub fn proof(&self, index: u64) -> Vec<H256> {
+        let mut index = index as usize;
+        let mut branch = Vec::with_capacity(consts::DEPOSIT_CONTRACT_TREE_DEPTH as usize + 1);
+        for level in 0..consts::DEPOSIT_CONTRACT_TREE_DEPTH as usize {
+            branch.push(self.node(level, index ^ 1));
+            index /= 2;
+        }
+        branch.push(self.length_node());
+        branch
+    }
+
+    fn node(&self, level: usize, index: usize) -> H256 {
+        self.layers[level]
+            .get(index)
+            .copied()
+            .unwrap_or(self.zero_hashes[level])
+    }
+
+    fn length_node(&self) -> H256 {
+        let mut length = [0; 32];
+        length[..8].copy_from_slice(&int_to_bytes8(self.len()));
+        H256::from(length)
+    }
+}
+
+fn hash_pair(left: H256, right: H256) -> H256 {
+    let mut input = left.as_bytes().to_vec();
+    input.extend_from_slice(right.as_bytes());
+    H256::from_slice(&hash(&input))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper_functions::is_valid_merkle_branch;
+
+    fn leaf(index: u64) -> H256 {
+        H256::from_low_u64_le(index + 1)
+    }
+
+    #[test]
+    fn should_prove_every_deposit_against_root() {
+        let mut deposit_tree = DepositTree::new();
+        for count in 1..=9 {
+            deposit_tree.push(leaf(count - 1));
+            let root = deposit_tree.root();
+            for index in 0..count {
+                assert!(is_valid_merkle_branch(
+                    leaf(index),
+                    &deposit_tree.proof(index),
+                    consts::DEPOSIT_CONTRACT_TREE_DEPTH + 1,
+                    index,
+                    root,
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn should_refuse_proof_of_other_leaf_or_count() {
+        let mut deposit_tree = DepositTree::new();
+        for index in 0..3 {
+            deposit_tree.push(leaf(index));
+        }
+        let depth = consts::DEPOSIT_CONTRACT_TREE_DEPTH + 1;
+        let proof = deposit_tree.proof(1);
+
+        assert!(!is_valid_merkle_branch(
+            leaf(2),
+            &proof,
+            depth,
+            1,
+            deposit_tree.root()
+        ));
+        assert!(!is_valid_merkle_branch(
+            leaf(1),
+            &proof,
+            depth,
+            2,
+            deposit_tree.root()
+        ));
+        deposit_tree.push(leaf(3));
+        assert!(!is_valid_merkle_branch(
+            leaf(1),
+            &proof,
+            depth,
+            1,
+            deposit_tree.root()
+        ));
+    }
+
+    #[test]
+    fn should_mix_deposit_count_into_root() {
+        let mut deposit_tree = DepositTree::new();
+        assert!(deposit_tree.is_empty());
+        assert_eq!(
+            deposit_tree.root(),
+            hash_pair(
+                deposit_tree.zero_hashes[consts::DEPOSIT_CONTRACT_TREE_DEPTH as usize],
+                H256::zero()
+            )
+        );
+
+        deposit_tree.push(H256::zero());
+        assert_eq!(deposit_tree.len(), 1);
+        assert_ne!(deposit_tree.root(), DepositTree::new().root());
+    }
+}
@@ -0,0 +1,209 @@
+use bls::{Keypair, PublicKey, PublicKeyBytes, SecretKey, Signature};
+use eth2_hashing::hash;
+use num_bigint::BigUint;
+use ssz_types::FixedVector;
+use tree_hash::{SignedRoot, TreeHash};
+
+use crate::beacon_state::BeaconState;
+use crate::deposit_tree::DepositTree;
+use crate::state_transition::{block_processing::process_deposit, Error};
+use crate::{
+    chain_spec::ChainSpec, config::*, consts, helper_functions::*, primitives::*, types::*,
+};
+
+/// Order of the BLS12-381 curve.
+const CURVE_ORDER: &str =
+    "52435875175126190479447740508185965837690552500527637822603658699938581184513";
+const INTEROP_ETH1_BLOCK_HASH: [u8; 32] = [0x42; 32];
+const INTEROP_ETH1_TIMESTAMP: u64 = 1 << 40;
+
+/// Genesis state built from the deposits made up to the eth1 block `eth1_block_hash`.
+pub fn initialize_beacon_state_from_eth1<C: Config>(
+    eth1_block_hash: H256,
+    eth1_timestamp: u64,
+    deposits: &[Deposit],
+    spec: &ChainSpec,
+) -> Result<BeaconState<C>, Error> {
+    let mut state = BeaconState::<C> {
+        genesis_time: eth1_timestamp - eth1_timestamp % consts::SECONDS_PER_DAY
+            + 2 * consts::SECONDS_PER_DAY,
+        fork: Fork {
+            previous_version: spec.genesis_fork_version,
+            current_version: spec.genesis_fork_version,
+            epoch: C::genesis_epoch(),
+        },
+        eth1_data: Eth1Data {
+            block_hash: eth1_block_hash,
+            deposit_count: deposits.len() as u64,
+            deposit_root: H256::zero(),
+        },
+        latest_block_header: BeaconBlockHeader {
+            body_root: H256::from_slice(&empty_block_body::<C>().tree_hash_root()),
+            ..BeaconBlockHeader::default()
+        },
+        block_roots: FixedVector::from(vec![]),
+        state_roots: FixedVector::from(vec![]),
+        randao_mixes: FixedVector::from_elem(eth1_block_hash),
+        slashings: FixedVector::from(vec![]),
+        ..BeaconState::default()
+    };
+
+    // Process deposits, each against the deposit root of the deposits made up to it
+    let mut deposit_tree = DepositTree::new();
+    for deposit in deposits {
+        deposit_tree.push(H256::from_slice(&deposit.data.tree_hash_root()));
+        state.eth1_data.deposit_root = deposit_tree.root();
+        process_deposit(&mut state, deposit, spec)?;
+    }
+
+    // Process activations
+    for (validator, balance) in state.validators.iter_mut().zip(state.balances.iter()) {
+        validator.effective_balance =
+            (balance - balance % spec.effective_balance_increment).min(spec.max_effective_balance);
+        if validator.effective_balance == spec.max_effective_balance {
+            validator.activation_eligibility_epoch = C::genesis_epoch();
+            validator.activation_epoch = C::genesis_epoch();
+        }
+    }
+    Ok(state)
+}
+
+pub fn is_valid_genesis_state<C: Config>(state: &BeaconState<C>, spec: &ChainSpec) -> bool {
+    state.genesis_time >= spec.min_genesis_time
+        && state.get_active_validator_indices(C::genesis_epoch()).len() as u64
+            >= spec.min_genesis_active_validator_count
+}
+
+/// Insecure, publicly known keypair of validator `index` as used by interop test chains.
+pub fn interop_keypair(index: u64) -> Keypair {
+    let mut preimage = [0; 32];
+    preimage[..8].copy_from_slice(&int_to_bytes8(index));
+    let curve_order = CURVE_ORDER.parse::<BigUint>().unwrap();
+    let secret = BigUint::from_bytes_le(&hash(&preimage)) % curve_order;
+
+    let secret_bytes = secret.to_bytes_be();
+    let mut bytes = vec![0; 48 - secret_bytes.len()];
+    bytes.extend_from_slice(&secret_bytes);
+    let sk = SecretKey::from_bytes(&bytes).expect("keys below the curve order are valid");
+    Keypair {
+        pk: PublicKey::from_secret_key(&sk),
+        sk,
+    }
+}
+
+/// Signed maximum deposits of `keypairs` with proofs against the deposit root after each of
+/// them, as `initialize_beacon_state_from_eth1` expects.
+pub fn interop_deposits(keypairs: &[Keypair], spec: &ChainSpec) -> Vec<Deposit> {
+    let domain = compute_domain(spec.domain_deposit, spec.genesis_fork_version);
+    let mut deposit_tree = DepositTree::new();
+    keypairs
+        .iter()
+        .map(|keypair| {
+            let mut data = DepositData {
+                pubkey: PublicKeyBytes::from_bytes(&keypair.pk.as_bytes())
+                    .expect("public keys serialize to valid bytes"),
+                withdrawal_credentials: H256::from_slice(&bls::get_withdrawal_credentials(
+                    &keypair.pk,
+                    spec.bls_withdrawal_prefix,
+                )),
+                amount: spec.max_effective_balance,
+                signature: Signature::empty_signature(),
+            };
+            data.signature = Signature::new(&data.signed_root(), domain, &keypair.sk);
+
+            deposit_tree.push(H256::from_slice(&data.tree_hash_root()));
+            Deposit {
+                proof: FixedVector::from(deposit_tree.proof(deposit_tree.len() - 1)),
+                data,
+            }
+        })
+        .collect()
+}
+
+/// Genesis state of a test chain with `validator_count` active interop validators, starting at
+/// `genesis_time`. The chain uses the constants of the preset `C`.
+pub fn interop_genesis_state<C: Config>(
+    validator_count: u64,
+    genesis_time: u64,
+) -> Result<BeaconState<C>, Error> {
+    let spec = ChainSpec::from_config::<C>();
+    let keypairs: Vec<Keypair> = (0..validator_count).map(interop_keypair).collect();
+    let mut state = initialize_beacon_state_from_eth1(
+        H256::from(INTEROP_ETH1_BLOCK_HASH),
+        INTEROP_ETH1_TIMESTAMP,
+        &interop_deposits(&keypairs, &spec),
+        &spec,
+    )?;
+    state.genesis_time = genesis_time;
+    Ok(state)
+}
+
+fn empty_block_body<C: Config>() -> BeaconBlockBody<C> {
+    BeaconBlockBody {
+        randao_reveal: Signature::empty_signature(),
+        eth1_data: Eth1Data::default(),
+        graffiti: [0; 32],
+        proposer_slashings: Default::default(),
+        attester_slashings: Default::default(),
+        attestations: Default::default(),
+        deposits: Default::default(),
+        voluntary_exits: Default::default(),
+        transfers: Default::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssz::Encode;
+
+    #[test]
+    fn should_derive_interop_secret_keys() {
+        // Keys published with the interop mocked start specification.
+        let secret_keys = [
+            "25295f0d1d592a90b333e26e85149708208e9f8e8bc18f6c77bd62f8ad7a6866",
+            "51d0b65185db6989ab0b560d6deed19c7ead0e24b9b6372cbecb1f26bdfad000",
+            "315ed405fafe339603932eebe8dbfd650ce5dafa561f6928664c75db85f97857",
+            "25b1166a43c109cb330af8945d364722757c65ed2bfed5444b5a2f057f82d391",
+            "3f5615898238c4c4f906b507ee917e9ea1bb69b93f1dbd11a34d229c3b06784b",
+        ];
+        for (index, secret_key) in secret_keys.iter().enumerate() {
+            let mut expected = vec![0; 16];
+            expected.extend(hex::decode(secret_key).unwrap());
+            assert_eq!(interop_keypair(index as u64).sk.as_ssz_bytes(), expected);
+        }
+    }
+
+    #[test]
+    fn should_activate_validators_with_maximum_deposits() {
+        let state = interop_genesis_state::<MinimalConfig>(4, 0).unwrap();
+
+        assert_eq!(state.validators.len(), 4);
+        assert_eq!(state.eth1_deposit_index, 4);
+        assert_eq!(state.eth1_data.deposit_count, 4);
+        assert_eq!(state.get_active_validator_indices(0), vec![0, 1, 2, 3]);
+        assert_eq!(state.randao_mixes[0], H256::from(INTEROP_ETH1_BLOCK_HASH));
+    }
+
+    #[test]
+    fn should_require_minimum_genesis_time() {
+        let spec = ChainSpec::from_config::<QuickConfig>();
+        let state = interop_genesis_state::<QuickConfig>(1, spec.min_genesis_time).unwrap();
+        assert!(is_valid_genesis_state(&state, &spec));
+
+        let state = interop_genesis_state::<QuickConfig>(1, spec.min_genesis_time - 1).unwrap();
+        assert!(!is_valid_genesis_state(&state, &spec));
+    }
+
+    #[test]
+    fn should_require_minimum_active_validator_count() {
+        let spec = ChainSpec::from_config::<MinimalConfig>();
+        let count = spec.min_genesis_active_validator_count;
+        let mut state =
+            interop_genesis_state::<MinimalConfig>(count, spec.min_genesis_time).unwrap();
+        assert!(is_valid_genesis_state(&state, &spec));
+
+        state.validators[0].activation_epoch = consts::FAR_FUTURE_EPOCH;
+        assert!(!is_valid_genesis_state(&state, &spec));
+    }
+}
@@ -0,0 +1,232 @@
+use eth2_hashing::hash;
+use typenum::Unsigned;
+
+use crate::{chain_spec::ChainSpec, config::*, primitives::*, types::*};
+
+pub fn compute_epoch_at_slot<C: Config>(slot: Slot) -> Epoch {
+    slot / C::SlotsPerEpoch::to_u64()
+}
+
+pub fn compute_start_slot_at_epoch<C: Config>(epoch: Epoch) -> Slot {
+    epoch * C::SlotsPerEpoch::to_u64()
+}
+
+/// Epoch at which an activation or exit initiated at `epoch` takes effect.
+pub fn compute_activation_exit_epoch(epoch: Epoch, spec: &ChainSpec) -> Epoch {
+    epoch + 1 + spec.activation_exit_delay
+}
+
+pub fn is_active_validator(validator: &Validator, epoch: Epoch) -> bool {
+    validator.activation_epoch <= epoch && epoch < validator.exit_epoch
+}
+
+pub fn is_slashable_validator(validator: &Validator, epoch: Epoch) -> bool {
+    !validator.slashed
+        && validator.activation_epoch <= epoch
+        && epoch < validator.withdrawable_epoch
+}
+
+/// Whether the two attestations are a double vote or one surrounds the other.
+pub fn is_slashable_attestation_data(data_1: &AttestationData, data_2: &AttestationData) -> bool {
+    (data_1 != data_2 && data_1.target.epoch == data_2.target.epoch)
+        || (data_1.source.epoch < data_2.source.epoch && data_2.target.epoch < data_1.target.epoch)
+}
+
+/// Whether `leaf` is at `index` in the Merkle tree of `depth` with the given `root`.
+pub fn is_valid_merkle_branch(
+    leaf: H256,
+    branch: &[H256],
+    depth: u64,
+    index: u64,
+    root: H256,
+) -> bool {
+    if branch.len() < depth as usize {
+        return false;
+    }
+    let mut value = leaf;
+    for (i, node) in branch.iter().take(depth as usize).enumerate() {
+        let mut input = Vec::with_capacity(64);
+        if (index >> i) % 2 == 1 {
+            input.extend_from_slice(node.as_bytes());
+            input.extend_from_slice(value.as_bytes());
+        } else {
+            input.extend_from_slice(value.as_bytes());
+            input.extend_from_slice(node.as_bytes());
+        }
+        value = H256::from_slice(&hash(&input));
+    }
+    value == root
+}
+
+pub fn compute_domain(domain_type: DomainType, fork_version: [u8; 4]) -> Domain {
+    let mut bytes = [0; 8];
+    bytes[..4].copy_from_slice(&int_to_bytes4(domain_type));
+    bytes[4..].copy_from_slice(&fork_version);
+    u64::from_le_bytes(bytes)
+}
+
+/// Largest integer `x` such that `x * x <= n`.
+pub fn integer_squareroot(n: u64) -> u64 {
+    let mut x = n;
+    let mut y = x / 2 + x % 2;
+    while y < x {
+        x = y;
+        y = (x + n / x) / 2;
+    }
+    x
+}
+
+pub fn int_to_bytes4(value: u32) -> [u8; 4] {
+    value.to_le_bytes()
+}
+
+pub fn int_to_bytes8(value: u64) -> [u8; 8] {
+    value.to_le_bytes()
+}
+
+/// Position of `index` after shuffling `0..index_count` with the swap-or-not algorithm,
+/// `None` if `index` is out of range.
+pub fn compute_shuffled_index(
+    index: u64,
+    index_count: u64,
+    seed: &[u8],
+    spec: &ChainSpec,
+) -> Option<u64> {
+    if index >= index_count {
+        return None;
+    }
+
+    let mut index = index;
+    for current_round in 0..spec.shuffle_round_count {
+        let round = current_round as u8;
+        let mut pivot_input = seed.to_vec();
+        pivot_input.push(round);
+        let mut pivot_bytes = [0; 8];
+        pivot_bytes.copy_from_slice(&hash(&pivot_input)[..8]);
+        let pivot = u64::from_le_bytes(pivot_bytes) % index_count;
+
+        let flip = (pivot + index_count - index) % index_count;
+        let position = if index > flip { index } else { flip };
+
+        let mut source_input = seed.to_vec();
+        source_input.push(round);
+        source_input.extend_from_slice(&int_to_bytes4((position / 256) as u32));
+        let source = hash(&source_input);
+        let byte = source[((position % 256) / 8) as usize];
+        let bit = (byte >> (position % 8)) % 2;
+
+        if bit == 1 {
+            index = flip;
+        }
+    }
+    Some(index)
+}
+
+/// Committee number `index` out of `count` committees cut from the shuffled `indices`.
+pub fn compute_committee(
+    indices: &[ValidatorIndex],
+    seed: &[u8],
+    index: u64,
+    count: u64,
+    spec: &ChainSpec,
+) -> Option<Vec<ValidatorIndex>> {
+    let len = indices.len() as u64;
+    let start = len * index / count;
+    let end = len * (index + 1) / count;
+    (start..end)
+        .map(|i| {
+            compute_shuffled_index(i, len, seed, spec).map(|shuffled| indices[shuffled as usize])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shuffles of `0..mapping.len()` in the format of the eth2 `shuffling` spec tests, for the
+    /// minimal preset and the seed `hash(int_to_bytes4(seed_index))`.
+    const SHUFFLINGS: &[(u32, &[u64])] = &[
+        (0, &[0]),
+        (0, &[1, 0]),
+        (0, &[1, 2, 0]),
+        (0, &[3, 4, 0, 1, 2]),
+        (0, &[8, 9, 6, 7, 4, 3, 5, 1, 0, 2]),
+        (
+            0,
+            &[
+                30, 0, 28, 2, 11, 18, 13, 7, 3, 6, 32, 22, 4, 5, 12, 8, 21, 14, 27, 16, 20, 1, 23,
+                10, 24, 25, 26, 9, 31, 29, 19, 15, 17,
+            ],
+        ),
+        (1, &[0, 1]),
+        (1, &[0, 2, 1]),
+        (1, &[4, 1, 0, 3, 2]),
+        (1, &[2, 3, 9, 8, 7, 4, 6, 0, 5, 1]),
+        (
+            1,
+            &[
+                16, 26, 25, 20, 17, 22, 10, 19, 4, 7, 6, 11, 9, 0, 31, 12, 23, 14, 13, 18, 32, 15,
+                3, 29, 30, 2, 1, 28, 5, 8, 24, 27, 21,
+            ],
+        ),
+    ];
+
+    #[test]
+    fn should_delay_activations_and_exits() {
+        let mut spec = ChainSpec::from_config::<MinimalConfig>();
+        assert_eq!(compute_activation_exit_epoch(0, &spec), 5);
+        assert_eq!(
+            compute_activation_exit_epoch(10, &spec),
+            11 + spec.activation_exit_delay
+        );
+        spec.activation_exit_delay = 0;
+        assert_eq!(compute_activation_exit_epoch(10, &spec), 11);
+    }
+
+    #[test]
+    fn should_convert_between_slots_and_epochs() {
+        assert_eq!(compute_epoch_at_slot::<MinimalConfig>(7), 0);
+        assert_eq!(compute_epoch_at_slot::<MinimalConfig>(8), 1);
+        assert_eq!(compute_start_slot_at_epoch::<MinimalConfig>(3), 24);
+    }
+
+    #[test]
+    fn should_compute_domain() {
+        assert_eq!(
+            compute_domain(
+                MinimalConfig::domain_deposit(),
+                MinimalConfig::genesis_fork_version()
+            ),
+            3
+        );
+        assert_eq!(compute_domain(3, [1, 0, 0, 0]), 3 + (1 << 32));
+        assert_eq!(compute_domain(0x0102, [0, 0, 0, 1]), 0x0102 + (1 << 56));
+    }
+
+    #[test]
+    fn should_calculate_integer_squareroot() {
+        assert_eq!(integer_squareroot(0), 0);
+        assert_eq!(integer_squareroot(15), 3);
+        assert_eq!(integer_squareroot(16), 4);
+        assert_eq!(integer_squareroot(1024), 32);
+        assert_eq!(integer_squareroot(u64::MAX), u64::from(u32::MAX));
+    }
+
+    #[test]
+    fn should_match_shuffling_vectors() {
+        let spec = ChainSpec::from_config::<MinimalConfig>();
+        for (seed_index, mapping) in SHUFFLINGS {
+            let seed = hash(&int_to_bytes4(*seed_index));
+            let count = mapping.len() as u64;
+            let shuffled: Vec<u64> = (0..count)
+                .map(|index| compute_shuffled_index(index, count, &seed, &spec).unwrap())
+                .collect();
+            assert_eq!(&shuffled, mapping);
+        }
+        assert_eq!(
+            hex::encode(hash(&int_to_bytes4(0))),
+            "df3f619804a92fdb4057192dc43dd748ea778adc52bc498ce80524c014b81119"
+        );
+    }
+}
@@ -1,5 +1,10 @@
 pub mod beacon_state;
+pub mod chain_spec;
 pub mod config;
 pub mod consts;
+pub mod deposit_tree;
+pub mod genesis;
+pub mod helper_functions;
 pub mod primitives;
+pub mod state_transition;
 pub mod types;
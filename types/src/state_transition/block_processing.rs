@@ -0,0 +1,1172 @@
+use bls::{verify_signature_sets, PublicKey, SignatureSet, SignedMessage};
+use eth2_hashing::hash;
+use std::collections::BTreeSet;
+use tree_hash::{SignedRoot, TreeHash};
+use typenum::Unsigned;
+
+use super::Error;
+use crate::beacon_state::BeaconState;
+use crate::{
+    chain_spec::ChainSpec, config::*, consts, helper_functions::*, primitives::*, types::*,
+};
+
+/// How `process_block` checks the signatures in a block.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BlockSignatureStrategy {
+    /// Trust the signatures, e.g. for blocks produced locally.
+    NoVerification,
+    /// Verify every signature as its operation is processed.
+    VerifyIndividual,
+    /// Verify all signatures at once before processing, which is considerably faster.
+    VerifyBulk,
+}
+
+/// Applies `block` on top of `state`, which has to be advanced to the slot of the block already.
+///
+/// Deposit signatures are always verified as invalid deposits are skipped instead of invalidating
+/// the block.
+pub fn process_block<C: Config>(
+    state: &mut BeaconState<C>,
+    block: &BeaconBlock<C>,
+    strategy: BlockSignatureStrategy,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    if strategy == BlockSignatureStrategy::VerifyBulk {
+        let signature_sets = block_signature_sets(state, block, spec)?;
+        if !verify_signature_sets(signature_sets.into_iter()) {
+            return Err(Error::InvalidSignature);
+        }
+    }
+    let verify = strategy == BlockSignatureStrategy::VerifyIndividual;
+
+    process_block_header(state, block, verify, spec)?;
+    process_randao(state, &block.body, verify, spec)?;
+    process_eth1_data(state, &block.body)?;
+    process_operations(state, &block.body, verify, spec)
+}
+
+/// Signature sets of every signature in `block` apart from deposits, checked against `state`.
+pub fn block_signature_sets<'a, C: Config>(
+    state: &'a BeaconState<C>,
+    block: &'a BeaconBlock<C>,
+    spec: &ChainSpec,
+) -> Result<Vec<SignatureSet<'a>>, Error> {
+    let mut signature_sets = vec![
+        block_proposal_signature_set(state, block, spec)?,
+        randao_signature_set(state, &block.body, spec)?,
+    ];
+    for proposer_slashing in block.body.proposer_slashings.iter() {
+        signature_sets.extend(proposer_slashing_signature_sets(
+            state,
+            proposer_slashing,
+            spec,
+        )?);
+    }
+    for attester_slashing in block.body.attester_slashings.iter() {
+        signature_sets.push(indexed_attestation_signature_set(
+            state,
+            &attester_slashing.attestation_1,
+            spec,
+        )?);
+        signature_sets.push(indexed_attestation_signature_set(
+            state,
+            &attester_slashing.attestation_2,
+            spec,
+        )?);
+    }
+    for attestation in block.body.attestations.iter() {
+        let attesting_indices =
+            state.get_attesting_indices(&attestation.data, &attestation.aggregation_bits, spec)?;
+        signature_sets.push(attestation_signature_set(
+            state,
+            &attesting_indices,
+            &attestation.data,
+            &attestation.signature,
+            spec,
+        )?);
+    }
+    for voluntary_exit in block.body.voluntary_exits.iter() {
+        signature_sets.push(voluntary_exit_signature_set(state, voluntary_exit, spec)?);
+    }
+    Ok(signature_sets)
+}
+
+pub fn process_block_header<C: Config>(
+    state: &mut BeaconState<C>,
+    block: &BeaconBlock<C>,
+    verify_signature: bool,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    if block.slot != state.slot
+        || block.parent_root != H256::from_slice(&state.latest_block_header.signed_root())
+    {
+        return Err(Error::InvalidBlockHeader);
+    }
+    let proposer_index = state.get_beacon_proposer_index(spec)?;
+    if state.validators[proposer_index as usize].slashed {
+        return Err(Error::ProposerSlashed);
+    }
+    if verify_signature && !block_proposal_signature_set(state, block, spec)?.is_valid() {
+        return Err(Error::InvalidSignature);
+    }
+
+    state.latest_block_header = BeaconBlockHeader {
+        slot: block.slot,
+        parent_root: block.parent_root,
+        // Overwritten in the next `process_slot` call
+        state_root: H256::zero(),
+        body_root: H256::from_slice(&block.body.tree_hash_root()),
+        signature: Signature::empty_signature(),
+    };
+    Ok(())
+}
+
+pub fn process_randao<C: Config>(
+    state: &mut BeaconState<C>,
+    body: &BeaconBlockBody<C>,
+    verify_signature: bool,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    if verify_signature && !randao_signature_set(state, body, spec)?.is_valid() {
+        return Err(Error::InvalidSignature);
+    }
+
+    let epoch = state.get_current_epoch();
+    let mut mix = state.get_randao_mix(epoch)?;
+    for (byte, reveal_byte) in mix
+        .as_bytes_mut()
+        .iter_mut()
+        .zip(hash(&body.randao_reveal.as_bytes()))
+    {
+        *byte ^= reveal_byte;
+    }
+    state.randao_mixes[(epoch % C::EpochsPerHistoricalVector::to_u64()) as usize] = mix;
+    Ok(())
+}
+
+pub fn process_eth1_data<C: Config>(
+    state: &mut BeaconState<C>,
+    body: &BeaconBlockBody<C>,
+) -> Result<(), Error> {
+    state
+        .eth1_data_votes
+        .push(body.eth1_data.clone())
+        .map_err(|_| Error::ListFull)?;
+    let votes = state
+        .eth1_data_votes
+        .iter()
+        .filter(|vote| **vote == body.eth1_data)
+        .count() as u64;
+    if votes * 2 > C::SlotsPerEth1VotingPeriod::to_u64() {
+        state.eth1_data = body.eth1_data.clone();
+    }
+    Ok(())
+}
+
+pub fn process_operations<C: Config>(
+    state: &mut BeaconState<C>,
+    body: &BeaconBlockBody<C>,
+    verify_signatures: bool,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    // Verify that outstanding deposits are processed up to the maximum number of deposits
+    let outstanding_deposits = state
+        .eth1_data
+        .deposit_count
+        .saturating_sub(state.eth1_deposit_index);
+    if body.deposits.len() as u64 != outstanding_deposits.min(C::MaxDeposits::to_u64()) {
+        return Err(Error::InvalidDepositCount);
+    }
+
+    for proposer_slashing in body.proposer_slashings.iter() {
+        process_proposer_slashing(state, proposer_slashing, verify_signatures, spec)?;
+    }
+    for attester_slashing in body.attester_slashings.iter() {
+        process_attester_slashing(state, attester_slashing, verify_signatures, spec)?;
+    }
+    for attestation in body.attestations.iter() {
+        process_attestation(state, attestation, verify_signatures, spec)?;
+    }
+    for deposit in body.deposits.iter() {
+        process_deposit(state, deposit, spec)?;
+    }
+    for voluntary_exit in body.voluntary_exits.iter() {
+        process_voluntary_exit(state, voluntary_exit, verify_signatures, spec)?;
+    }
+    Ok(())
+}
+
+pub fn process_proposer_slashing<C: Config>(
+    state: &mut BeaconState<C>,
+    proposer_slashing: &ProposerSlashing,
+    verify_signatures: bool,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    let proposer = state
+        .validators
+        .get(proposer_slashing.proposer_index as usize)
+        .ok_or(Error::InvalidProposerSlashing)?;
+    if proposer_slashing.header_1.slot != proposer_slashing.header_2.slot
+        || proposer_slashing.header_1 == proposer_slashing.header_2
+        || !is_slashable_validator(proposer, state.get_current_epoch())
+    {
+        return Err(Error::InvalidProposerSlashing);
+    }
+    if verify_signatures
+        && !proposer_slashing_signature_sets(state, proposer_slashing, spec)?
+            .iter()
+            .all(SignatureSet::is_valid)
+    {
+        return Err(Error::InvalidSignature);
+    }
+
+    state.slash_validator(proposer_slashing.proposer_index, None, spec)?;
+    Ok(())
+}
+
+pub fn process_attester_slashing<C: Config>(
+    state: &mut BeaconState<C>,
+    attester_slashing: &AttesterSlashing<C>,
+    verify_signatures: bool,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    let attestation_1 = &attester_slashing.attestation_1;
+    let attestation_2 = &attester_slashing.attestation_2;
+    if !is_slashable_attestation_data(&attestation_1.data, &attestation_2.data) {
+        return Err(Error::InvalidAttesterSlashing);
+    }
+    is_valid_indexed_attestation(state, attestation_1, verify_signatures, spec)?;
+    is_valid_indexed_attestation(state, attestation_2, verify_signatures, spec)?;
+
+    let indices_1 = indexed_attestation_indices(attestation_1);
+    let indices_2 = indexed_attestation_indices(attestation_2);
+    let current_epoch = state.get_current_epoch();
+    let mut slashed_any = false;
+    for index in indices_1.intersection(&indices_2) {
+        let validator = state
+            .validators
+            .get(*index as usize)
+            .ok_or(Error::IndexOutOfRange)?;
+        if is_slashable_validator(validator, current_epoch) {
+            state.slash_validator(*index, None, spec)?;
+            slashed_any = true;
+        }
+    }
+    if !slashed_any {
+        return Err(Error::InvalidAttesterSlashing);
+    }
+    Ok(())
+}
+
+pub fn process_attestation<C: Config>(
+    state: &mut BeaconState<C>,
+    attestation: &Attestation<C>,
+    verify_signature: bool,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    let data = &attestation.data;
+    let current_epoch = state.get_current_epoch();
+    let previous_epoch = state.get_previous_epoch();
+    if data.index >= state.get_committee_count_at_slot(data.slot, spec)
+        || (data.target.epoch != current_epoch && data.target.epoch != previous_epoch)
+        || data.target.epoch != compute_epoch_at_slot::<C>(data.slot)
+        || data.slot + spec.min_attestation_inclusion_delay > state.slot
+        || state.slot > data.slot + C::SlotsPerEpoch::to_u64()
+    {
+        return Err(Error::InvalidAttestation);
+    }
+
+    let committee = state.get_beacon_committee(data.slot, data.index, spec)?;
+    if attestation.aggregation_bits.len() != committee.len() {
+        return Err(Error::InvalidAttestation);
+    }
+
+    let attesting_indices =
+        state.get_attesting_indices(&attestation.data, &attestation.aggregation_bits, spec)?;
+    if attesting_indices.is_empty() {
+        return Err(Error::InvalidAttestation);
+    }
+    if verify_signature
+        && !attestation_signature_set(
+            state,
+            &attesting_indices,
+            &attestation.data,
+            &attestation.signature,
+            spec,
+        )?
+        .is_valid()
+    {
+        return Err(Error::InvalidSignature);
+    }
+
+    let pending_attestation = PendingAttestation {
+        aggregation_bits: attestation.aggregation_bits.clone(),
+        data: data.clone(),
+        inclusion_delay: state.slot - data.slot,
+        proposer_index: state.get_beacon_proposer_index(spec)?,
+    };
+    let attestations = if data.target.epoch == current_epoch {
+        if data.source != state.current_justified_checkpoint {
+            return Err(Error::InvalidAttestation);
+        }
+        &mut state.current_epoch_attestations
+    } else {
+        if data.source != state.previous_justified_checkpoint {
+            return Err(Error::InvalidAttestation);
+        }
+        &mut state.previous_epoch_attestations
+    };
+    attestations
+        .push(pending_attestation)
+        .map_err(|_| Error::ListFull)
+}
+
+pub fn process_deposit<C: Config>(
+    state: &mut BeaconState<C>,
+    deposit: &Deposit,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    // Verify the Merkle branch, the extra level mixes in the number of deposits
+    if !is_valid_merkle_branch(
+        H256::from_slice(&deposit.data.tree_hash_root()),
+        &deposit.proof,
+        consts::DEPOSIT_CONTRACT_TREE_DEPTH + 1,
+        state.eth1_deposit_index,
+        state.eth1_data.deposit_root,
+    ) {
+        return Err(Error::InvalidDepositProof);
+    }
+    state.eth1_deposit_index += 1;
+
+    let pubkey_bytes = deposit.data.pubkey.as_bytes();
+    let amount = deposit.data.amount;
+    if let Some(index) = state
+        .validators
+        .iter()
+        .position(|validator| validator.pubkey.as_bytes() == pubkey_bytes)
+    {
+        // Top-up of an existing validator
+        state.increase_balance(index as ValidatorIndex, amount)?;
+        return Ok(());
+    }
+
+    // Deposits with an invalid proof of possession are consumed without effect
+    let pubkey = match PublicKey::from_bytes(&pubkey_bytes) {
+        Ok(pubkey) => pubkey,
+        Err(_) => return Ok(()),
+    };
+    let domain = compute_domain(spec.domain_deposit, spec.genesis_fork_version);
+    if !deposit
+        .data
+        .signature
+        .verify(&deposit.data.signed_root(), domain, &pubkey)
+    {
+        return Ok(());
+    }
+
+    state
+        .validators
+        .push(Validator {
+            pubkey,
+            withdrawal_credentials: deposit.data.withdrawal_credentials,
+            activation_eligibility_epoch: consts::FAR_FUTURE_EPOCH,
+            activation_epoch: consts::FAR_FUTURE_EPOCH,
+            exit_epoch: consts::FAR_FUTURE_EPOCH,
+            withdrawable_epoch: consts::FAR_FUTURE_EPOCH,
+            effective_balance: (amount - amount % spec.effective_balance_increment)
+                .min(spec.max_effective_balance),
+            slashed: false,
+        })
+        .map_err(|_| Error::ListFull)?;
+    state.balances.push(amount).map_err(|_| Error::ListFull)
+}
+
+pub fn process_voluntary_exit<C: Config>(
+    state: &mut BeaconState<C>,
+    exit: &VoluntaryExit,
+    verify_signature: bool,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    let current_epoch = state.get_current_epoch();
+    let validator = state
+        .validators
+        .get(exit.validator_index as usize)
+        .ok_or(Error::InvalidVoluntaryExit)?;
+    if !is_active_validator(validator, current_epoch)
+        || validator.exit_epoch != consts::FAR_FUTURE_EPOCH
+        || current_epoch < exit.epoch
+        || current_epoch < validator.activation_epoch + spec.persistent_committee_period
+    {
+        return Err(Error::InvalidVoluntaryExit);
+    }
+    if verify_signature && !voluntary_exit_signature_set(state, exit, spec)?.is_valid() {
+        return Err(Error::InvalidSignature);
+    }
+
+    state.initiate_validator_exit(exit.validator_index, spec)?;
+    Ok(())
+}
+
+fn is_valid_indexed_attestation<C: Config>(
+    state: &BeaconState<C>,
+    indexed_attestation: &IndexedAttestation<C>,
+    verify_signature: bool,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    let bit_0_indices = &indexed_attestation.custody_bit_0_indices;
+    // Custody bits are not used in phase 0
+    if !indexed_attestation.custody_bit_1_indices.is_empty()
+        || bit_0_indices.is_empty()
+        || bit_0_indices.windows(2).any(|pair| pair[0] >= pair[1])
+    {
+        return Err(Error::InvalidAttesterSlashing);
+    }
+    if verify_signature
+        && !indexed_attestation_signature_set(state, indexed_attestation, spec)?.is_valid()
+    {
+        return Err(Error::InvalidSignature);
+    }
+    Ok(())
+}
+
+fn indexed_attestation_indices<C: Config>(
+    indexed_attestation: &IndexedAttestation<C>,
+) -> BTreeSet<ValidatorIndex> {
+    indexed_attestation
+        .custody_bit_0_indices
+        .iter()
+        .chain(indexed_attestation.custody_bit_1_indices.iter())
+        .copied()
+        .collect()
+}
+
+fn validator_pubkey<C: Config>(
+    state: &BeaconState<C>,
+    index: ValidatorIndex,
+) -> Result<&PublicKey, Error> {
+    state
+        .validators
+        .get(index as usize)
+        .map(|validator| &validator.pubkey)
+        .ok_or(Error::IndexOutOfRange)
+}
+
+fn block_proposal_signature_set<'a, C: Config>(
+    state: &'a BeaconState<C>,
+    block: &'a BeaconBlock<C>,
+    spec: &ChainSpec,
+) -> Result<SignatureSet<'a>, Error> {
+    let proposer_index = state.get_beacon_proposer_index(spec)?;
+    Ok(SignatureSet::single(
+        &block.signature,
+        validator_pubkey(state, proposer_index)?,
+        block.signed_root(),
+        state.get_domain(
+            spec.domain_beacon_proposer,
+            Some(compute_epoch_at_slot::<C>(block.slot)),
+        ),
+    ))
+}
+
+fn randao_signature_set<'a, C: Config>(
+    state: &'a BeaconState<C>,
+    body: &'a BeaconBlockBody<C>,
+    spec: &ChainSpec,
+) -> Result<SignatureSet<'a>, Error> {
+    let epoch = state.get_current_epoch();
+    let proposer_index = state.get_beacon_proposer_index(spec)?;
+    Ok(SignatureSet::single(
+        &body.randao_reveal,
+        validator_pubkey(state, proposer_index)?,
+        epoch.tree_hash_root(),
+        state.get_domain(spec.domain_randao, Some(epoch)),
+    ))
+}
+
+fn proposer_slashing_signature_sets<'a, C: Config>(
+    state: &'a BeaconState<C>,
+    proposer_slashing: &'a ProposerSlashing,
+    spec: &ChainSpec,
+) -> Result<Vec<SignatureSet<'a>>, Error> {
+    let pubkey = validator_pubkey(state, proposer_slashing.proposer_index)?;
+    Ok([&proposer_slashing.header_1, &proposer_slashing.header_2]
+        .iter()
+        .map(|header| {
+            SignatureSet::single(
+                &header.signature,
+                pubkey,
+                header.signed_root(),
+                state.get_domain(
+                    spec.domain_beacon_proposer,
+                    Some(compute_epoch_at_slot::<C>(header.slot)),
+                ),
+            )
+        })
+        .collect())
+}
+
+fn indexed_attestation_signature_set<'a, C: Config>(
+    state: &'a BeaconState<C>,
+    indexed_attestation: &'a IndexedAttestation<C>,
+    spec: &ChainSpec,
+) -> Result<SignatureSet<'a>, Error> {
+    let indices: Vec<ValidatorIndex> = indexed_attestation
+        .custody_bit_0_indices
+        .iter()
+        .copied()
+        .collect();
+    attestation_signature_set(
+        state,
+        &indices,
+        &indexed_attestation.data,
+        &indexed_attestation.signature,
+        spec,
+    )
+}
+
+fn attestation_signature_set<'a, C: Config>(
+    state: &'a BeaconState<C>,
+    attesting_indices: &[ValidatorIndex],
+    data: &AttestationData,
+    signature: &'a Signature,
+    spec: &ChainSpec,
+) -> Result<SignatureSet<'a>, Error> {
+    let pubkeys = attesting_indices
+        .iter()
+        .map(|index| validator_pubkey(state, *index))
+        .collect::<Result<Vec<&PublicKey>, Error>>()?;
+    Ok(SignatureSet::new(
+        signature,
+        vec![SignedMessage::new(pubkeys, data.tree_hash_root())],
+        state.get_domain(spec.domain_beacon_attester, Some(data.target.epoch)),
+    ))
+}
+
+fn voluntary_exit_signature_set<'a, C: Config>(
+    state: &'a BeaconState<C>,
+    exit: &'a VoluntaryExit,
+    spec: &ChainSpec,
+) -> Result<SignatureSet<'a>, Error> {
+    Ok(SignatureSet::single(
+        &exit.signature,
+        validator_pubkey(state, exit.validator_index)?,
+        exit.signed_root(),
+        state.get_domain(spec.domain_voluntary_exit, Some(exit.epoch)),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deposit_tree::DepositTree;
+    use crate::genesis::{interop_deposits, interop_genesis_state, interop_keypair};
+    use crate::state_transition::process_slots;
+    use bls::{Keypair, PublicKeyBytes};
+    use ssz_types::{BitList, FixedVector, VariableList};
+
+    const VALIDATOR_COUNT: u64 = 16;
+
+    fn spec() -> ChainSpec {
+        ChainSpec::from_config::<MinimalConfig>()
+    }
+
+    /// Interop genesis state advanced to slot 1.
+    fn state() -> BeaconState<MinimalConfig> {
+        let mut state = interop_genesis_state(VALIDATOR_COUNT, 0).unwrap();
+        process_slots(&mut state, 1, &spec()).unwrap();
+        state
+    }
+
+    fn proposer(state: &BeaconState<MinimalConfig>) -> Keypair {
+        interop_keypair(state.get_beacon_proposer_index(&spec()).unwrap())
+    }
+
+    fn sign_block(state: &BeaconState<MinimalConfig>, block: &mut BeaconBlock<MinimalConfig>) {
+        let domain = state.get_domain(spec().domain_beacon_proposer, None);
+        block.signature = Signature::new(&block.signed_root(), domain, &proposer(state).sk);
+    }
+
+    /// Block at the slot of `state` with a valid RANDAO reveal and proposer signature.
+    fn block(state: &BeaconState<MinimalConfig>) -> BeaconBlock<MinimalConfig> {
+        let epoch = state.get_current_epoch();
+        let randao_domain = state.get_domain(spec().domain_randao, Some(epoch));
+        let mut block = BeaconBlock {
+            slot: state.slot,
+            parent_root: H256::from_slice(&state.latest_block_header.signed_root()),
+            state_root: H256::zero(),
+            body: BeaconBlockBody {
+                randao_reveal: Signature::new(
+                    &epoch.tree_hash_root(),
+                    randao_domain,
+                    &proposer(state).sk,
+                ),
+                eth1_data: state.eth1_data.clone(),
+                graffiti: [0; 32],
+                proposer_slashings: VariableList::default(),
+                attester_slashings: VariableList::default(),
+                attestations: VariableList::default(),
+                deposits: VariableList::default(),
+                voluntary_exits: VariableList::default(),
+                transfers: VariableList::default(),
+            },
+            signature: Signature::empty_signature(),
+        };
+        sign_block(state, &mut block);
+        block
+    }
+
+    /// Attestation of the first member of committee 0 at `slot`, with the source `state` expects.
+    fn attestation(state: &BeaconState<MinimalConfig>, slot: Slot) -> Attestation<MinimalConfig> {
+        let committee = state.get_beacon_committee(slot, 0, &spec()).unwrap();
+        let mut aggregation_bits = BitList::with_capacity(committee.len()).unwrap();
+        aggregation_bits.set(0, true).unwrap();
+        let target_epoch = compute_epoch_at_slot::<MinimalConfig>(slot);
+        let source = if target_epoch == state.get_current_epoch() {
+            state.current_justified_checkpoint.clone()
+        } else {
+            state.previous_justified_checkpoint.clone()
+        };
+        let data = AttestationData {
+            slot,
+            index: 0,
+            beacon_block_root: H256::zero(),
+            source,
+            target: Checkpoint {
+                epoch: target_epoch,
+                root: H256::zero(),
+            },
+        };
+        let domain = state.get_domain(spec().domain_beacon_attester, Some(target_epoch));
+        let signature = Signature::new(
+            &data.tree_hash_root(),
+            domain,
+            &interop_keypair(committee[0]).sk,
+        );
+        Attestation {
+            aggregation_bits,
+            data,
+            signature,
+        }
+    }
+
+    /// Vote of `indices` for `beacon_block_root` in epoch 0, signed if there is a single signer.
+    fn indexed_attestation(
+        state: &BeaconState<MinimalConfig>,
+        indices: Vec<ValidatorIndex>,
+        beacon_block_root: u64,
+    ) -> IndexedAttestation<MinimalConfig> {
+        let data = AttestationData {
+            slot: 0,
+            index: 0,
+            beacon_block_root: H256::from_low_u64_le(beacon_block_root),
+            source: Checkpoint::default(),
+            target: Checkpoint::default(),
+        };
+        let signature = match indices.as_slice() {
+            [index] => Signature::new(
+                &data.tree_hash_root(),
+                state.get_domain(spec().domain_beacon_attester, Some(0)),
+                &interop_keypair(*index).sk,
+            ),
+            _ => Signature::empty_signature(),
+        };
+        IndexedAttestation {
+            custody_bit_0_indices: VariableList::from(indices),
+            custody_bit_1_indices: VariableList::default(),
+            data,
+            signature,
+        }
+    }
+
+    fn signed_header(
+        state: &BeaconState<MinimalConfig>,
+        index: ValidatorIndex,
+        body_root: u64,
+    ) -> BeaconBlockHeader {
+        let mut header = BeaconBlockHeader {
+            slot: 1,
+            body_root: H256::from_low_u64_le(body_root),
+            ..BeaconBlockHeader::default()
+        };
+        header.signature = Signature::new(
+            &header.signed_root(),
+            state.get_domain(spec().domain_beacon_proposer, Some(0)),
+            &interop_keypair(index).sk,
+        );
+        header
+    }
+
+    fn deposit_data(keypair: &Keypair, signer: &Keypair, amount: Gwei) -> DepositData {
+        let mut data = DepositData {
+            pubkey: PublicKeyBytes::from_bytes(&keypair.pk.as_bytes()).unwrap(),
+            withdrawal_credentials: H256::zero(),
+            amount,
+            signature: Signature::empty_signature(),
+        };
+        let domain = compute_domain(spec().domain_deposit, spec().genesis_fork_version);
+        data.signature = Signature::new(&data.signed_root(), domain, &signer.sk);
+        data
+    }
+
+    /// Deposit of `data` following the genesis deposits, with `state` voting for the new root.
+    fn deposit(state: &mut BeaconState<MinimalConfig>, data: DepositData) -> Deposit {
+        let keypairs: Vec<Keypair> = (0..VALIDATOR_COUNT).map(interop_keypair).collect();
+        let mut deposit_tree = DepositTree::new();
+        for genesis_deposit in interop_deposits(&keypairs, &spec()) {
+            deposit_tree.push(H256::from_slice(&genesis_deposit.data.tree_hash_root()));
+        }
+        deposit_tree.push(H256::from_slice(&data.tree_hash_root()));
+        state.eth1_data.deposit_root = deposit_tree.root();
+        state.eth1_data.deposit_count = deposit_tree.len();
+        Deposit {
+            proof: FixedVector::from(deposit_tree.proof(VALIDATOR_COUNT)),
+            data,
+        }
+    }
+
+    #[test]
+    fn should_process_signed_block_with_every_strategy() {
+        let state = state();
+        let block = block(&state);
+
+        for strategy in [
+            BlockSignatureStrategy::NoVerification,
+            BlockSignatureStrategy::VerifyIndividual,
+            BlockSignatureStrategy::VerifyBulk,
+        ]
+        .iter()
+        {
+            let mut post_state = state.clone();
+            assert_eq!(
+                process_block(&mut post_state, &block, *strategy, &spec()),
+                Ok(())
+            );
+            assert_eq!(post_state.latest_block_header.slot, 1);
+            assert_eq!(post_state.eth1_data_votes.len(), 1);
+            assert_ne!(post_state.randao_mixes[0], state.randao_mixes[0]);
+        }
+    }
+
+    #[test]
+    fn should_only_refuse_forged_signatures_when_verifying() {
+        let state = state();
+        let mut forged_block = block(&state);
+        forged_block.body.graffiti = [1; 32];
+
+        for strategy in [
+            BlockSignatureStrategy::VerifyIndividual,
+            BlockSignatureStrategy::VerifyBulk,
+        ]
+        .iter()
+        {
+            assert_eq!(
+                process_block(&mut state.clone(), &forged_block, *strategy, &spec()),
+                Err(Error::InvalidSignature)
+            );
+        }
+        assert_eq!(
+            process_block(
+                &mut state.clone(),
+                &forged_block,
+                BlockSignatureStrategy::NoVerification,
+                &spec()
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn should_verify_bulk_signatures_before_changing_state() {
+        let mut state = state();
+        process_slots(&mut state, 2, &spec()).unwrap();
+        let mut block = block(&state);
+        let mut attestation = attestation(&state, 1);
+        attestation.data.beacon_block_root = H256::from_low_u64_le(1);
+        block.body.attestations.push(attestation).unwrap();
+        sign_block(&state, &mut block);
+
+        let mut bulk_state = state.clone();
+        assert_eq!(
+            process_block(
+                &mut bulk_state,
+                &block,
+                BlockSignatureStrategy::VerifyBulk,
+                &spec()
+            ),
+            Err(Error::InvalidSignature)
+        );
+        assert_eq!(bulk_state, state);
+
+        // Operations before the invalid attestation are applied already.
+        let mut individual_state = state.clone();
+        assert_eq!(
+            process_block(
+                &mut individual_state,
+                &block,
+                BlockSignatureStrategy::VerifyIndividual,
+                &spec()
+            ),
+            Err(Error::InvalidSignature)
+        );
+        assert_ne!(individual_state, state);
+    }
+
+    #[test]
+    fn should_refuse_block_not_building_on_latest_header() {
+        let state = state();
+        let mut block = block(&state);
+        block.parent_root = H256::from_low_u64_le(1);
+        sign_block(&state, &mut block);
+        assert_eq!(
+            process_block(
+                &mut state.clone(),
+                &block,
+                BlockSignatureStrategy::VerifyIndividual,
+                &spec()
+            ),
+            Err(Error::InvalidBlockHeader)
+        );
+    }
+
+    #[test]
+    fn should_require_outstanding_deposits() {
+        let mut state = state();
+        let data = deposit_data(&interop_keypair(16), &interop_keypair(16), 32_000_000_000);
+        deposit(&mut state, data);
+        let block = block(&state);
+        assert_eq!(
+            process_block(
+                &mut state,
+                &block,
+                BlockSignatureStrategy::NoVerification,
+                &spec()
+            ),
+            Err(Error::InvalidDepositCount)
+        );
+    }
+
+    #[test]
+    fn should_add_validator_for_deposit_with_valid_proof() {
+        let mut state = state();
+        let keypair = interop_keypair(16);
+        let deposit = deposit(&mut state, deposit_data(&keypair, &keypair, 32_000_000_000));
+
+        assert_eq!(process_deposit(&mut state, &deposit, &spec()), Ok(()));
+
+        assert_eq!(state.eth1_deposit_index, 17);
+        assert_eq!(state.validators.len(), 17);
+        assert_eq!(state.balances[16], 32_000_000_000);
+        assert_eq!(state.validators[16].pubkey, keypair.pk);
+        assert_eq!(state.validators[16].effective_balance, 32_000_000_000);
+        assert_eq!(
+            state.validators[16].activation_eligibility_epoch,
+            consts::FAR_FUTURE_EPOCH
+        );
+    }
+
+    #[test]
+    fn should_refuse_deposit_with_invalid_proof() {
+        let mut state = state();
+        let keypair = interop_keypair(16);
+        let mut deposit = deposit(&mut state, deposit_data(&keypair, &keypair, 32_000_000_000));
+        deposit.proof[0] = H256::from_low_u64_le(1);
+
+        assert_eq!(
+            process_deposit(&mut state, &deposit, &spec()),
+            Err(Error::InvalidDepositProof)
+        );
+        assert_eq!(state.eth1_deposit_index, 16);
+        assert_eq!(state.validators.len(), 16);
+    }
+
+    #[test]
+    fn should_top_up_existing_validator() {
+        let mut state = state();
+        let keypair = interop_keypair(3);
+        // Top-ups need no proof of possession.
+        let deposit = deposit(
+            &mut state,
+            deposit_data(&keypair, &interop_keypair(4), 1_000_000_000),
+        );
+
+        assert_eq!(process_deposit(&mut state, &deposit, &spec()), Ok(()));
+
+        assert_eq!(state.validators.len(), 16);
+        assert_eq!(state.balances[3], 33_000_000_000);
+        assert_eq!(state.eth1_deposit_index, 17);
+    }
+
+    #[test]
+    fn should_consume_deposit_without_proof_of_possession() {
+        let mut state = state();
+        let deposit = deposit(
+            &mut state,
+            deposit_data(&interop_keypair(16), &interop_keypair(17), 32_000_000_000),
+        );
+
+        assert_eq!(process_deposit(&mut state, &deposit, &spec()), Ok(()));
+
+        assert_eq!(state.validators.len(), 16);
+        assert_eq!(state.eth1_deposit_index, 17);
+    }
+
+    #[test]
+    fn should_exit_validator_after_persistent_committee_period() {
+        let mut state = state();
+        let epoch = spec().persistent_committee_period;
+        state.slot = compute_start_slot_at_epoch::<MinimalConfig>(epoch);
+        let mut exit = VoluntaryExit {
+            epoch,
+            validator_index: 2,
+            signature: Signature::empty_signature(),
+        };
+        let domain = state.get_domain(spec().domain_voluntary_exit, Some(epoch));
+        exit.signature = Signature::new(&exit.signed_root(), domain, &interop_keypair(2).sk);
+
+        assert_eq!(
+            process_voluntary_exit(&mut state, &exit, true, &spec()),
+            Ok(())
+        );
+
+        let exit_epoch = compute_activation_exit_epoch(epoch, &spec());
+        assert_eq!(state.validators[2].exit_epoch, exit_epoch);
+        assert_eq!(
+            process_voluntary_exit(&mut state, &exit, true, &spec()),
+            Err(Error::InvalidVoluntaryExit)
+        );
+    }
+
+    #[test]
+    fn should_refuse_early_or_forged_exits() {
+        let mut state = state();
+        let exit = VoluntaryExit {
+            epoch: 0,
+            validator_index: 2,
+            signature: Signature::empty_signature(),
+        };
+        assert_eq!(
+            process_voluntary_exit(&mut state, &exit, false, &spec()),
+            Err(Error::InvalidVoluntaryExit)
+        );
+
+        state.slot =
+            compute_start_slot_at_epoch::<MinimalConfig>(spec().persistent_committee_period);
+        assert_eq!(
+            process_voluntary_exit(&mut state.clone(), &exit, true, &spec()),
+            Err(Error::InvalidSignature)
+        );
+        assert_eq!(
+            process_voluntary_exit(&mut state, &exit, false, &spec()),
+            Ok(())
+        );
+        let unknown_validator = VoluntaryExit {
+            validator_index: VALIDATOR_COUNT,
+            ..exit
+        };
+        assert_eq!(
+            process_voluntary_exit(&mut state, &unknown_validator, false, &spec()),
+            Err(Error::InvalidVoluntaryExit)
+        );
+    }
+
+    #[test]
+    fn should_verify_signatures_with_domains_of_spec() {
+        let other_spec = ChainSpec {
+            domain_voluntary_exit: 0x0100,
+            ..spec()
+        };
+        let mut state = state();
+        let epoch = other_spec.persistent_committee_period;
+        state.slot = compute_start_slot_at_epoch::<MinimalConfig>(epoch);
+        let mut exit = VoluntaryExit {
+            epoch,
+            validator_index: 2,
+            signature: Signature::empty_signature(),
+        };
+        let domain = state.get_domain(other_spec.domain_voluntary_exit, Some(epoch));
+        exit.signature = Signature::new(&exit.signed_root(), domain, &interop_keypair(2).sk);
+
+        assert_eq!(
+            process_voluntary_exit(&mut state.clone(), &exit, true, &spec()),
+            Err(Error::InvalidSignature)
+        );
+        assert_eq!(
+            process_voluntary_exit(&mut state, &exit, true, &other_spec),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn should_slash_proposer_of_two_blocks_at_same_slot() {
+        let mut state = state();
+        let proposer_slashing = ProposerSlashing {
+            proposer_index: 5,
+            header_1: signed_header(&state, 5, 1),
+            header_2: signed_header(&state, 5, 2),
+        };
+
+        assert_eq!(
+            process_proposer_slashing(&mut state, &proposer_slashing, true, &spec()),
+            Ok(())
+        );
+
+        assert!(state.validators[5].slashed);
+        assert_eq!(
+            state.validators[5].exit_epoch,
+            compute_activation_exit_epoch(0, &spec())
+        );
+        assert_eq!(state.slashings[0], 32_000_000_000);
+        assert_eq!(
+            process_proposer_slashing(&mut state, &proposer_slashing, true, &spec()),
+            Err(Error::InvalidProposerSlashing)
+        );
+    }
+
+    #[test]
+    fn should_refuse_invalid_proposer_slashings() {
+        let mut state = state();
+        let header = signed_header(&state, 5, 1);
+        let same_header = ProposerSlashing {
+            proposer_index: 5,
+            header_1: header.clone(),
+            header_2: header.clone(),
+        };
+        assert_eq!(
+            process_proposer_slashing(&mut state, &same_header, false, &spec()),
+            Err(Error::InvalidProposerSlashing)
+        );
+        let forged = ProposerSlashing {
+            proposer_index: 6,
+            header_1: header,
+            header_2: signed_header(&state, 5, 2),
+        };
+        assert_eq!(
+            process_proposer_slashing(&mut state, &forged, true, &spec()),
+            Err(Error::InvalidSignature)
+        );
+        let unknown_proposer = ProposerSlashing {
+            proposer_index: VALIDATOR_COUNT,
+            ..forged
+        };
+        assert_eq!(
+            process_proposer_slashing(&mut state, &unknown_proposer, false, &spec()),
+            Err(Error::InvalidProposerSlashing)
+        );
+    }
+
+    #[test]
+    fn should_slash_validators_of_both_attestations() {
+        let mut state = state();
+        let attester_slashing = AttesterSlashing {
+            attestation_1: indexed_attestation(&state, vec![1, 2, 3], 1),
+            attestation_2: indexed_attestation(&state, vec![2, 3, 4], 2),
+        };
+
+        assert_eq!(
+            process_attester_slashing(&mut state, &attester_slashing, false, &spec()),
+            Ok(())
+        );
+
+        let slashed: Vec<bool> = (1..=4)
+            .map(|index| state.validators[index].slashed)
+            .collect();
+        assert_eq!(slashed, vec![false, true, true, false]);
+        assert_eq!(
+            process_attester_slashing(&mut state, &attester_slashing, false, &spec()),
+            Err(Error::InvalidAttesterSlashing)
+        );
+    }
+
+    #[test]
+    fn should_verify_attester_slashing_signatures() {
+        let mut state = state();
+        let mut attester_slashing = AttesterSlashing {
+            attestation_1: indexed_attestation(&state, vec![2], 1),
+            attestation_2: indexed_attestation(&state, vec![2], 2),
+        };
+        assert_eq!(
+            process_attester_slashing(&mut state.clone(), &attester_slashing, true, &spec()),
+            Ok(())
+        );
+
+        attester_slashing.attestation_2.signature =
+            attester_slashing.attestation_1.signature.clone();
+        assert_eq!(
+            process_attester_slashing(&mut state.clone(), &attester_slashing, true, &spec()),
+            Err(Error::InvalidSignature)
+        );
+        let same_vote = AttesterSlashing {
+            attestation_1: indexed_attestation(&state, vec![2], 1),
+            attestation_2: indexed_attestation(&state, vec![2], 1),
+        };
+        assert_eq!(
+            process_attester_slashing(&mut state, &same_vote, true, &spec()),
+            Err(Error::InvalidAttesterSlashing)
+        );
+    }
+
+    #[test]
+    fn should_refuse_attester_slashing_of_unknown_validator() {
+        let mut state = state();
+        let attester_slashing = AttesterSlashing {
+            attestation_1: indexed_attestation(&state, vec![1, VALIDATOR_COUNT], 1),
+            attestation_2: indexed_attestation(&state, vec![1, VALIDATOR_COUNT], 2),
+        };
+
+        assert_eq!(
+            process_attester_slashing(&mut state, &attester_slashing, false, &spec()),
+            Err(Error::IndexOutOfRange)
+        );
+    }
+
+    #[test]
+    fn should_include_attestations_within_inclusion_window() {
+        let mut state = state();
+        let current_slot_attestation = attestation(&state, 1);
+        assert_eq!(
+            process_attestation(&mut state, &current_slot_attestation, true, &spec()),
+            Err(Error::InvalidAttestation)
+        );
+
+        process_slots(&mut state, 2, &spec()).unwrap();
+        assert_eq!(
+            process_attestation(&mut state, &current_slot_attestation, true, &spec()),
+            Ok(())
+        );
+        assert_eq!(state.current_epoch_attestations.len(), 1);
+        assert_eq!(state.current_epoch_attestations[0].inclusion_delay, 1);
+
+        let mut late_state = state.clone();
+        process_slots(&mut late_state, 1 + 8, &spec()).unwrap();
+        assert_eq!(
+            process_attestation(&mut late_state, &current_slot_attestation, true, &spec()),
+            Ok(())
+        );
+        assert_eq!(late_state.previous_epoch_attestations.len(), 2);
+        process_slots(&mut late_state, 1 + 8 + 1, &spec()).unwrap();
+        assert_eq!(
+            process_attestation(&mut late_state, &current_slot_attestation, true, &spec()),
+            Err(Error::InvalidAttestation)
+        );
+    }
+
+    #[test]
+    fn should_refuse_attestation_with_other_source_or_signer() {
+        let mut state = state();
+        process_slots(&mut state, 2, &spec()).unwrap();
+        let mut attestation = attestation(&state, 1);
+        attestation.data.source.epoch = 1;
+        assert_eq!(
+            process_attestation(&mut state, &attestation, false, &spec()),
+            Err(Error::InvalidAttestation)
+        );
+
+        let mut attestation = self::attestation(&state, 1);
+        attestation.aggregation_bits.set(0, false).unwrap();
+        attestation.aggregation_bits.set(1, true).unwrap();
+        assert_eq!(
+            process_attestation(&mut state, &attestation, true, &spec()),
+            Err(Error::InvalidSignature)
+        );
+    }
+}
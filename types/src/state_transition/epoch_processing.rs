@@ -0,0 +1,795 @@
+use std::collections::BTreeSet;
+use tree_hash::TreeHash;
+use typenum::Unsigned;
+
+use super::Error;
+use crate::beacon_state::{self, BeaconState};
+use crate::{
+    chain_spec::ChainSpec, config::*, consts, helper_functions::*, primitives::*, types::*,
+};
+
+const BASE_REWARDS_PER_EPOCH: u64 = 4;
+// Total slashed balance counts triple towards the penalty of every slashed validator.
+const SLASHINGS_MULTIPLIER: u64 = 3;
+
+pub fn process_epoch<C: Config>(state: &mut BeaconState<C>, spec: &ChainSpec) -> Result<(), Error> {
+    process_justification_and_finalization(state, spec)?;
+    process_rewards_and_penalties(state, spec)?;
+    process_registry_updates(state, spec)?;
+    process_slashings(state, spec)?;
+    process_final_updates(state, spec)
+}
+
+pub fn process_justification_and_finalization<C: Config>(
+    state: &mut BeaconState<C>,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    let current_epoch = state.get_current_epoch();
+    if current_epoch <= C::genesis_epoch() + 1 {
+        return Ok(());
+    }
+    let previous_epoch = state.get_previous_epoch();
+    let old_previous_justified_checkpoint = state.previous_justified_checkpoint.clone();
+    let old_current_justified_checkpoint = state.current_justified_checkpoint.clone();
+
+    // Process justifications
+    state.previous_justified_checkpoint = state.current_justified_checkpoint.clone();
+    let mut bits = [false; consts::JUSTIFICATION_BITS_LENGTH];
+    for (i, bit) in bits.iter_mut().enumerate().skip(1) {
+        *bit = state.justification_bits.get(i - 1).unwrap_or(false);
+    }
+
+    let total_active_balance = state.get_total_active_balance(spec);
+    for (bit, epoch) in [(1, previous_epoch), (0, current_epoch)].iter() {
+        let matching_target_attestations = get_matching_target_attestations(state, *epoch)?;
+        let attesting_balance = get_attesting_balance(state, &matching_target_attestations, spec)?;
+        if attesting_balance * 3 >= total_active_balance * 2 {
+            state.current_justified_checkpoint = Checkpoint {
+                epoch: *epoch,
+                root: state.get_block_root(*epoch)?,
+            };
+            bits[*bit] = true;
+        }
+    }
+    for (i, bit) in bits.iter().enumerate() {
+        state
+            .justification_bits
+            .set(i, *bit)
+            .map_err(|_| Error::IndexOutOfRange)?;
+    }
+
+    // Process finalizations
+    // The 2nd/3rd/4th most recent epochs are justified, the 2nd using the 4th as source
+    if bits[1..4].iter().all(|bit| *bit)
+        && old_previous_justified_checkpoint.epoch + 3 == current_epoch
+    {
+        state.finalized_checkpoint = old_previous_justified_checkpoint.clone();
+    }
+    // The 2nd/3rd most recent epochs are justified, the 2nd using the 3rd as source
+    if bits[1..3].iter().all(|bit| *bit)
+        && old_previous_justified_checkpoint.epoch + 2 == current_epoch
+    {
+        state.finalized_checkpoint = old_previous_justified_checkpoint;
+    }
+    // The 1st/2nd/3rd most recent epochs are justified, the 1st using the 3rd as source
+    if bits[0..3].iter().all(|bit| *bit)
+        && old_current_justified_checkpoint.epoch + 2 == current_epoch
+    {
+        state.finalized_checkpoint = old_current_justified_checkpoint.clone();
+    }
+    // The 1st/2nd most recent epochs are justified, the 1st using the 2nd as source
+    if bits[0..2].iter().all(|bit| *bit)
+        && old_current_justified_checkpoint.epoch + 1 == current_epoch
+    {
+        state.finalized_checkpoint = old_current_justified_checkpoint;
+    }
+    Ok(())
+}
+
+pub fn process_rewards_and_penalties<C: Config>(
+    state: &mut BeaconState<C>,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    if state.get_current_epoch() == C::genesis_epoch() {
+        return Ok(());
+    }
+    let (rewards, penalties) = get_attestation_deltas(state, spec)?;
+    for (index, (reward, penalty)) in rewards.into_iter().zip(penalties).enumerate() {
+        state.increase_balance(index as ValidatorIndex, reward)?;
+        state.decrease_balance(index as ValidatorIndex, penalty)?;
+    }
+    Ok(())
+}
+
+/// Rewards and penalties of every validator for its attestations in the previous epoch.
+pub fn get_attestation_deltas<C: Config>(
+    state: &BeaconState<C>,
+    spec: &ChainSpec,
+) -> Result<(Vec<Gwei>, Vec<Gwei>), Error> {
+    let previous_epoch = state.get_previous_epoch();
+    let total_balance = state.get_total_active_balance(spec);
+    let increment = spec.effective_balance_increment;
+    let validator_count = state.validators.len();
+    let mut rewards = vec![0; validator_count];
+    let mut penalties = vec![0; validator_count];
+
+    let eligible_validator_indices: Vec<ValidatorIndex> = state
+        .validators
+        .iter()
+        .enumerate()
+        .filter(|(_, validator)| {
+            is_active_validator(validator, previous_epoch)
+                || (validator.slashed && previous_epoch + 1 < validator.withdrawable_epoch)
+        })
+        .map(|(index, _)| index as ValidatorIndex)
+        .collect();
+    let base_rewards: Vec<Gwei> = (0..validator_count as ValidatorIndex)
+        .map(|index| get_base_reward(state, index, total_balance, spec))
+        .collect();
+
+    let matching_source_attestations = get_matching_source_attestations(state, previous_epoch)?;
+    let matching_target_attestations = get_matching_target_attestations(state, previous_epoch)?;
+    let matching_head_attestations = get_matching_head_attestations(state, previous_epoch)?;
+
+    // Micro-incentives for matching FFG source, FFG target, and head
+    for attestations in [
+        &matching_source_attestations,
+        &matching_target_attestations,
+        &matching_head_attestations,
+    ]
+    .iter()
+    {
+        let unslashed_attesting_indices =
+            get_unslashed_attesting_indices(state, attestations, spec)?;
+        let attesting_balance = state.get_total_balance(
+            &unslashed_attesting_indices
+                .iter()
+                .copied()
+                .collect::<Vec<_>>(),
+            spec,
+        );
+        for index in eligible_validator_indices.iter() {
+            let base_reward = base_rewards[*index as usize];
+            if unslashed_attesting_indices.contains(index) {
+                // Factored out from balance totals to avoid uint64 overflow
+                let reward_numerator = base_reward * (attesting_balance / increment);
+                rewards[*index as usize] += reward_numerator / (total_balance / increment);
+            } else {
+                penalties[*index as usize] += base_reward;
+            }
+        }
+    }
+
+    // Proposer and inclusion delay micro-rewards
+    let mut earliest_inclusions: Vec<Option<&PendingAttestation<C>>> = vec![None; validator_count];
+    for attestation in matching_source_attestations.iter() {
+        for index in
+            state.get_attesting_indices(&attestation.data, &attestation.aggregation_bits, spec)?
+        {
+            let earliest = &mut earliest_inclusions[index as usize];
+            match earliest {
+                Some(included) if included.inclusion_delay <= attestation.inclusion_delay => {}
+                _ => *earliest = Some(attestation),
+            }
+        }
+    }
+    for index in get_unslashed_attesting_indices(state, &matching_source_attestations, spec)? {
+        let attestation = match earliest_inclusions[index as usize] {
+            Some(attestation) => attestation,
+            None => continue,
+        };
+        let base_reward = base_rewards[index as usize];
+        let proposer_reward = base_reward / spec.proposer_reward_quotient;
+        *rewards
+            .get_mut(attestation.proposer_index as usize)
+            .ok_or(Error::IndexOutOfRange)? += proposer_reward;
+        let max_attester_reward = base_reward - proposer_reward;
+        rewards[index as usize] += max_attester_reward / attestation.inclusion_delay.max(1);
+    }
+
+    // Inactivity penalty
+    let finality_delay = previous_epoch.saturating_sub(state.finalized_checkpoint.epoch);
+    if finality_delay > spec.min_epochs_to_inactivity_penalty {
+        let matching_target_attesting_indices =
+            get_unslashed_attesting_indices(state, &matching_target_attestations, spec)?;
+        for index in eligible_validator_indices.iter() {
+            let index = *index as usize;
+            penalties[index] += BASE_REWARDS_PER_EPOCH * base_rewards[index];
+            if !matching_target_attesting_indices.contains(&(index as ValidatorIndex)) {
+                penalties[index] += state.validators[index].effective_balance * finality_delay
+                    / spec.inactivity_penalty_quotient;
+            }
+        }
+    }
+
+    Ok((rewards, penalties))
+}
+
+pub fn process_registry_updates<C: Config>(
+    state: &mut BeaconState<C>,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    let current_epoch = state.get_current_epoch();
+
+    // Process activation eligibility and ejections
+    for index in 0..state.validators.len() {
+        let validator = &mut state.validators[index];
+        if validator.activation_eligibility_epoch == consts::FAR_FUTURE_EPOCH
+            && validator.effective_balance == spec.max_effective_balance
+        {
+            validator.activation_eligibility_epoch = current_epoch + 1;
+        }
+        if is_active_validator(validator, current_epoch)
+            && validator.effective_balance <= spec.ejection_balance
+        {
+            state.initiate_validator_exit(index as ValidatorIndex, spec)?;
+        }
+    }
+
+    // Queue validators eligible for activation and not yet dequeued for activation
+    let finalized_epoch = state.finalized_checkpoint.epoch;
+    let mut activation_queue: Vec<(Epoch, usize)> = state
+        .validators
+        .iter()
+        .enumerate()
+        .filter(|(_, validator)| {
+            validator.activation_eligibility_epoch <= finalized_epoch
+                && validator.activation_epoch == consts::FAR_FUTURE_EPOCH
+        })
+        .map(|(index, validator)| (validator.activation_eligibility_epoch, index))
+        .collect();
+    activation_queue.sort();
+
+    // Dequeue validators for activation up to churn limit
+    let churn_limit = state.get_validator_churn_limit(spec) as usize;
+    let activation_epoch = compute_activation_exit_epoch(current_epoch, spec);
+    for (_, index) in activation_queue.into_iter().take(churn_limit) {
+        state.validators[index].activation_epoch = activation_epoch;
+    }
+    Ok(())
+}
+
+pub fn process_slashings<C: Config>(
+    state: &mut BeaconState<C>,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    let epoch = state.get_current_epoch();
+    let total_balance = state.get_total_active_balance(spec);
+    let total_slashings: Gwei = state.slashings.iter().sum();
+    let adjusted_total_slashing_balance =
+        (total_slashings * SLASHINGS_MULTIPLIER).min(total_balance);
+    let increment = spec.effective_balance_increment;
+    let withdrawable_epoch = epoch + C::EpochsPerSlashingsVector::to_u64() / 2;
+
+    for index in 0..state.validators.len() {
+        let validator = &state.validators[index];
+        if validator.slashed && validator.withdrawable_epoch == withdrawable_epoch {
+            // Factored out from penalty numerator to avoid uint64 overflow
+            let penalty_numerator =
+                validator.effective_balance / increment * adjusted_total_slashing_balance;
+            let penalty = penalty_numerator / total_balance * increment;
+            state.decrease_balance(index as ValidatorIndex, penalty)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn process_final_updates<C: Config>(
+    state: &mut BeaconState<C>,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    let current_epoch = state.get_current_epoch();
+    let next_epoch = current_epoch + 1;
+
+    // Reset eth1 data votes
+    if (state.slot + 1) % C::SlotsPerEth1VotingPeriod::to_u64() == 0 {
+        state.eth1_data_votes = Default::default();
+    }
+
+    // Update effective balances with hysteresis
+    let increment = spec.effective_balance_increment;
+    let half_increment = increment / 2;
+    for (validator, balance) in state.validators.iter_mut().zip(state.balances.iter()) {
+        if *balance < validator.effective_balance
+            || validator.effective_balance + 3 * half_increment < *balance
+        {
+            validator.effective_balance =
+                (balance - balance % increment).min(spec.max_effective_balance);
+        }
+    }
+
+    // Reset slashings
+    let slashings_index = (next_epoch % C::EpochsPerSlashingsVector::to_u64()) as usize;
+    if slashings_index >= state.slashings.len() {
+        return Err(Error::IndexOutOfRange);
+    }
+    state.slashings[slashings_index] = 0;
+
+    // Set randao mix
+    let randao_mix = state.get_randao_mix(current_epoch)?;
+    let mix_index = (next_epoch % C::EpochsPerHistoricalVector::to_u64()) as usize;
+    if mix_index >= state.randao_mixes.len() {
+        return Err(Error::IndexOutOfRange);
+    }
+    state.randao_mixes[mix_index] = randao_mix;
+
+    // Set historical root accumulator
+    let epochs_per_historical_root =
+        C::SlotsPerHistoricalRoot::to_u64() / C::SlotsPerEpoch::to_u64();
+    if next_epoch % epochs_per_historical_root == 0 {
+        let historical_batch = HistoricalBatch::<C> {
+            block_roots: state.block_roots.clone(),
+            state_roots: state.state_roots.clone(),
+        };
+        state
+            .historical_roots
+            .push(H256::from_slice(&historical_batch.tree_hash_root()))
+            .map_err(|_| Error::ListFull)?;
+    }
+
+    // Rotate current/previous epoch attestations
+    state.previous_epoch_attestations = std::mem::take(&mut state.current_epoch_attestations);
+    Ok(())
+}
+
+fn get_base_reward<C: Config>(
+    state: &BeaconState<C>,
+    index: ValidatorIndex,
+    total_balance: Gwei,
+    spec: &ChainSpec,
+) -> Gwei {
+    state.validators[index as usize].effective_balance * spec.base_reward_factor
+        / integer_squareroot(total_balance)
+        / BASE_REWARDS_PER_EPOCH
+}
+
+fn get_matching_source_attestations<C: Config>(
+    state: &BeaconState<C>,
+    epoch: Epoch,
+) -> Result<Vec<PendingAttestation<C>>, Error> {
+    if epoch == state.get_current_epoch() {
+        Ok(state.current_epoch_attestations.to_vec())
+    } else if epoch == state.get_previous_epoch() {
+        Ok(state.previous_epoch_attestations.to_vec())
+    } else {
+        Err(Error::BeaconState(beacon_state::Error::EpochOutOfRange))
+    }
+}
+
+fn get_matching_target_attestations<C: Config>(
+    state: &BeaconState<C>,
+    epoch: Epoch,
+) -> Result<Vec<PendingAttestation<C>>, Error> {
+    let block_root = state.get_block_root(epoch)?;
+    Ok(get_matching_source_attestations(state, epoch)?
+        .into_iter()
+        .filter(|attestation| attestation.data.target.root == block_root)
+        .collect())
+}
+
+fn get_matching_head_attestations<C: Config>(
+    state: &BeaconState<C>,
+    epoch: Epoch,
+) -> Result<Vec<PendingAttestation<C>>, Error> {
+    let mut matching = Vec::new();
+    for attestation in get_matching_target_attestations(state, epoch)? {
+        if attestation.data.beacon_block_root
+            == state.get_block_root_at_slot(attestation.data.slot)?
+        {
+            matching.push(attestation);
+        }
+    }
+    Ok(matching)
+}
+
+fn get_unslashed_attesting_indices<C: Config>(
+    state: &BeaconState<C>,
+    attestations: &[PendingAttestation<C>],
+    spec: &ChainSpec,
+) -> Result<BTreeSet<ValidatorIndex>, Error> {
+    let mut indices = BTreeSet::new();
+    for attestation in attestations {
+        indices.extend(state.get_attesting_indices(
+            &attestation.data,
+            &attestation.aggregation_bits,
+            spec,
+        )?);
+    }
+    Ok(indices
+        .into_iter()
+        .filter(|index| !state.validators[*index as usize].slashed)
+        .collect())
+}
+
+fn get_attesting_balance<C: Config>(
+    state: &BeaconState<C>,
+    attestations: &[PendingAttestation<C>],
+    spec: &ChainSpec,
+) -> Result<Gwei, Error> {
+    let indices: Vec<ValidatorIndex> = get_unslashed_attesting_indices(state, attestations, spec)?
+        .into_iter()
+        .collect();
+    Ok(state.get_total_balance(&indices, spec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssz_types::{BitList, FixedVector, VariableList};
+
+    const BALANCE: Gwei = 32_000_000_000;
+
+    fn spec() -> ChainSpec {
+        ChainSpec::from_config::<MinimalConfig>()
+    }
+
+    fn active_validator() -> Validator {
+        Validator {
+            effective_balance: BALANCE,
+            exit_epoch: consts::FAR_FUTURE_EPOCH,
+            withdrawable_epoch: consts::FAR_FUTURE_EPOCH,
+            ..Validator::default()
+        }
+    }
+
+    /// State at `slot` with `validator_count` validators active since genesis and distinct block
+    /// roots and randao mixes.
+    fn state(slot: Slot, validator_count: usize) -> BeaconState<MinimalConfig> {
+        BeaconState {
+            slot,
+            validators: VariableList::from(vec![active_validator(); validator_count]),
+            balances: VariableList::from(vec![BALANCE; validator_count]),
+            block_roots: FixedVector::from(
+                (0..64)
+                    .map(|slot| H256::from_low_u64_le(1000 + slot))
+                    .collect::<Vec<_>>(),
+            ),
+            randao_mixes: FixedVector::from((0..64).map(H256::from_low_u64_le).collect::<Vec<_>>()),
+            state_roots: FixedVector::from(vec![]),
+            slashings: FixedVector::from(vec![]),
+            ..BeaconState::default()
+        }
+    }
+
+    /// Attestations of every committee of `epoch` for the epoch boundary block and the block of
+    /// their slot, signed by the members `participates` accepts.
+    fn attestations(
+        state: &BeaconState<MinimalConfig>,
+        epoch: Epoch,
+        participates: impl Fn(ValidatorIndex) -> bool,
+    ) -> Vec<PendingAttestation<MinimalConfig>> {
+        let mut attestations = Vec::new();
+        let start_slot = compute_start_slot_at_epoch::<MinimalConfig>(epoch);
+        for slot in start_slot..start_slot + 8 {
+            for index in 0..state.get_committee_count_at_slot(slot, &spec()) {
+                let committee = state.get_beacon_committee(slot, index, &spec()).unwrap();
+                let mut aggregation_bits = BitList::with_capacity(committee.len()).unwrap();
+                for (position, member) in committee.iter().enumerate() {
+                    aggregation_bits
+                        .set(position, participates(*member))
+                        .unwrap();
+                }
+                attestations.push(PendingAttestation {
+                    aggregation_bits,
+                    data: AttestationData {
+                        slot,
+                        index,
+                        beacon_block_root: state.get_block_root_at_slot(slot).unwrap_or_default(),
+                        source: Checkpoint::default(),
+                        target: Checkpoint {
+                            epoch,
+                            root: state.get_block_root(epoch).unwrap(),
+                        },
+                    },
+                    inclusion_delay: 1,
+                    proposer_index: 0,
+                });
+            }
+        }
+        attestations
+    }
+
+    fn base_reward(state: &BeaconState<MinimalConfig>) -> Gwei {
+        BALANCE * spec().base_reward_factor
+            / integer_squareroot(state.get_total_active_balance(&spec()))
+            / BASE_REWARDS_PER_EPOCH
+    }
+
+    #[test]
+    fn should_justify_and_finalize_with_supermajority() {
+        let mut state = state(8 * 4 + 7, 64);
+        let epoch_3 = Checkpoint {
+            epoch: 3,
+            root: state.get_block_root(3).unwrap(),
+        };
+        state.current_justified_checkpoint = epoch_3.clone();
+        state.justification_bits.set(0, true).unwrap();
+        state.previous_epoch_attestations = attestations(&state, 3, |_| true).into();
+        state.current_epoch_attestations = attestations(&state, 4, |_| true).into();
+
+        process_justification_and_finalization(&mut state, &spec()).unwrap();
+
+        assert_eq!(state.previous_justified_checkpoint, epoch_3);
+        assert_eq!(state.current_justified_checkpoint.epoch, 4);
+        assert_eq!(
+            state.current_justified_checkpoint.root,
+            state.get_block_root(4).unwrap()
+        );
+        assert_eq!(state.finalized_checkpoint, epoch_3);
+        let bits: Vec<bool> = (0..4)
+            .map(|i| state.justification_bits.get(i).unwrap())
+            .collect();
+        assert_eq!(bits, vec![true, true, false, false]);
+    }
+
+    #[test]
+    fn should_finalize_from_previous_justified_checkpoint() {
+        let mut state = state(8 * 4 + 7, 64);
+        let epoch_2 = Checkpoint {
+            epoch: 2,
+            root: state.get_block_root(2).unwrap(),
+        };
+        // Epoch 2 was justified, epoch 3 is justified now with it as source.
+        state.previous_justified_checkpoint = epoch_2.clone();
+        state.current_justified_checkpoint = epoch_2.clone();
+        state.justification_bits.set(1, true).unwrap();
+        state.previous_epoch_attestations = attestations(&state, 3, |_| true).into();
+
+        process_justification_and_finalization(&mut state, &spec()).unwrap();
+
+        assert_eq!(state.current_justified_checkpoint.epoch, 3);
+        assert_eq!(state.finalized_checkpoint, epoch_2);
+    }
+
+    #[test]
+    fn should_not_justify_without_supermajority() {
+        let mut state = state(8 * 4 + 7, 64);
+        let justified = state.current_justified_checkpoint.clone();
+        state.justification_bits.set(0, true).unwrap();
+        state.previous_epoch_attestations = attestations(&state, 3, |index| index % 3 != 0).into();
+
+        process_justification_and_finalization(&mut state, &spec()).unwrap();
+
+        assert_eq!(state.current_justified_checkpoint, justified);
+        assert_eq!(state.finalized_checkpoint, Checkpoint::default());
+        assert!(!state.justification_bits.get(0).unwrap());
+        assert!(state.justification_bits.get(1).unwrap());
+    }
+
+    #[test]
+    fn should_skip_justification_in_first_epochs() {
+        let mut state = state(8 + 7, 64);
+        state.current_epoch_attestations = attestations(&state, 1, |_| true).into();
+
+        process_justification_and_finalization(&mut state, &spec()).unwrap();
+
+        assert_eq!(state.current_justified_checkpoint, Checkpoint::default());
+        assert!(!state.justification_bits.get(0).unwrap());
+    }
+
+    #[test]
+    fn should_reward_attesters_and_penalize_absent_validators() {
+        let mut state = state(8 * 2 + 7, 64);
+        let absent = 63;
+        state.previous_epoch_attestations = attestations(&state, 1, |index| index != absent).into();
+        let base_reward = base_reward(&state);
+
+        let (rewards, penalties) = get_attestation_deltas(&state, &spec()).unwrap();
+
+        assert_eq!(rewards[absent as usize], 0);
+        assert_eq!(penalties[absent as usize], 3 * base_reward);
+        let attesting_increments = 63 * BALANCE / 1_000_000_000;
+        let total_increments = 64 * BALANCE / 1_000_000_000;
+        let proposer_reward = base_reward / spec().proposer_reward_quotient;
+        let attester_reward = 3 * (base_reward * attesting_increments / total_increments)
+            + base_reward
+            - proposer_reward;
+        assert_eq!(penalties[1], 0);
+        assert_eq!(rewards[1], attester_reward);
+        // Validator 0 proposed every block including an attestation.
+        assert_eq!(rewards[0], attester_reward + 63 * proposer_reward);
+
+        process_rewards_and_penalties(&mut state, &spec()).unwrap();
+        assert_eq!(state.balances[absent as usize], BALANCE - 3 * base_reward);
+        assert_eq!(state.balances[1], BALANCE + attester_reward);
+    }
+
+    #[test]
+    fn should_leak_balances_of_inactive_validators_without_finality() {
+        let state = state(8 * 7 + 7, 64);
+        let base_reward = base_reward(&state);
+        let finality_delay = 6;
+
+        let (rewards, penalties) = get_attestation_deltas(&state, &spec()).unwrap();
+
+        assert_eq!(rewards[0], 0);
+        assert_eq!(
+            penalties[0],
+            3 * base_reward
+                + BASE_REWARDS_PER_EPOCH * base_reward
+                + BALANCE * finality_delay / spec().inactivity_penalty_quotient
+        );
+    }
+
+    #[test]
+    fn should_not_leak_within_inactivity_delay() {
+        let state = state(8 * 5 + 7, 64);
+        let (_, penalties) = get_attestation_deltas(&state, &spec()).unwrap();
+        assert_eq!(penalties[0], 3 * base_reward(&state));
+    }
+
+    #[test]
+    fn should_skip_rewards_in_genesis_epoch() {
+        let mut state = state(7, 64);
+        process_rewards_and_penalties(&mut state, &spec()).unwrap();
+        assert!(state.balances.iter().all(|balance| *balance == BALANCE));
+    }
+
+    #[test]
+    fn should_activate_queued_validators_up_to_churn_limit() {
+        let mut state = state(7, 8);
+        let queued = Validator {
+            activation_eligibility_epoch: 0,
+            activation_epoch: consts::FAR_FUTURE_EPOCH,
+            ..active_validator()
+        };
+        for _ in 0..6 {
+            state.validators.push(queued.clone()).unwrap();
+        }
+        // Queued behind the validators above despite the lower index.
+        state.validators[0] = queued.clone();
+        state.validators[0].activation_eligibility_epoch = 1;
+        state.finalized_checkpoint.epoch = 1;
+        let new_deposit = Validator {
+            activation_eligibility_epoch: consts::FAR_FUTURE_EPOCH,
+            ..queued.clone()
+        };
+        state.validators.push(new_deposit.clone()).unwrap();
+        state
+            .validators
+            .push(Validator {
+                effective_balance: BALANCE - 1_000_000_000,
+                ..new_deposit
+            })
+            .unwrap();
+        state.validators[1].effective_balance = spec().ejection_balance;
+
+        process_registry_updates(&mut state, &spec()).unwrap();
+
+        let activation_epoch = compute_activation_exit_epoch(0, &spec());
+        let activated: Vec<usize> = (0..state.validators.len())
+            .filter(|index| state.validators[*index].activation_epoch == activation_epoch)
+            .collect();
+        assert_eq!(activated, vec![8, 9, 10, 11]);
+        assert_eq!(state.validators[14].activation_eligibility_epoch, 1);
+        assert_eq!(
+            state.validators[15].activation_eligibility_epoch,
+            consts::FAR_FUTURE_EPOCH
+        );
+        assert_eq!(state.validators[1].exit_epoch, activation_epoch);
+        assert_eq!(state.validators[2].exit_epoch, consts::FAR_FUTURE_EPOCH);
+    }
+
+    #[test]
+    fn should_activate_validators_up_to_churn_limit_of_spec() {
+        let mut state = state(7, 8);
+        for _ in 0..8 {
+            state
+                .validators
+                .push(Validator {
+                    activation_eligibility_epoch: 0,
+                    activation_epoch: consts::FAR_FUTURE_EPOCH,
+                    ..active_validator()
+                })
+                .unwrap();
+        }
+        let activated = |state: &BeaconState<MinimalConfig>| {
+            state
+                .validators
+                .iter()
+                .filter(|validator| validator.activation_epoch != consts::FAR_FUTURE_EPOCH)
+                .count()
+        };
+
+        let mut preset_state = state.clone();
+        process_registry_updates(&mut preset_state, &spec()).unwrap();
+        // The 8 active validators are below the churn limit quotient of the preset.
+        assert_eq!(activated(&preset_state), 8 + 4);
+
+        let fast_churn_spec = ChainSpec {
+            churn_limit_quotient: 1,
+            ..spec()
+        };
+        process_registry_updates(&mut state, &fast_churn_spec).unwrap();
+        assert_eq!(activated(&state), 8 + 8);
+    }
+
+    #[test]
+    fn should_penalize_slashed_validators_halfway_to_withdrawal() {
+        let mut state = state(8 * 3 + 7, 10);
+        state.slashings[0] = BALANCE;
+        state.validators[0].slashed = true;
+        state.validators[0].withdrawable_epoch = 3 + 32;
+        state.validators[1].slashed = true;
+        state.validators[1].withdrawable_epoch = 3 + 33;
+
+        process_slashings(&mut state, &spec()).unwrap();
+
+        // 32 increments of the tripled slashings out of the total active balance of 320 ETH.
+        let penalty = 32 * (3 * BALANCE) / (10 * BALANCE) * 1_000_000_000;
+        assert_eq!(state.balances[0], BALANCE - penalty);
+        assert_eq!(state.balances[1], BALANCE);
+        assert_eq!(state.balances[2], BALANCE);
+    }
+
+    #[test]
+    fn should_update_effective_balances_with_hysteresis() {
+        let mut state = state(7, 5);
+        state.validators[1].effective_balance = 31_000_000_000;
+        state.validators[2].effective_balance = 31_000_000_000;
+        state.balances[0] = 31_600_000_000;
+        state.balances[1] = 32_600_000_000;
+        state.balances[2] = 32_400_000_000;
+        state.balances[3] = 40_000_000_000;
+        state.balances[4] = 32_900_000_000;
+
+        process_final_updates(&mut state, &spec()).unwrap();
+
+        let effective_balances: Vec<Gwei> = state
+            .validators
+            .iter()
+            .map(|validator| validator.effective_balance)
+            .collect();
+        assert_eq!(
+            effective_balances,
+            vec![
+                31_000_000_000,
+                32_000_000_000,
+                31_000_000_000,
+                32_000_000_000,
+                32_000_000_000
+            ]
+        );
+    }
+
+    #[test]
+    fn should_reset_and_rotate_per_epoch_data() {
+        let mut state = state(8 * 7 + 7, 16);
+        state.slashings[8] = BALANCE;
+        state.slashings[7] = BALANCE;
+        state.eth1_data_votes.push(Eth1Data::default()).unwrap();
+        let current_epoch_attestations = attestations(&state, 6, |_| true);
+        state.current_epoch_attestations = current_epoch_attestations.clone().into();
+        let historical_batch = HistoricalBatch::<MinimalConfig> {
+            block_roots: state.block_roots.clone(),
+            state_roots: state.state_roots.clone(),
+        };
+
+        process_final_updates(&mut state, &spec()).unwrap();
+
+        assert_eq!(state.slashings[8], 0);
+        assert_eq!(state.slashings[7], BALANCE);
+        assert_eq!(state.randao_mixes[8], state.randao_mixes[7]);
+        assert!(state.eth1_data_votes.is_empty());
+        assert_eq!(
+            state.previous_epoch_attestations.to_vec(),
+            current_epoch_attestations
+        );
+        assert!(state.current_epoch_attestations.is_empty());
+        assert_eq!(
+            state.historical_roots.to_vec(),
+            vec![H256::from_slice(&historical_batch.tree_hash_root())]
+        );
+    }
+
+    #[test]
+    fn should_keep_data_within_voting_and_historical_periods() {
+        let mut state = state(8 * 2 + 7, 16);
+        state.eth1_data_votes.push(Eth1Data::default()).unwrap();
+
+        process_final_updates(&mut state, &spec()).unwrap();
+
+        assert_eq!(state.eth1_data_votes.len(), 1);
+        assert!(state.historical_roots.is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+use ssz_types::FixedVector;
+use tree_hash::{SignedRoot, TreeHash};
+use typenum::Unsigned;
+
+use crate::beacon_state::{self, BeaconState};
+use crate::{chain_spec::ChainSpec, config::*, primitives::*};
+
+pub mod block_processing;
+pub mod epoch_processing;
+
+pub use block_processing::{process_block, BlockSignatureStrategy};
+pub use epoch_processing::process_epoch;
+
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    SlotOutOfRange,
+    IndexOutOfRange,
+    ListFull,
+    InvalidSignature,
+    InvalidBlockHeader,
+    ProposerSlashed,
+    InvalidDepositCount,
+    InvalidDepositProof,
+    InvalidProposerSlashing,
+    InvalidAttesterSlashing,
+    InvalidAttestation,
+    InvalidVoluntaryExit,
+    BeaconState(beacon_state::Error),
+}
+
+impl From<beacon_state::Error> for Error {
+    fn from(error: beacon_state::Error) -> Error {
+        Error::BeaconState(error)
+    }
+}
+
+/// Advances `state` to `slot`, running epoch processing at every epoch boundary on the way.
+pub fn process_slots<C: Config>(
+    state: &mut BeaconState<C>,
+    slot: Slot,
+    spec: &ChainSpec,
+) -> Result<(), Error> {
+    if state.slot >= slot {
+        return Err(Error::SlotOutOfRange);
+    }
+    while state.slot < slot {
+        process_slot(state)?;
+        if (state.slot + 1) % C::SlotsPerEpoch::to_u64() == 0 {
+            process_epoch(state, spec)?;
+        }
+        state.slot += 1;
+    }
+    Ok(())
+}
+
+/// Caches the roots of the state and of the latest block before leaving the current slot.
+pub fn process_slot<C: Config>(state: &mut BeaconState<C>) -> Result<(), Error> {
+    let previous_state_root = H256::from_slice(&state.tree_hash_root());
+    set_historical_root(&mut state.state_roots, state.slot, previous_state_root)?;
+
+    if state.latest_block_header.state_root == H256::zero() {
+        state.latest_block_header.state_root = previous_state_root;
+    }
+
+    let previous_block_root = H256::from_slice(&state.latest_block_header.signed_root());
+    set_historical_root(&mut state.block_roots, state.slot, previous_block_root)
+}
+
+fn set_historical_root<N: Unsigned>(
+    roots: &mut FixedVector<H256, N>,
+    slot: Slot,
+    root: H256,
+) -> Result<(), Error> {
+    let index = (slot % N::to_u64()) as usize;
+    if index >= roots.len() {
+        return Err(Error::IndexOutOfRange);
+    }
+    roots[index] = root;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::genesis::interop_genesis_state;
+
+    #[test]
+    fn should_cache_roots_of_processed_slots() {
+        let spec = ChainSpec::from_config::<MinimalConfig>();
+        let mut state: BeaconState<MinimalConfig> = interop_genesis_state(16, 0).unwrap();
+        let genesis_state_root = H256::from_slice(&state.tree_hash_root());
+
+        process_slots(&mut state, 3, &spec).unwrap();
+
+        assert_eq!(state.slot, 3);
+        assert_eq!(state.state_roots[0], genesis_state_root);
+        assert_eq!(state.latest_block_header.state_root, genesis_state_root);
+        let genesis_block_root = H256::from_slice(&state.latest_block_header.signed_root());
+        // Empty slots repeat the root of the latest block.
+        assert_eq!(state.block_roots[0..3], [genesis_block_root; 3]);
+        assert_ne!(state.state_roots[1], state.state_roots[0]);
+        assert_eq!(state.state_roots[3], H256::zero());
+    }
+
+    #[test]
+    fn should_process_epoch_at_epoch_boundaries() {
+        let spec = ChainSpec::from_config::<MinimalConfig>();
+        let mut state: BeaconState<MinimalConfig> = interop_genesis_state(16, 0).unwrap();
+        state.randao_mixes[0] = H256::from_low_u64_le(1);
+
+        process_slots(&mut state, 7, &spec).unwrap();
+        assert_ne!(state.randao_mixes[1], state.randao_mixes[0]);
+
+        process_slots(&mut state, 8, &spec).unwrap();
+        assert_eq!(state.get_current_epoch(), 1);
+        assert_eq!(state.randao_mixes[1], state.randao_mixes[0]);
+    }
+
+    #[test]
+    fn should_refuse_to_rewind() {
+        let spec = ChainSpec::from_config::<MinimalConfig>();
+        let mut state: BeaconState<MinimalConfig> = interop_genesis_state(16, 0).unwrap();
+        process_slots(&mut state, 2, &spec).unwrap();
+
+        assert_eq!(
+            process_slots(&mut state, 2, &spec),
+            Err(Error::SlotOutOfRange)
+        );
+        assert_eq!(
+            process_slots(&mut state, 1, &spec),
+            Err(Error::SlotOutOfRange)
+        );
+        assert_eq!(state.slot, 2);
+    }
+}
@@ -110,6 +110,7 @@ pub struct BeaconBlockHeader {
     pub parent_root: H256,
     pub state_root: H256,
     pub body_root: H256,
+    #[signed_root(skip_hashing)]
     pub signature: Signature,
 }
 
@@ -289,7 +290,7 @@ pub struct VoluntaryExit {
 }
 
 #[derive(Clone, PartialEq, Debug, Deserialize, Serialize, Encode, Decode, TreeHash, SignedRoot)]
-pub struct SignedBeaconBlock {
-    pub message: BeaconBlock<MinimalConfig>,
+pub struct SignedBeaconBlock<C: Config> {
+    pub message: BeaconBlock<C>,
     pub signature: Signature,
 }